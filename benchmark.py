@@ -61,6 +61,9 @@ def main(feature_key="test"):
             if isinstance(feature, list):
                 feature = ",".join(feature)
 
+            # Every run has its own file with the metadata of the run
+            stats_file = Path("results") / feature_key / feature / f"{level}.json"
+
             subprocess.run(
                 [
                     "cargo",
@@ -70,15 +73,16 @@ def main(feature_key="test"):
                     "--features",
                     feature,
                     "--",
+                    "--output",
+                    str(stats_file),
                     "--level",
                     level,
                     "bench",
                 ],
                 check=True,
             )
-            stats_file = Path(f"statistics.json")
             with stats_file.open() as f:
-                stats = json.load(f)
+                stats = json.load(f)["timings"]
                 for key in statistics:
                     statistics[key][level] = stats[key]
                     data = np.array(stats[key])
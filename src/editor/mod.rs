@@ -2,7 +2,6 @@
 
 use std::f32::consts::TAU;
 use std::fmt::Write;
-use std::fs::File;
 
 use bevy::ecs::query::QueryFilter;
 use bevy::ecs::system::SystemState;
@@ -14,7 +13,7 @@ use bevy::prelude::*;
 use itertools::Itertools;
 
 use crate::level::*;
-use crate::utils::Vertices;
+use crate::utils::{make_circle, Vertices};
 use crate::visualization::draw_gizmo_cross;
 
 pub struct EditorPlugin;
@@ -68,6 +67,7 @@ impl Plugin for EditorPlugin {
                         .run_if(in_state(EditingState::CreatingBigCircleWall)),
                     create_preview_entity,
                     duplicate_selection,
+                    wall_to_enemy_spawn,
                 )
                     .chain()
                     .in_set(EditorUpdateSet::Create),
@@ -125,9 +125,11 @@ pub enum EditorAction {
     CreateSquareWall,
     CreateCircleWall,
     CreateBigCircleWall,
+    CreateEnemySpawnCircle,
     CreateEnemySpawn,
     CreatePlayerSpawn,
     AddWallVertex,
+    WallToEnemySpawn,
     MoveSelection,
     RotateSelection,
     ScaleSelection,
@@ -148,6 +150,7 @@ enum EditingState {
     AddingWallVertex,
     CreatingPlayerSpawn,
     CreatingEnemySpawn,
+    CreatingEnemySpawnCircle,
     CreatingSquareWall,
     CreatingCircleWall,
     CreatingBigCircleWall,
@@ -222,9 +225,11 @@ static EDITOR_KEYS: Lazy<EnumMap<EditorAction, (EditorKeyBind, &'static str)>> =
         CreateSquareWall => (KeyCode::Digit1.into(), "Create a square wall"),
         CreateCircleWall => (KeyCode::Digit2.into(), "Create a circle wall"),
         CreateBigCircleWall => (KeyCode::Digit3.into(), "Create a big circle wall"),
+        CreateEnemySpawnCircle => (KeyCode::Digit8.into(), "Create a circle enemy spawn area"),
         CreateEnemySpawn => (KeyCode::Digit9.into(), "Create an enemy spawn"),
         CreatePlayerSpawn => (KeyCode::Digit0.into(), "Create a player spawn"),
         AddWallVertex => (KeyCode::KeyW.into(), "Add a wall vertex"),
+        WallToEnemySpawn => (KeyCode::KeyP.into(), "Turn the selected wall into an enemy spawn area"),
         MoveSelection => (KeyCode::KeyG.into(), "Move selection"),
         RotateSelection => (KeyCode::KeyR.into(), "Rotate selection"),
         ScaleSelection => (KeyCode::KeyS.into(), "Scale selection"),
@@ -412,6 +417,7 @@ fn set_editing_state(
 		(EditorAction::CreateCircleWall, EditingState::CreatingCircleWall),
 		(EditorAction::CreateBigCircleWall, EditingState::CreatingBigCircleWall),
 		(EditorAction::CreateEnemySpawn, EditingState::CreatingEnemySpawn),
+		(EditorAction::CreateEnemySpawnCircle, EditingState::CreatingEnemySpawnCircle),
 		(EditorAction::CreatePlayerSpawn, EditingState::CreatingPlayerSpawn),
 		(EditorAction::AddWallVertex, EditingState::AddingWallVertex),
 		(EditorAction::MoveSelection, EditingState::Moving),
@@ -420,6 +426,7 @@ fn set_editing_state(
 		(EditorAction::DuplicateSelection, EditingState::None),
 		(EditorAction::DeleteSelection, EditingState::None),
 		(EditorAction::SelectLinked, EditingState::None),
+		(EditorAction::WallToEnemySpawn, EditingState::None),
 		(EditorAction::Save, EditingState::None),
 	];
 
@@ -444,7 +451,15 @@ fn create_preview_entity(
             preview_entity.0 = Some(commands.spawn(TargetBundle::new(**mouse_pos)).id());
         }
         EditingState::CreatingEnemySpawn => {
-            preview_entity.0 = Some(commands.spawn(SpawnPointBundle::new(**mouse_pos)).id());
+            let region = SpawnRegion::Point(**mouse_pos);
+            preview_entity.0 = Some(commands.spawn(SpawnPointBundle::new(region)).id());
+        }
+        EditingState::CreatingEnemySpawnCircle => {
+            let region = SpawnRegion::Circle {
+                center: **mouse_pos,
+                radius: CIRCLE_SIZE,
+            };
+            preview_entity.0 = Some(commands.spawn(SpawnPointBundle::new(region)).id());
         }
         _ => {}
     }
@@ -577,15 +592,6 @@ const CIRCLE_SIZE: f32 = 10.0;
 const CIRCLE_SEGMENTS_BIG: usize = 80;
 const CIRCLE_SIZE_BIG: f32 = 30.0;
 
-fn make_circle(center: Vec2, radius: f32, segments: usize) -> Vec<Vec2> {
-    let mut vertices = Vec::with_capacity(segments);
-    for i in 0..segments {
-        let angle = -(i as f32 / segments as f32) * std::f32::consts::TAU;
-        vertices.push(center + Vec2::new(angle.cos(), angle.sin()) * radius);
-    }
-    vertices
-}
-
 fn create_circle_wall_preview(
     mouse_pos: Res<MousePos>,
     mut wall_vertices: ResMut<WallVertices>,
@@ -617,6 +623,7 @@ fn rotate_selection(
     mut wall_vertices: ResMut<WallVertices>,
     mut selected: ResMut<Selected>,
     mut transforms: Query<&mut Transform>,
+    mut spawn_point_q: Query<&mut SpawnPoint>,
     mut next_editing_state: ResMut<NextState<EditingState>>,
     editing_state: Res<State<EditingState>>,
     mut start_rot: Local<Option<Vec2>>,
@@ -687,6 +694,10 @@ fn rotate_selection(
 
         let v = transform.translation.truncate() - center;
         transform.translation = (center + vec_angle.rotate(v)).extend(0.0);
+
+        if let Ok(mut spawn_point) = spawn_point_q.get_mut(*e) {
+            spawn_point.0.rotate(vec_angle);
+        }
     }
 
     for [w_i, v_i] in selected.wall_indices.iter() {
@@ -765,6 +776,7 @@ fn scale_selection(
     mut wall_vertices: ResMut<WallVertices>,
     mut selected: ResMut<Selected>,
     mut transforms: Query<&mut Transform>,
+    mut spawn_point_q: Query<&mut SpawnPoint>,
     mut next_editing_state: ResMut<NextState<EditingState>>,
     editing_state: Res<State<EditingState>>,
     editor_mouse_buttons: Res<EditorMouseButtons>,
@@ -821,6 +833,10 @@ fn scale_selection(
         let v = pos - center;
         transform.translation = (center + v * scale).extend(0.0);
         // transform.scale *= scale; // Dunno if should scale
+
+        if let Ok(mut spawn_point) = spawn_point_q.get_mut(*e) {
+            spawn_point.0.scale(scale);
+        }
     }
 
     for [w_i, v_i] in selected.wall_indices.iter() {
@@ -877,7 +893,7 @@ fn duplicate_selection(
     mut commands: Commands,
     editor_inputs: Res<EditorInputs>,
     mut selected: ResMut<Selected>,
-    transform_q: Query<(Entity, &Transform, Has<SpawnPoint>, Has<Target>)>,
+    transform_q: Query<(Entity, &Transform, Option<&SpawnPoint>, Has<Target>)>,
     mut wall_vertices: ResMut<WallVertices>,
     mouse_pos: Res<MousePos>,
 ) {
@@ -909,13 +925,13 @@ fn duplicate_selection(
         wall_indices: HashSet::new(),
     };
 
-    for (_, transform, is_spawn, is_target) in
+    for (_, transform, spawn_point, is_target) in
         transform_q.iter().filter(|q| sel.entities.contains(&q.0))
     {
         let mut t = *transform;
         t.translation += diff.extend(0.);
-        let mut e = if is_spawn {
-            commands.spawn(SpawnPointBundle::new(Vec2::ZERO))
+        let mut e = if let Some(spawn_point) = spawn_point {
+            commands.spawn(SpawnPointBundle::new(spawn_point.0.clone()))
         } else if is_target {
             commands.spawn(TargetBundle::new(Vec2::ZERO))
         } else {
@@ -944,6 +960,44 @@ fn duplicate_selection(
     selected.0 = Some(new_sel);
 }
 
+fn wall_to_enemy_spawn(
+    mut commands: Commands,
+    editor_inputs: Res<EditorInputs>,
+    mut selected: ResMut<Selected>,
+    mut wall_vertices: ResMut<WallVertices>,
+) {
+    if !editor_inputs[EditorAction::WallToEnemySpawn].just_pressed {
+        return;
+    }
+
+    let Some(sel) = &selected.0 else {
+        return;
+    };
+    let Ok(w_i) = sel
+        .wall_indices
+        .iter()
+        .map(|[w_i, _]| *w_i)
+        .all_equal_value()
+    else {
+        warn!("Select vertices of exactly one wall to turn it into a spawn area");
+        return;
+    };
+    if wall_vertices.0[w_i].len() < 3 {
+        warn!("Spawn areas need at least 3 vertices");
+        return;
+    }
+
+    let vertices = wall_vertices.0.remove(w_i);
+    let entity = commands
+        .spawn(SpawnPointBundle::new(SpawnRegion::Polygon { vertices }))
+        .id();
+
+    selected.0 = Some(SelectedInner {
+        wall_indices: HashSet::new(),
+        entities: [entity].into_iter().collect(),
+    });
+}
+
 fn delete_selection(
     mut commands: Commands,
     editor_inputs: Res<EditorInputs>,
@@ -1227,18 +1281,25 @@ pub fn save_level(world: &mut World, name: &str) -> anyhow::Result<()> {
     let size = world.resource::<LevelSize>().0;
     let walls = world.resource::<WallVertices>().0.clone();
     let targets = get_positions::<With<Target>>(world);
-    let spawn_points = get_positions::<With<SpawnPoint>>(world);
+    let spawn_points = world
+        .query::<(&SpawnPoint, &Transform)>()
+        .iter(world)
+        .map(|(spawn_point, transform)| spawn_point.region(transform))
+        .collect();
+    // Not editable in the editor, keep the one of the loaded level
+    let spawn_schedule = world
+        .get_resource::<Level>()
+        .and_then(|level| level.spawn_schedule.clone());
 
     let level = Level {
         size,
         spawn_points,
         targets,
         walls,
+        spawn_schedule,
     };
 
-    let mut file = File::create(name)?;
-    rmp_serde::encode::write_named(&mut file, &level)?;
-    Ok(())
+    level.save(name)
 }
 
 fn save(world: &mut World) {
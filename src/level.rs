@@ -1,8 +1,17 @@
+use std::fs::File;
+
 use bevy::prelude::*;
 use bevy_rapier2d::geometry::Collider;
 use serde::{Deserialize, Serialize};
 
-use crate::utils::{rectangle, spatial, square, Vertices, WithOffset};
+use crate::{
+    simulation::spawning::SpawnSchedule,
+    utils::{is_point_in_polygon, rectangle, spatial, square, Vertices, WithOffset},
+};
+
+pub mod generation;
+pub mod tiled;
+pub mod validation;
 
 pub struct LevelPlugin;
 
@@ -26,9 +35,12 @@ pub enum LevelStartupSet {
 #[derive(Resource, Serialize, Deserialize, Clone)]
 pub struct Level {
     pub size: f32,
-    pub spawn_points: Vec<Vec2>,
+    pub spawn_points: Vec<SpawnRegion>,
     pub targets: Vec<Vec2>,
     pub walls: Vec<Vertices>,
+    /// Used when no schedule is given on the command line.
+    #[serde(default)]
+    pub spawn_schedule: Option<SpawnSchedule>,
 }
 
 impl Default for Level {
@@ -36,9 +48,9 @@ impl Default for Level {
         Level {
             size: 100.,
             spawn_points: vec![
-                Vec2::new(10., 10.),
-                Vec2::new(10., 20.),
-                Vec2::new(20., 10.),
+                SpawnRegion::Point(Vec2::new(10., 10.)),
+                SpawnRegion::Point(Vec2::new(10., 20.)),
+                SpawnRegion::Point(Vec2::new(20., 10.)),
             ],
             targets: vec![
                 Vec2::new(90., 90.),
@@ -46,6 +58,7 @@ impl Default for Level {
                 Vec2::new(90., 80.),
             ],
             walls: vec![square(50.).with_offset(Vec2::new(50., 50.))],
+            spawn_schedule: None,
         }
     }
 }
@@ -54,16 +67,127 @@ impl Level {
     pub fn scale_to(&mut self, size: f32) {
         let scale = size / self.size;
         self.size = size;
-        self.spawn_points.iter_mut().for_each(|p| *p *= scale);
+        self.spawn_points.iter_mut().for_each(|r| r.scale(scale));
         self.targets.iter_mut().for_each(|p| *p *= scale);
         self.walls
             .iter_mut()
             .for_each(|v| v.iter_mut().for_each(|p| *p *= scale));
     }
+
+    pub fn load(path: &str) -> anyhow::Result<Self> {
+        let file = File::open(path)?;
+        Ok(rmp_serde::from_read(file)?)
+    }
+
+    pub fn save(&self, path: &str) -> anyhow::Result<()> {
+        let mut file = File::create(path)?;
+        rmp_serde::encode::write_named(&mut file, self)?;
+        Ok(())
+    }
 }
 
-#[derive(Component, Debug)]
-pub struct SpawnPoint;
+/// Agents are packed around point spawns up to this distance.
+pub const POINT_SPAWN_RADIUS: f32 = 10.;
+
+/// Area that agents are spawned in.
+/// Untagged so that levels saved when spawn points were plain positions still load.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(untagged)]
+pub enum SpawnRegion {
+    /// Agents start at the point and fill the space around it.
+    Point(Vec2),
+    Circle {
+        center: Vec2,
+        radius: f32,
+    },
+    Polygon {
+        vertices: Vertices,
+    },
+}
+
+impl SpawnRegion {
+    pub fn center(&self) -> Vec2 {
+        match self {
+            Self::Point(pos) => *pos,
+            Self::Circle { center, .. } => *center,
+            Self::Polygon { vertices } => {
+                vertices.iter().sum::<Vec2>() / vertices.len().max(1) as f32
+            }
+        }
+    }
+
+    pub fn bounds(&self) -> Rect {
+        match self {
+            Self::Point(pos) => Rect::from_center_half_size(*pos, Vec2::splat(POINT_SPAWN_RADIUS)),
+            Self::Circle { center, radius } => {
+                Rect::from_center_half_size(*center, Vec2::splat(*radius))
+            }
+            Self::Polygon { vertices } => vertices.iter().fold(
+                Rect {
+                    min: Vec2::INFINITY,
+                    max: Vec2::NEG_INFINITY,
+                },
+                |rect, v| rect.union_point(*v),
+            ),
+        }
+    }
+
+    pub fn contains(&self, pos: Vec2) -> bool {
+        match self {
+            Self::Point(center) => center.distance(pos) <= POINT_SPAWN_RADIUS,
+            Self::Circle { center, radius } => center.distance(pos) <= *radius,
+            Self::Polygon { vertices } => is_point_in_polygon(pos, vertices),
+        }
+    }
+
+    /// Scales the region around the origin.
+    pub fn scale(&mut self, scale: f32) {
+        match self {
+            Self::Point(pos) => *pos *= scale,
+            Self::Circle { center, radius } => {
+                *center *= scale;
+                *radius *= scale;
+            }
+            Self::Polygon { vertices } => vertices.iter_mut().for_each(|v| *v *= scale),
+        }
+    }
+
+    /// Rotates the region around the origin.
+    pub fn rotate(&mut self, rotation: Vec2) {
+        match self {
+            Self::Point(pos) => *pos = rotation.rotate(*pos),
+            Self::Circle { center, .. } => *center = rotation.rotate(*center),
+            Self::Polygon { vertices } => {
+                vertices.iter_mut().for_each(|v| *v = rotation.rotate(*v))
+            }
+        }
+    }
+}
+
+impl WithOffset for SpawnRegion {
+    fn with_offset(self, offset: Vec2) -> Self {
+        match self {
+            Self::Point(pos) => Self::Point(pos + offset),
+            Self::Circle { center, radius } => Self::Circle {
+                center: center + offset,
+                radius,
+            },
+            Self::Polygon { vertices } => Self::Polygon {
+                vertices: vertices.with_offset(offset),
+            },
+        }
+    }
+}
+
+/// Spawn region relative to the transform of the entity, so it can be moved around in the editor.
+#[derive(Component, Debug, Clone)]
+pub struct SpawnPoint(pub SpawnRegion);
+
+impl SpawnPoint {
+    pub fn region(&self, transform: &Transform) -> SpawnRegion {
+        self.0.clone().with_offset(transform.translation.truncate())
+    }
+}
 
 #[derive(Component, Debug)]
 pub struct Target;
@@ -78,10 +202,11 @@ pub struct SpawnPointBundle {
 }
 
 impl SpawnPointBundle {
-    pub fn new(pos: Vec2) -> Self {
+    pub fn new(region: SpawnRegion) -> Self {
+        let center = region.center();
         Self {
-            spawn_point: SpawnPoint,
-            spatial: spatial(pos, 2.),
+            spawn_point: SpawnPoint(region.with_offset(-center)),
+            spatial: spatial(center, 2.),
         }
     }
 }
@@ -133,7 +258,7 @@ fn spawn_level(world: &mut World) {
     let level = (*world.get_resource::<Level>().unwrap()).clone();
 
     for spawn_point in &level.spawn_points {
-        world.spawn(SpawnPointBundle::new(*spawn_point));
+        world.spawn(SpawnPointBundle::new(spawn_point.clone()));
     }
     for target in &level.targets {
         world.spawn(TargetBundle::new(*target));
@@ -150,3 +275,30 @@ fn spawn_level(world: &mut World) {
 
     world.insert_resource(LevelSize(level.size));
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn spawn_regions_round_trip() {
+        let regions = vec![
+            SpawnRegion::Point(Vec2::new(1., 2.)),
+            SpawnRegion::Circle {
+                center: Vec2::new(3., 4.),
+                radius: 5.,
+            },
+            SpawnRegion::Polygon {
+                vertices: square(2.),
+            },
+        ];
+        let bytes = rmp_serde::to_vec_named(&regions).unwrap();
+        let loaded: Vec<SpawnRegion> = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(loaded, regions);
+
+        // Spawn points used to be plain positions
+        let bytes = rmp_serde::to_vec_named(&vec![Vec2::new(1., 2.)]).unwrap();
+        let loaded: Vec<SpawnRegion> = rmp_serde::from_slice(&bytes).unwrap();
+        assert_eq!(loaded, vec![SpawnRegion::Point(Vec2::new(1., 2.))]);
+    }
+}
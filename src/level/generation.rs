@@ -0,0 +1,300 @@
+use std::f32::consts::TAU;
+
+use anyhow::bail;
+use bevy::prelude::*;
+use clap::ValueEnum;
+use rand::prelude::*;
+use rand_xoshiro::Xoshiro256PlusPlus;
+
+use crate::utils::{make_circle, rectangle, Vertices};
+
+use super::{Level, SpawnRegion};
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum Algorithm {
+    /// Randomly placed rectangles and circles.
+    Shapes,
+    /// Maze made with recursive division, like "2-Labyrinth".
+    Maze,
+    /// Grid of randomly shaped pillars, like "3-Cathedral".
+    Pillars,
+}
+
+const SPAWN_POINT_COUNT: usize = 4;
+const TARGET_COUNT: usize = 4;
+
+/// Minimum distance between shapes and from shapes to the level border.
+/// Keeps every part of the level reachable.
+const SHAPE_GAP: f32 = 4.;
+/// Minimum distance from spawn points and targets to walls and each other.
+const POINT_CLEARANCE: f32 = 2.;
+const CIRCLE_SEGMENTS: usize = 32;
+
+/// Generates a level of the given size.
+/// The meaning of `count` depends on the algorithm: number of shapes, maze cells per side
+/// or pillars per side. A reasonable value for the level size is used if it is not given.
+pub fn generate(
+    algorithm: Algorithm,
+    size: f32,
+    seed: u64,
+    count: Option<usize>,
+) -> anyhow::Result<Level> {
+    if size < 20. {
+        bail!("Level size must be at least 20, got {size}");
+    }
+
+    let mut rng = Xoshiro256PlusPlus::seed_from_u64(seed);
+
+    match algorithm {
+        Algorithm::Shapes => shapes(&mut rng, size, count),
+        Algorithm::Maze => Ok(maze(&mut rng, size, count)),
+        Algorithm::Pillars => pillars(&mut rng, size, count),
+    }
+}
+
+/// Returns a circle, square or rectangle that fits inside the circle at `center` with `radius`.
+fn random_shape(rng: &mut impl Rng, center: Vec2, radius: f32) -> Vertices {
+    let shape = match rng.gen_range(0..3) {
+        0 => return make_circle(center, radius, CIRCLE_SEGMENTS),
+        1 => Vec2::ONE,
+        _ => Vec2::new(1., rng.gen_range(0.2..0.8)),
+    };
+    let rotation = Vec2::from_angle(rng.gen_range(0. ..TAU));
+    rectangle(shape.normalize() * radius * 2.)
+        .into_iter()
+        .map(|v| center + rotation.rotate(v))
+        .collect()
+}
+
+fn shapes(rng: &mut impl Rng, size: f32, count: Option<usize>) -> anyhow::Result<Level> {
+    let count = count.unwrap_or((size * size / 400.) as usize);
+    let max_radius = size * 0.08;
+
+    // Bounding circles of the placed shapes
+    let mut bounds: Vec<(Vec2, f32)> = Vec::with_capacity(count);
+    let mut walls = Vec::with_capacity(count);
+
+    for _ in 0..count * 50 {
+        if walls.len() == count {
+            break;
+        }
+        let radius = rng.gen_range(max_radius * 0.25..max_radius);
+        let range = radius + SHAPE_GAP..size - radius - SHAPE_GAP;
+        let center = Vec2::new(rng.gen_range(range.clone()), rng.gen_range(range));
+        if bounds
+            .iter()
+            .any(|(c, r)| c.distance(center) < r + radius + SHAPE_GAP)
+        {
+            continue;
+        }
+        bounds.push((center, radius));
+        walls.push(random_shape(rng, center, radius));
+    }
+
+    if walls.len() < count {
+        println!("Could only fit {} of {count} shapes", walls.len());
+    }
+
+    let (spawn_points, targets) = random_points(rng, size, &bounds)?;
+    Ok(Level {
+        size,
+        spawn_points: spawn_points.into_iter().map(SpawnRegion::Point).collect(),
+        targets,
+        walls,
+        spawn_schedule: None,
+    })
+}
+
+fn pillars(rng: &mut impl Rng, size: f32, count: Option<usize>) -> anyhow::Result<Level> {
+    let count = count.unwrap_or((size / 12.).round() as usize).max(2);
+    let spacing = size / count as f32;
+
+    let mut bounds = Vec::with_capacity(count * count);
+    let mut walls = Vec::with_capacity(count * count);
+
+    for x in 0..count {
+        for y in 0..count {
+            let jitter = Vec2::new(rng.gen_range(-0.1..0.1), rng.gen_range(-0.1..0.1));
+            let center = (Vec2::new(x as f32, y as f32) + 0.5 + jitter) * spacing;
+            let radius = rng.gen_range(0.1..0.25) * spacing;
+            bounds.push((center, radius));
+            walls.push(random_shape(rng, center, radius));
+        }
+    }
+
+    let (spawn_points, targets) = random_points(rng, size, &bounds)?;
+    Ok(Level {
+        size,
+        spawn_points: spawn_points.into_iter().map(SpawnRegion::Point).collect(),
+        targets,
+        walls,
+        spawn_schedule: None,
+    })
+}
+
+/// Picks spawn points and targets that are clear of the bounding circles and each other.
+fn random_points(
+    rng: &mut impl Rng,
+    size: f32,
+    bounds: &[(Vec2, f32)],
+) -> anyhow::Result<(Vec<Vec2>, Vec<Vec2>)> {
+    let mut points: Vec<Vec2> = Vec::with_capacity(SPAWN_POINT_COUNT + TARGET_COUNT);
+
+    for _ in 0..(SPAWN_POINT_COUNT + TARGET_COUNT) * 1000 {
+        if points.len() == SPAWN_POINT_COUNT + TARGET_COUNT {
+            break;
+        }
+        let range = POINT_CLEARANCE..size - POINT_CLEARANCE;
+        let point = Vec2::new(rng.gen_range(range.clone()), rng.gen_range(range));
+        let clear = bounds
+            .iter()
+            .all(|(c, r)| c.distance(point) >= r + POINT_CLEARANCE)
+            && points.iter().all(|p| p.distance(point) >= POINT_CLEARANCE);
+        if clear {
+            points.push(point);
+        }
+    }
+
+    if points.len() < SPAWN_POINT_COUNT + TARGET_COUNT {
+        bail!("Level is too crowded to place spawn points and targets");
+    }
+
+    let targets = points.split_off(SPAWN_POINT_COUNT);
+    Ok((points, targets))
+}
+
+fn maze(rng: &mut impl Rng, size: f32, count: Option<usize>) -> Level {
+    let cells = count.unwrap_or((size / 8.).round() as usize).max(2);
+    let maze = Maze {
+        cell_size: size / cells as f32,
+        thickness: size / cells as f32 * 0.25,
+        size,
+    };
+
+    let mut walls = Vec::new();
+    maze.divide(rng, [0, 0], [cells, cells], &mut walls);
+
+    // Spawn in the middle and try to get out through the corners
+    let cell_center = |x: usize, y: usize| (Vec2::new(x as f32, y as f32) + 0.5) * maze.cell_size;
+    let middle = cell_center(cells / 2, cells / 2);
+    let offset = maze.cell_size * 0.2;
+    let spawn_points = vec![
+        middle + Vec2::new(-offset, -offset),
+        middle + Vec2::new(offset, -offset),
+        middle + Vec2::new(offset, offset),
+        middle + Vec2::new(-offset, offset),
+    ];
+    let last = cells - 1;
+    let targets = vec![
+        cell_center(0, 0),
+        cell_center(last, 0),
+        cell_center(last, last),
+        cell_center(0, last),
+    ];
+
+    Level {
+        size,
+        spawn_points: spawn_points.into_iter().map(SpawnRegion::Point).collect(),
+        targets,
+        walls,
+        spawn_schedule: None,
+    }
+}
+
+struct Maze {
+    cell_size: f32,
+    thickness: f32,
+    size: f32,
+}
+
+impl Maze {
+    /// Splits the chamber at `pos` with `dims` (in cells) with a wall that has one gap
+    /// and recurses into both halves.
+    fn divide(
+        &self,
+        rng: &mut impl Rng,
+        pos: [usize; 2],
+        dims: [usize; 2],
+        walls: &mut Vec<Vertices>,
+    ) {
+        let [x, y] = pos;
+        let [w, h] = dims;
+        if w < 2 || h < 2 {
+            return;
+        }
+
+        let horizontal = match w.cmp(&h) {
+            std::cmp::Ordering::Less => true,
+            std::cmp::Ordering::Greater => false,
+            std::cmp::Ordering::Equal => rng.gen(),
+        };
+
+        if horizontal {
+            let wall_y = rng.gen_range(y + 1..y + h);
+            let gap = rng.gen_range(x..x + w);
+            self.add_wall([x, wall_y], [gap, wall_y], walls);
+            self.add_wall([gap + 1, wall_y], [x + w, wall_y], walls);
+            self.divide(rng, [x, y], [w, wall_y - y], walls);
+            self.divide(rng, [x, wall_y], [w, y + h - wall_y], walls);
+        } else {
+            let wall_x = rng.gen_range(x + 1..x + w);
+            let gap = rng.gen_range(y..y + h);
+            self.add_wall([wall_x, y], [wall_x, gap], walls);
+            self.add_wall([wall_x, gap + 1], [wall_x, y + h], walls);
+            self.divide(rng, [x, y], [wall_x - x, h], walls);
+            self.divide(rng, [wall_x, y], [x + w - wall_x, h], walls);
+        }
+    }
+
+    /// Adds a wall along the grid line from `start` to `end` (in cells).
+    fn add_wall(&self, start: [usize; 2], end: [usize; 2], walls: &mut Vec<Vertices>) {
+        if start == end {
+            return;
+        }
+        let to_world = |p: [usize; 2]| Vec2::new(p[0] as f32, p[1] as f32) * self.cell_size;
+        let half = self.thickness / 2.;
+        let min = (to_world(start) - half).max(Vec2::ZERO);
+        let max = (to_world(end) + half).min(Vec2::splat(self.size));
+        walls.push(vec![
+            min,
+            Vec2::new(max.x, min.y),
+            max,
+            Vec2::new(min.x, max.y),
+        ]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::level::validation;
+
+    use super::*;
+
+    #[test]
+    fn same_seed_gives_the_same_level() {
+        for &algorithm in Algorithm::value_variants() {
+            let a = generate(algorithm, 60., 7, None).unwrap();
+            let b = generate(algorithm, 60., 7, None).unwrap();
+            assert_eq!(a.walls, b.walls, "{algorithm:?}");
+            assert_eq!(a.spawn_points, b.spawn_points, "{algorithm:?}");
+            assert_eq!(a.targets, b.targets, "{algorithm:?}");
+
+            let other = generate(algorithm, 60., 8, None).unwrap();
+            assert_ne!(a.walls, other.walls, "{algorithm:?}");
+        }
+    }
+
+    #[test]
+    fn generated_levels_are_valid() {
+        for &algorithm in Algorithm::value_variants() {
+            for (size, seed) in [(20., 0), (60., 1), (100., 2), (150., 3)] {
+                let level = generate(algorithm, size, seed, None).unwrap();
+                assert_eq!(
+                    validation::validate(&level),
+                    Vec::new(),
+                    "{algorithm:?} of size {size} with seed {seed}"
+                );
+            }
+        }
+    }
+}
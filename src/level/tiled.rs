@@ -0,0 +1,728 @@
+//! Importer for maps made with the [Tiled](https://www.mapeditor.org/) editor.
+//!
+//! Solid tiles of the collision layer are merged into wall outlines and objects become spawn
+//! points or targets based on their class (or type, name or layer name as fallbacks).
+//! Spawn objects with a size become rectangular spawn regions.
+
+use std::{collections::VecDeque, fs, path::Path};
+
+use anyhow::{bail, Context};
+use bevy::{prelude::*, utils::HashMap};
+use serde::Deserialize;
+
+use crate::utils::Vertices;
+
+use super::{Level, SpawnRegion};
+
+/// Loads a `.tmx` or `.json` map and converts it to a level.
+/// One tile is `tile_size` units wide in the resulting level.
+pub fn load_level(path: &Path, tile_size: f32, collision_layer: &str) -> anyhow::Result<Level> {
+    let contents = fs::read_to_string(path)
+        .with_context(|| format!("Failed to read Tiled map {}", path.display()))?;
+
+    let map = match path.extension().and_then(|e| e.to_str()) {
+        Some("tmx") => parse_tmx(&contents)?,
+        Some("json" | "tmj") => parse_json(&contents)?,
+        _ => bail!("Unknown Tiled map format, expected a .tmx or .json file"),
+    };
+
+    map.to_level(tile_size, collision_layer)
+}
+
+struct TiledMap {
+    width: usize,
+    height: usize,
+    tile_width: f32,
+    tile_height: f32,
+    layers: Vec<Layer>,
+}
+
+enum Layer {
+    Tiles { name: String, gids: Vec<u32> },
+    Objects { name: String, objects: Vec<Object> },
+}
+
+struct Object {
+    name: String,
+    class: String,
+    x: f32,
+    y: f32,
+    width: f32,
+    height: f32,
+    /// Tile objects are anchored at their bottom left corner instead of the top left.
+    is_tile: bool,
+}
+
+enum ObjectKind {
+    SpawnPoint,
+    Target,
+}
+
+impl Object {
+    fn kind(&self, layer_name: &str) -> Option<ObjectKind> {
+        [self.class.as_str(), self.name.as_str(), layer_name]
+            .into_iter()
+            .find(|s| !s.is_empty())
+            .and_then(|s| {
+                let s = s.to_lowercase();
+                if s.starts_with("spawn") {
+                    Some(ObjectKind::SpawnPoint)
+                } else if ["target", "exit", "goal"].iter().any(|t| s.starts_with(t)) {
+                    Some(ObjectKind::Target)
+                } else {
+                    None
+                }
+            })
+    }
+
+    /// Rectangles, unlike points and tiles.
+    fn has_area(&self) -> bool {
+        !self.is_tile && self.width > 0. && self.height > 0.
+    }
+
+    /// Center of the object in pixels, y pointing down.
+    fn center(&self) -> Vec2 {
+        let half = Vec2::new(self.width, self.height) / 2.;
+        if self.is_tile {
+            Vec2::new(self.x + half.x, self.y - half.y)
+        } else {
+            Vec2::new(self.x, self.y) + half
+        }
+    }
+}
+
+impl TiledMap {
+    fn to_level(&self, tile_size: f32, collision_layer: &str) -> anyhow::Result<Level> {
+        if self.width == 0 || self.height == 0 {
+            bail!("Map has no tiles");
+        }
+
+        let gids = self
+            .layers
+            .iter()
+            .find_map(|l| match l {
+                Layer::Tiles { name, gids } if name.eq_ignore_ascii_case(collision_layer) => {
+                    Some(gids)
+                }
+                _ => None,
+            })
+            .with_context(|| format!("No tile layer named '{collision_layer}' found"))?;
+
+        if gids.len() != self.width * self.height {
+            bail!(
+                "Layer '{collision_layer}' has {} tiles, expected {}",
+                gids.len(),
+                self.width * self.height
+            );
+        }
+
+        // Flip rows so that y points up like in the simulation
+        let mut solid = TileGrid::new(self.width, self.height);
+        for (i, gid) in gids.iter().enumerate() {
+            let (x, row) = (i % self.width, i / self.width);
+            solid.set([x as i32, (self.height - row - 1) as i32], *gid != 0);
+        }
+
+        let mut walls = merge_tiles(&solid)
+            .into_iter()
+            .map(|wall| wall.into_iter().map(|v| v * tile_size).collect())
+            .collect::<Vec<Vertices>>();
+
+        let map_size = Vec2::new(self.width as f32, self.height as f32) * tile_size;
+        let size = map_size.max_element();
+
+        // Levels are square, so block off the area that is not part of the map
+        if map_size.x < size {
+            walls.push(rect_vertices(Vec2::new(map_size.x, 0.), Vec2::splat(size)));
+        } else if map_size.y < size {
+            walls.push(rect_vertices(Vec2::new(0., map_size.y), Vec2::splat(size)));
+        }
+
+        let to_level_pos = |pixel: Vec2| {
+            Vec2::new(
+                pixel.x / self.tile_width,
+                self.height as f32 - pixel.y / self.tile_height,
+            ) * tile_size
+        };
+
+        let mut spawn_points = Vec::new();
+        let mut targets = Vec::new();
+        let mut ignored = 0;
+        for layer in &self.layers {
+            let Layer::Objects { name, objects } = layer else {
+                continue;
+            };
+            for object in objects {
+                let pos = to_level_pos(object.center());
+                match object.kind(name) {
+                    Some(ObjectKind::SpawnPoint) if object.has_area() => {
+                        let half = Vec2::new(
+                            object.width / self.tile_width,
+                            object.height / self.tile_height,
+                        ) * tile_size
+                            / 2.;
+                        spawn_points.push(SpawnRegion::Polygon {
+                            vertices: rect_vertices(pos - half, pos + half),
+                        });
+                    }
+                    Some(ObjectKind::SpawnPoint) => spawn_points.push(SpawnRegion::Point(pos)),
+                    Some(ObjectKind::Target) => targets.push(pos),
+                    None => ignored += 1,
+                }
+            }
+        }
+
+        if ignored > 0 {
+            println!("Ignored {ignored} objects that are not spawn points or targets");
+        }
+
+        Ok(Level {
+            size,
+            spawn_points,
+            targets,
+            walls,
+            spawn_schedule: None,
+        })
+    }
+}
+
+fn rect_vertices(min: Vec2, max: Vec2) -> Vertices {
+    vec![min, Vec2::new(max.x, min.y), max, Vec2::new(min.x, max.y)]
+}
+
+/// Solid cells of a map, with `[0, 0]` in the bottom left corner.
+#[derive(Clone)]
+struct TileGrid {
+    width: usize,
+    height: usize,
+    cells: Vec<bool>,
+}
+
+impl TileGrid {
+    fn new(width: usize, height: usize) -> Self {
+        Self {
+            width,
+            height,
+            cells: vec![false; width * height],
+        }
+    }
+
+    fn get(&self, [x, y]: [i32; 2]) -> bool {
+        if x < 0 || y < 0 || x as usize >= self.width || y as usize >= self.height {
+            return false;
+        }
+        self.cells[x as usize + y as usize * self.width]
+    }
+
+    fn set(&mut self, [x, y]: [i32; 2], value: bool) {
+        self.cells[x as usize + y as usize * self.width] = value;
+    }
+
+    fn solid_cells(&self) -> impl Iterator<Item = [i32; 2]> + '_ {
+        (0..self.height as i32)
+            .flat_map(move |y| (0..self.width as i32).map(move |x| [x, y]))
+            .filter(|c| self.get(*c))
+    }
+}
+
+const NEIGHBORS: [[i32; 2]; 4] = [[1, 0], [0, 1], [-1, 0], [0, -1]];
+
+/// Merges solid tiles into wall outlines, one per 4-connected group of tiles.
+/// Groups that enclose empty tiles are cut into pieces first, so every outline is a simple
+/// polygon without holes.
+fn merge_tiles(solid: &TileGrid) -> Vec<Vertices> {
+    let mut walls = Vec::new();
+    let mut queue = connected_groups(solid);
+
+    while let Some(group) = queue.pop_front() {
+        let loops = trace_outlines(&group);
+        // Outer outlines are counter-clockwise and holes clockwise
+        let Some(hole) = loops.iter().find(|l| signed_area(l) < 0.) else {
+            walls.extend(loops.iter().map(|l| {
+                l.iter()
+                    .map(|v| Vec2::new(v[0] as f32, v[1] as f32))
+                    .collect()
+            }));
+            continue;
+        };
+
+        // Cutting the group at the left edge of a hole opens the hole
+        let cut = hole.iter().map(|v| v[0]).min().unwrap();
+        let mut left = group.clone();
+        let mut right = group;
+        for cell in left.solid_cells().collect::<Vec<_>>() {
+            if cell[0] >= cut {
+                left.set(cell, false);
+            } else {
+                right.set(cell, false);
+            }
+        }
+        queue.extend(connected_groups(&left));
+        queue.extend(connected_groups(&right));
+    }
+
+    walls
+}
+
+fn connected_groups(solid: &TileGrid) -> VecDeque<TileGrid> {
+    let mut visited = TileGrid::new(solid.width, solid.height);
+    let mut groups = VecDeque::new();
+
+    for start in solid.solid_cells() {
+        if visited.get(start) {
+            continue;
+        }
+        let mut group = TileGrid::new(solid.width, solid.height);
+        let mut stack = vec![start];
+        visited.set(start, true);
+        while let Some(cell) = stack.pop() {
+            group.set(cell, true);
+            for [dx, dy] in NEIGHBORS {
+                let next = [cell[0] + dx, cell[1] + dy];
+                if solid.get(next) && !visited.get(next) {
+                    visited.set(next, true);
+                    stack.push(next);
+                }
+            }
+        }
+        groups.push_back(group);
+    }
+
+    groups
+}
+
+/// Follows the tile edges between solid and empty cells, keeping solid cells on the left.
+/// Returns closed loops with only the corner vertices.
+fn trace_outlines(group: &TileGrid) -> Vec<Vec<[i32; 2]>> {
+    let mut edges = Vec::new();
+    for [x, y] in group.solid_cells() {
+        let corners = [[x, y], [x + 1, y], [x + 1, y + 1], [x, y + 1]];
+        let outside = [[x, y - 1], [x + 1, y], [x, y + 1], [x - 1, y]];
+        for i in 0..4 {
+            if !group.get(outside[i]) {
+                edges.push((corners[i], corners[(i + 1) % 4]));
+            }
+        }
+    }
+
+    let mut starting_at: HashMap<[i32; 2], Vec<usize>> = HashMap::new();
+    for (i, (start, _)) in edges.iter().enumerate() {
+        starting_at.entry(*start).or_default().push(i);
+    }
+
+    let dir = |(start, end): ([i32; 2], [i32; 2])| [end[0] - start[0], end[1] - start[1]];
+
+    // Where outlines touch at a corner, turn left to stay on the same tile
+    let next_edge = |edge: usize| {
+        let incoming = dir(edges[edge]);
+        let candidates = &starting_at[&edges[edge].1];
+        *candidates
+            .iter()
+            .find(|&&e| {
+                let out = dir(edges[e]);
+                incoming[0] * out[1] - incoming[1] * out[0] > 0
+            })
+            .unwrap_or(&candidates[0])
+    };
+
+    let mut visited = vec![false; edges.len()];
+    let mut loops = Vec::new();
+    for first in 0..edges.len() {
+        if visited[first] {
+            continue;
+        }
+        let mut vertices = Vec::new();
+        let mut edge = first;
+        loop {
+            visited[edge] = true;
+            let next = next_edge(edge);
+            if dir(edges[edge]) != dir(edges[next]) {
+                vertices.push(edges[edge].1);
+            }
+            edge = next;
+            if edge == first {
+                break;
+            }
+        }
+        loops.push(vertices);
+    }
+
+    loops
+}
+
+fn signed_area(vertices: &[[i32; 2]]) -> f32 {
+    let mut sum = 0;
+    for i in 0..vertices.len() {
+        let [x1, y1] = vertices[i];
+        let [x2, y2] = vertices[(i + 1) % vertices.len()];
+        sum += x1 * y2 - x2 * y1;
+    }
+    sum as f32 / 2.
+}
+
+// Tiled stores flipping flags in the highest bits of the tile id
+const GID_MASK: u32 = 0x0FFF_FFFF;
+
+fn parse_tmx(contents: &str) -> anyhow::Result<TiledMap> {
+    let doc = roxmltree::Document::parse(contents).context("Invalid TMX file")?;
+    let root = doc.root_element();
+    if root.attribute("infinite") == Some("1") {
+        bail!("Infinite maps are not supported");
+    }
+    if root
+        .attribute("orientation")
+        .is_some_and(|o| o != "orthogonal")
+    {
+        bail!("Only orthogonal maps are supported");
+    }
+
+    let attr = |node: roxmltree::Node, name: &str| -> anyhow::Result<f32> {
+        node.attribute(name)
+            .with_context(|| format!("Missing attribute '{name}' in <{}>", node.tag_name().name()))?
+            .parse::<f32>()
+            .with_context(|| format!("Invalid attribute '{name}'"))
+    };
+    let attr_or_zero = |node: roxmltree::Node, name: &str| -> anyhow::Result<f32> {
+        if node.has_attribute(name) {
+            attr(node, name)
+        } else {
+            Ok(0.)
+        }
+    };
+
+    let mut layers = Vec::new();
+    let mut groups = vec![root];
+    while let Some(group) = groups.pop() {
+        for node in group.children().filter(|n| n.is_element()) {
+            let name = node.attribute("name").unwrap_or_default().to_owned();
+            match node.tag_name().name() {
+                "group" => groups.push(node),
+                "layer" => {
+                    let data = node
+                        .children()
+                        .find(|n| n.has_tag_name("data"))
+                        .context("Tile layer without data")?;
+                    let gids = match data.attribute("encoding") {
+                        Some("csv") => data
+                            .text()
+                            .unwrap_or_default()
+                            .split(',')
+                            .map(|s| s.trim().parse::<u32>())
+                            .collect::<Result<Vec<_>, _>>()
+                            .context("Invalid CSV tile data")?,
+                        None => data
+                            .children()
+                            .filter(|n| n.has_tag_name("tile"))
+                            .map(|n| n.attribute("gid").unwrap_or("0").parse::<u32>())
+                            .collect::<Result<Vec<_>, _>>()
+                            .context("Invalid tile data")?,
+                        Some(encoding) => {
+                            bail!("Tile layer encoding '{encoding}' is not supported, save the map with CSV encoding")
+                        }
+                    };
+                    let gids = gids.into_iter().map(|g| g & GID_MASK).collect();
+                    layers.push(Layer::Tiles { name, gids });
+                }
+                "objectgroup" => {
+                    let objects = node
+                        .children()
+                        .filter(|n| n.has_tag_name("object"))
+                        .map(|n| {
+                            Ok(Object {
+                                name: n.attribute("name").unwrap_or_default().to_owned(),
+                                class: n
+                                    .attribute("class")
+                                    .or(n.attribute("type"))
+                                    .unwrap_or_default()
+                                    .to_owned(),
+                                x: attr(n, "x")?,
+                                y: attr(n, "y")?,
+                                width: attr_or_zero(n, "width")?,
+                                height: attr_or_zero(n, "height")?,
+                                is_tile: n.has_attribute("gid"),
+                            })
+                        })
+                        .collect::<anyhow::Result<Vec<_>>>()?;
+                    layers.push(Layer::Objects { name, objects });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(TiledMap {
+        width: attr(root, "width")? as usize,
+        height: attr(root, "height")? as usize,
+        tile_width: attr(root, "tilewidth")?,
+        tile_height: attr(root, "tileheight")?,
+        layers,
+    })
+}
+
+#[derive(Deserialize)]
+struct JsonMap {
+    width: usize,
+    height: usize,
+    tilewidth: f32,
+    tileheight: f32,
+    #[serde(default)]
+    infinite: bool,
+    #[serde(default)]
+    orientation: Option<String>,
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonLayer {
+    #[serde(rename = "type")]
+    kind: String,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    encoding: Option<String>,
+    #[serde(default)]
+    data: Option<serde_json::Value>,
+    #[serde(default)]
+    objects: Vec<JsonObject>,
+    #[serde(default)]
+    layers: Vec<JsonLayer>,
+}
+
+#[derive(Deserialize)]
+struct JsonObject {
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    class: Option<String>,
+    #[serde(default, rename = "type")]
+    kind: Option<String>,
+    x: f32,
+    y: f32,
+    #[serde(default)]
+    width: f32,
+    #[serde(default)]
+    height: f32,
+    #[serde(default)]
+    gid: Option<u32>,
+}
+
+fn parse_json(contents: &str) -> anyhow::Result<TiledMap> {
+    let map: JsonMap = serde_json::from_str(contents).context("Invalid Tiled JSON file")?;
+    if map.infinite {
+        bail!("Infinite maps are not supported");
+    }
+    if map
+        .orientation
+        .as_deref()
+        .is_some_and(|o| o != "orthogonal")
+    {
+        bail!("Only orthogonal maps are supported");
+    }
+
+    let mut layers = Vec::new();
+    let mut groups = vec![map.layers];
+    while let Some(group) = groups.pop() {
+        for layer in group {
+            match layer.kind.as_str() {
+                "group" => groups.push(layer.layers),
+                "tilelayer" => {
+                    if let Some(encoding) = layer.encoding.filter(|e| e != "csv") {
+                        bail!("Tile layer encoding '{encoding}' is not supported, save the map with CSV encoding");
+                    }
+                    let gids = serde_json::from_value::<Vec<u32>>(layer.data.unwrap_or_default())
+                        .context("Invalid tile data")?
+                        .into_iter()
+                        .map(|g| g & GID_MASK)
+                        .collect();
+                    layers.push(Layer::Tiles {
+                        name: layer.name,
+                        gids,
+                    });
+                }
+                "objectgroup" => {
+                    let objects = layer
+                        .objects
+                        .into_iter()
+                        .map(|o| Object {
+                            name: o.name,
+                            class: o.class.or(o.kind).unwrap_or_default(),
+                            x: o.x,
+                            y: o.y,
+                            width: o.width,
+                            height: o.height,
+                            is_tile: o.gid.is_some(),
+                        })
+                        .collect();
+                    layers.push(Layer::Objects {
+                        name: layer.name,
+                        objects,
+                    });
+                }
+                _ => {}
+            }
+        }
+    }
+
+    Ok(TiledMap {
+        width: map.width,
+        height: map.height,
+        tile_width: map.tilewidth,
+        tile_height: map.tileheight,
+        layers,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Grid from rows of `#` for solid tiles, top row first like in Tiled.
+    fn grid(rows: &[&str]) -> TileGrid {
+        let mut grid = TileGrid::new(rows[0].len(), rows.len());
+        for (row, line) in rows.iter().enumerate() {
+            for (x, c) in line.chars().enumerate() {
+                grid.set([x as i32, (rows.len() - row - 1) as i32], c == '#');
+            }
+        }
+        grid
+    }
+
+    fn areas(walls: &[Vertices]) -> Vec<f32> {
+        walls
+            .iter()
+            .map(|wall| {
+                let corners = wall
+                    .iter()
+                    .map(|v| [v.x as i32, v.y as i32])
+                    .collect::<Vec<_>>();
+                signed_area(&corners)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn merges_an_l_shape_into_one_outline() {
+        let walls = merge_tiles(&grid(&["#.", "#.", "##"]));
+        assert_eq!(walls.len(), 1);
+        // Only the corners are kept
+        assert_eq!(walls[0].len(), 6);
+        assert_eq!(areas(&walls), vec![4.]);
+    }
+
+    #[test]
+    fn cuts_holes_open() {
+        let ring = grid(&["###", "#.#", "###"]);
+        let loops = trace_outlines(&ring);
+        let mut loop_areas = loops.iter().map(|l| signed_area(l)).collect::<Vec<_>>();
+        loop_areas.sort_by(f32::total_cmp);
+        assert_eq!(loop_areas, vec![-1., 9.]);
+
+        let walls = merge_tiles(&ring);
+        let areas = areas(&walls);
+        assert_eq!(walls.len(), 2);
+        assert!(areas.iter().all(|a| *a > 0.), "{areas:?}");
+        assert_eq!(areas.iter().sum::<f32>(), 8.);
+    }
+
+    #[test]
+    fn separates_tiles_touching_at_corners() {
+        let loops = trace_outlines(&grid(&["#.", ".#"]));
+        assert_eq!(loops.len(), 2);
+        assert!(loops.iter().all(|l| l.len() == 4 && signed_area(l) == 1.));
+
+        // A hole that is only open at a corner still gets cut
+        let walls = merge_tiles(&grid(&["###", "#.#", "##."]));
+        let areas = areas(&walls);
+        assert!(areas.iter().all(|a| *a > 0.), "{areas:?}");
+        assert_eq!(areas.iter().sum::<f32>(), 7.);
+    }
+
+    const TMX: &str = r#"<?xml version="1.0" encoding="UTF-8"?>
+<map version="1.10" orientation="orthogonal" width="3" height="2" tilewidth="32" tileheight="16">
+ <layer id="1" name="Collision" width="3" height="2">
+  <data encoding="csv">
+1,0,0,
+0,0,2147483649
+</data>
+ </layer>
+ <objectgroup id="2" name="Objects">
+  <object id="1" class="spawn" x="16" y="8"/>
+  <object id="2" type="Spawn" x="32" y="16" width="64" height="16"/>
+  <object id="3" name="exit" gid="1" x="32" y="32" width="32" height="16"/>
+  <object id="4" class="decoration" x="0" y="0"/>
+ </objectgroup>
+ <objectgroup id="3" name="Targets">
+  <object id="5" x="80" y="24"/>
+ </objectgroup>
+</map>
+"#;
+
+    const JSON: &str = r#"{
+ "width": 3, "height": 2, "tilewidth": 32, "tileheight": 16,
+ "orientation": "orthogonal", "infinite": false,
+ "layers": [
+  {"type": "tilelayer", "name": "Collision", "data": [1, 0, 0, 0, 0, 2147483649]},
+  {"type": "objectgroup", "name": "Objects", "objects": [
+   {"class": "spawn", "x": 16, "y": 8},
+   {"type": "Spawn", "x": 32, "y": 16, "width": 64, "height": 16},
+   {"name": "exit", "gid": 1, "x": 32, "y": 32, "width": 32, "height": 16},
+   {"class": "decoration", "x": 0, "y": 0}
+  ]},
+  {"type": "group", "layers": [
+   {"type": "objectgroup", "name": "Targets", "objects": [{"x": 80, "y": 24}]}
+  ]}
+ ]
+}"#;
+
+    /// Both fixtures describe the same map.
+    fn assert_fixture(map: TiledMap) {
+        let level = map.to_level(2., "collision").unwrap();
+        assert_eq!(level.size, 6.);
+
+        // Rows are flipped so the first row ends up at the top
+        let expected_walls: [Vertices; 3] = [
+            rect_vertices(Vec2::new(0., 2.), Vec2::new(2., 4.)),
+            rect_vertices(Vec2::new(4., 0.), Vec2::new(6., 2.)),
+            // Blocks off the rest of the square level
+            rect_vertices(Vec2::new(0., 4.), Vec2::new(6., 6.)),
+        ];
+        assert_eq!(level.walls.len(), expected_walls.len());
+        for expected in &expected_walls {
+            let mut expected = expected.clone();
+            expected.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+            assert!(
+                level.walls.iter().any(|wall| {
+                    let mut wall = wall.clone();
+                    wall.sort_by(|a, b| a.x.total_cmp(&b.x).then(a.y.total_cmp(&b.y)));
+                    wall == expected
+                }),
+                "{expected:?} not in {:?}",
+                level.walls
+            );
+        }
+
+        assert_eq!(
+            level.spawn_points,
+            vec![
+                SpawnRegion::Point(Vec2::new(1., 3.)),
+                SpawnRegion::Polygon {
+                    vertices: rect_vertices(Vec2::new(2., 0.), Vec2::new(6., 2.)),
+                },
+            ]
+        );
+        // The tile object is anchored at its bottom left corner
+        let mut targets = level.targets;
+        targets.sort_by(|a, b| a.x.total_cmp(&b.x));
+        assert_eq!(targets, vec![Vec2::new(3., 1.), Vec2::new(5., 1.)]);
+    }
+
+    #[test]
+    fn parses_tmx() {
+        assert_fixture(parse_tmx(TMX).unwrap());
+    }
+
+    #[test]
+    fn parses_json() {
+        assert_fixture(parse_json(JSON).unwrap());
+    }
+}
@@ -0,0 +1,396 @@
+use std::{f32::consts::PI, fmt};
+
+use bevy::prelude::*;
+
+use crate::{
+    simulation::{navigation::WALL_INFLATION, spawning::ENEMY_RADIUS},
+    utils::{
+        inflate_polygon, is_clockwise, is_point_in_polygon, polygon_area, segments_intersect,
+        InflateError, Vertices,
+    },
+};
+
+use super::{Level, SpawnRegion};
+
+/// Spawn points closer than this are practically in the same place.
+const MIN_SPAWN_POINT_DISTANCE: f32 = ENEMY_RADIUS * 2.;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum LevelProblem {
+    TooFewVertices {
+        wall: usize,
+        count: usize,
+    },
+    SelfIntersecting {
+        wall: usize,
+        edges: [usize; 2],
+    },
+    /// Walls can reach past the edges to close them off, but not lie completely outside.
+    WallOutsideLevel {
+        wall: usize,
+    },
+    InflationFailed {
+        wall: usize,
+        error: InflateError,
+    },
+    DegenerateInflation {
+        wall: usize,
+        area: f32,
+        inflated_area: f32,
+    },
+    OverlappingSpawnPoints {
+        spawn_points: [usize; 2],
+    },
+    SpawnPointInsideWall {
+        spawn_point: usize,
+        wall: usize,
+    },
+    SpawnRegionTooSmall {
+        spawn_point: usize,
+    },
+    TargetInsideWall {
+        target: usize,
+        wall: usize,
+    },
+    InvalidSpawnSchedule {
+        error: String,
+    },
+}
+
+impl fmt::Display for LevelProblem {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewVertices { wall, count } => {
+                write!(f, "wall {wall} has only {count} vertices")
+            }
+            Self::SelfIntersecting { wall, edges } => write!(
+                f,
+                "wall {wall} intersects itself (edges {} and {})",
+                edges[0], edges[1]
+            ),
+            Self::WallOutsideLevel { wall } => write!(f, "wall {wall} is outside the level"),
+            Self::InflationFailed { wall, error } => {
+                write!(f, "inflating wall {wall} failed: {error}")
+            }
+            Self::DegenerateInflation {
+                wall,
+                area,
+                inflated_area,
+            } => write!(
+                f,
+                "inflating wall {wall} shrunk it (area {area:.2} -> {inflated_area:.2})"
+            ),
+            Self::OverlappingSpawnPoints { spawn_points } => write!(
+                f,
+                "spawn points {} and {} overlap",
+                spawn_points[0], spawn_points[1]
+            ),
+            Self::SpawnPointInsideWall { spawn_point, wall } => {
+                write!(f, "spawn point {spawn_point} is inside wall {wall}")
+            }
+            Self::SpawnRegionTooSmall { spawn_point } => {
+                write!(f, "spawn region {spawn_point} can't fit an agent")
+            }
+            Self::TargetInsideWall { target, wall } => {
+                write!(f, "target {target} is inside wall {wall}")
+            }
+            Self::InvalidSpawnSchedule { error } => {
+                write!(f, "invalid spawn schedule: {error}")
+            }
+        }
+    }
+}
+
+/// Finds problems that break or degrade the simulation of a level.
+pub fn validate(level: &Level) -> Vec<LevelProblem> {
+    let mut problems = Vec::new();
+
+    for (i, wall) in level.walls.iter().enumerate() {
+        validate_wall(i, wall, level.size, &mut problems);
+    }
+
+    let points_inside_walls = |points: &[Vec2]| {
+        points
+            .iter()
+            .enumerate()
+            .filter_map(|(i, p)| {
+                let wall = level
+                    .walls
+                    .iter()
+                    .position(|w| is_point_in_polygon(*p, w))?;
+                Some((i, wall))
+            })
+            .collect::<Vec<_>>()
+    };
+
+    let spawn_centers = level
+        .spawn_points
+        .iter()
+        .map(SpawnRegion::center)
+        .collect::<Vec<_>>();
+    for (spawn_point, wall) in points_inside_walls(&spawn_centers) {
+        problems.push(LevelProblem::SpawnPointInsideWall { spawn_point, wall });
+    }
+    for (spawn_point, region) in level.spawn_points.iter().enumerate() {
+        let too_small = match region {
+            SpawnRegion::Point(_) => false,
+            SpawnRegion::Circle { radius, .. } => *radius < ENEMY_RADIUS,
+            SpawnRegion::Polygon { vertices } => {
+                vertices.len() < 3 || polygon_area(vertices) < ENEMY_RADIUS.powi(2) * PI
+            }
+        };
+        if too_small {
+            problems.push(LevelProblem::SpawnRegionTooSmall { spawn_point });
+        }
+    }
+    for (target, wall) in points_inside_walls(&level.targets) {
+        problems.push(LevelProblem::TargetInsideWall { target, wall });
+    }
+
+    for (i, a) in spawn_centers.iter().enumerate() {
+        for (j, b) in spawn_centers.iter().enumerate().skip(i + 1) {
+            if a.distance(*b) < MIN_SPAWN_POINT_DISTANCE {
+                problems.push(LevelProblem::OverlappingSpawnPoints {
+                    spawn_points: [i, j],
+                });
+            }
+        }
+    }
+
+    if let Some(schedule) = &level.spawn_schedule {
+        if let Err(error) = schedule.validate(level.spawn_points.len()) {
+            problems.push(LevelProblem::InvalidSpawnSchedule {
+                error: format!("{error:#}"),
+            });
+        }
+    }
+
+    problems
+}
+
+fn validate_wall(wall: usize, vertices: &Vertices, size: f32, problems: &mut Vec<LevelProblem>) {
+    if vertices.len() < 3 {
+        problems.push(LevelProblem::TooFewVertices {
+            wall,
+            count: vertices.len(),
+        });
+        return;
+    }
+
+    let min = vertices.iter().copied().fold(Vec2::MAX, Vec2::min);
+    let max = vertices.iter().copied().fold(Vec2::MIN, Vec2::max);
+    if max.x < 0. || max.y < 0. || min.x > size || min.y > size {
+        problems.push(LevelProblem::WallOutsideLevel { wall });
+    }
+
+    let n = vertices.len();
+    let edge = |i: usize| (vertices[i], vertices[(i + 1) % n]);
+    let mut self_intersecting = false;
+    'outer: for i in 0..n {
+        // Neighboring edges share a vertex, which doesn't count, but they can still fold back
+        for j in i + 1..n {
+            let (a1, a2) = edge(i);
+            let (b1, b2) = edge(j);
+            if segments_intersect(a1, a2, b1, b2) {
+                problems.push(LevelProblem::SelfIntersecting {
+                    wall,
+                    edges: [i, j],
+                });
+                self_intersecting = true;
+                break 'outer;
+            }
+        }
+    }
+
+    // Inflating self-intersecting walls gives garbage anyway, don't report it twice
+    if self_intersecting {
+        return;
+    }
+
+    match inflate_polygon(vertices, WALL_INFLATION) {
+        Err(error) => problems.push(LevelProblem::InflationFailed { wall, error }),
+        Ok(rings) => {
+            let area = polygon_area(vertices);
+            // Holes are clockwise
+            let inflated_area = rings
+                .iter()
+                .map(|r| {
+                    if is_clockwise(r) {
+                        -polygon_area(r)
+                    } else {
+                        polygon_area(r)
+                    }
+                })
+                .sum::<f32>();
+            if inflated_area <= area {
+                problems.push(LevelProblem::DegenerateInflation {
+                    wall,
+                    area,
+                    inflated_area,
+                });
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        simulation::spawning::{SpawnSchedule, SpawnWave},
+        utils::{square, WithOffset},
+    };
+
+    use super::*;
+
+    fn level(walls: Vec<Vertices>) -> Level {
+        Level {
+            size: 20.,
+            spawn_points: vec![SpawnRegion::Point(Vec2::new(2., 2.))],
+            targets: vec![Vec2::new(18., 18.)],
+            walls,
+            spawn_schedule: None,
+        }
+    }
+
+    fn square_at(min: Vec2, size: f32) -> Vertices {
+        square(size).with_offset(min + size / 2.)
+    }
+
+    #[test]
+    fn bundled_levels_are_valid() {
+        for entry in std::fs::read_dir("levels").unwrap() {
+            let path = entry.unwrap().path();
+            let level = Level::load(path.to_str().unwrap()).unwrap();
+            assert_eq!(validate(&level), Vec::new(), "{}", path.display());
+        }
+    }
+
+    #[test]
+    fn walls_can_reach_past_the_edges() {
+        let wall = square_at(Vec2::new(-2., 5.), 4.);
+        assert_eq!(validate(&level(vec![wall])), Vec::new());
+    }
+
+    #[test]
+    fn walls_touching_themselves_are_valid() {
+        // Two squares that share a corner, traced as one polygon
+        let wall = [
+            (0., 0.),
+            (1., 0.),
+            (1., 1.),
+            (2., 1.),
+            (2., 2.),
+            (1., 2.),
+            (1., 1.),
+            (0., 1.),
+        ]
+        .map(|(x, y)| Vec2::new(x, y) * 4. + 5.)
+        .to_vec();
+        assert_eq!(validate(&level(vec![wall])), Vec::new());
+    }
+
+    #[test]
+    fn finds_wall_problems() {
+        let problems = |wall: Vertices| validate(&level(vec![wall]));
+
+        assert_eq!(
+            problems(vec![Vec2::ZERO, Vec2::X]),
+            vec![LevelProblem::TooFewVertices { wall: 0, count: 2 }]
+        );
+        let bowtie = [(5., 5.), (10., 10.), (10., 5.), (5., 10.)]
+            .map(|(x, y)| Vec2::new(x, y))
+            .to_vec();
+        assert_eq!(
+            problems(bowtie),
+            vec![LevelProblem::SelfIntersecting {
+                wall: 0,
+                edges: [0, 2]
+            }]
+        );
+        // Neighboring edges on top of each other
+        let folded = [(5., 5.), (10., 5.), (10., 10.), (10., 7.)]
+            .map(|(x, y)| Vec2::new(x, y))
+            .to_vec();
+        assert!(matches!(
+            problems(folded)[..],
+            [LevelProblem::SelfIntersecting { wall: 0, .. }]
+        ));
+        assert_eq!(
+            problems(square_at(Vec2::new(22., 5.), 5.)),
+            vec![LevelProblem::WallOutsideLevel { wall: 0 }]
+        );
+        let tiny = [(5., 5.), (5.0004, 5.), (5., 5.0004)]
+            .map(|(x, y)| Vec2::new(x, y))
+            .to_vec();
+        assert_eq!(
+            problems(tiny),
+            vec![LevelProblem::InflationFailed {
+                wall: 0,
+                error: InflateError::Degenerate
+            }]
+        );
+        // A square wound around twice through a shared corner, the area counts the inside twice
+        let double = [
+            (0., 0.),
+            (10., 0.),
+            (10., 10.),
+            (0., 10.),
+            (0., 0.),
+            (9., 1.),
+            (9., 9.),
+            (1., 9.),
+        ]
+        .map(|(x, y)| Vec2::new(x, y) + 5.)
+        .to_vec();
+        assert!(matches!(
+            problems(double)[..],
+            [LevelProblem::DegenerateInflation { wall: 0, .. }]
+        ));
+    }
+
+    #[test]
+    fn finds_spawn_and_target_problems() {
+        let wall = square_at(Vec2::new(8., 8.), 4.);
+        let mut level = level(vec![wall]);
+        level.spawn_points = vec![
+            SpawnRegion::Point(Vec2::new(2., 2.)),
+            SpawnRegion::Point(Vec2::new(2.5, 2.)),
+            SpawnRegion::Point(Vec2::new(10., 10.)),
+            SpawnRegion::Circle {
+                center: Vec2::new(15., 2.),
+                radius: ENEMY_RADIUS / 2.,
+            },
+        ];
+        level.targets = vec![Vec2::new(18., 18.), Vec2::new(9., 9.)];
+        level.spawn_schedule = Some(SpawnSchedule {
+            waves: vec![SpawnWave {
+                start: 0,
+                count: 10,
+                rate: 0.,
+                spawn_point_weights: Vec::new(),
+                repeat: None,
+            }],
+        });
+
+        let problems = validate(&level);
+        assert_eq!(problems.len(), 5, "{problems:?}");
+        assert_eq!(
+            problems[..4],
+            [
+                LevelProblem::SpawnPointInsideWall {
+                    spawn_point: 2,
+                    wall: 0
+                },
+                LevelProblem::SpawnRegionTooSmall { spawn_point: 3 },
+                LevelProblem::TargetInsideWall { target: 1, wall: 0 },
+                LevelProblem::OverlappingSpawnPoints {
+                    spawn_points: [0, 1]
+                },
+            ]
+        );
+        assert!(matches!(
+            problems[4],
+            LevelProblem::InvalidSpawnSchedule { .. }
+        ));
+    }
+}
@@ -1,6 +1,12 @@
 #![allow(clippy::type_complexity, clippy::too_many_arguments)]
 
-use std::fs::File;
+use std::{
+    fs,
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, Context};
 
 use bevy::{
     app::AppExit, core::FrameCount, prelude::*, time::TimePlugin, window::WindowResolution,
@@ -9,12 +15,21 @@ use bevy_framepace::{FramepacePlugin, FramepaceSettings, Limiter};
 use editor::EditorPlugin;
 use level::{Level, LevelPath, LevelPlugin};
 use mouse_follow::MouseFollowPlugin;
-use statistics::StatisticsPlugin;
+use statistics::{PlotFormat, StatisticsPlugin, StatisticsSettings};
 use visualization::VisualizationPlugin;
 
-use crate::simulation::SimulationPlugin;
+use crate::simulation::{
+    arrival::{Arrival, GateSettings},
+    depenetration::DepenetrationSettings,
+    evacuation::EvacuationSettings,
+    player::PlayerControl,
+    spawning::{AgentParamsDistribution, SpawnSchedule},
+    target_motion::TargetMotions,
+    BoidsWeights, SimulationPlugin, SimulationTick,
+};
 
-use clap::{Parser, Subcommand};
+use clap::{Parser, Subcommand, ValueEnum};
+use itertools::Itertools;
 
 mod editor;
 pub mod level;
@@ -24,10 +39,11 @@ pub mod statistics;
 pub mod utils;
 pub mod visualization;
 
-/// How often the simulation is updated when visualizing.
+/// Default of how often the simulation is updated when visualizing.
 /// Benchmarks are ran as fast as possible.
 const FRAME_RATE: i32 = 60;
-const DELTA_TIME: f32 = 1.0 / FRAME_RATE as f32;
+/// Tick length that the steering is tuned for. Per tick factors are scaled by `dt / REFERENCE_DT`.
+const REFERENCE_DT: f32 = 1.0 / FRAME_RATE as f32;
 
 #[derive(Parser)]
 struct Cli {
@@ -44,11 +60,119 @@ struct Cli {
     #[clap(long, default_value = "false")]
     update_nav: bool,
 
+    /// Whether to make the flow field avoid crowded areas, so agents spread to alternative routes.
+    /// The flow field is updated every tick.
+    #[clap(long, default_value = "false")]
+    density_nav: bool,
+
+    /// Whether to push overlapping agents out of walls and each other after movement.
+    /// Flocking only keeps agents apart softly.
+    #[clap(long, default_value = "false")]
+    hard_collisions: bool,
+
+    /// How many depenetration passes to run per tick to push overlapping agents apart.
+    /// Depenetration is disabled if not given.
+    #[clap(long)]
+    depenetration: Option<u32>,
+
+    /// Overlap between two agents that depenetration is allowed to leave.
+    #[clap(long, default_value = "0.01")]
+    max_overlap: f32,
+
+    /// Strength of keeping agents apart. Requires the `flocking_boids` feature.
+    #[clap(long, default_value = "1")]
+    separation_weight: f32,
+
+    /// Strength of matching the velocity of neighbors. Requires the `flocking_boids` feature.
+    #[clap(long, default_value = "0.1")]
+    alignment_weight: f32,
+
+    /// Strength of moving towards neighbors. Requires the `flocking_boids` feature.
+    #[clap(long, default_value = "0.5")]
+    cohesion_weight: f32,
+
+    /// Maximum speed of spawned agents, either a value or a range "MIN..MAX" to pick from.
+    #[clap(long, value_parser = parse_range)]
+    max_speed: Option<RangeInclusive<f32>>,
+
+    /// How much spawned agents can change their velocity per second, a value or a range.
+    #[clap(long, value_parser = parse_range)]
+    acceleration: Option<RangeInclusive<f32>>,
+
+    /// Distance spawned agents try to keep to each other, a value or a range.
+    /// At most `MAX_PREFERRED_DISTANCE`, what the flocking neighbor search can see,
+    /// which depends on the enabled features. Larger values are rejected with the actual limit.
+    #[clap(long, value_parser = parse_range)]
+    preferred_spacing: Option<RangeInclusive<f32>>,
+
+    /// Agents closer than this to a target slow down.
+    #[clap(long, default_value = "0")]
+    arrival_radius: f32,
+
+    /// Whether agents stay at the target instead of being despawned, so crowds gather around it.
+    /// Can't be used with `--target-capacity`.
+    #[clap(long, default_value = "false")]
+    stay_at_target: bool,
+
+    /// Agents that can pass each target per second, so they have to wait at exits.
+    /// Queue lengths and wait times are written next to the output, e.g. to
+    /// statistics-queues.json.
+    #[clap(long)]
+    target_capacity: Option<f32>,
+
+    /// Agents closer than this to a target with a capacity line up behind each other.
+    #[clap(long, default_value = "0", requires = "target_capacity")]
+    queue_radius: f32,
+
+    /// JSON file with the spawn waves. Overrides the schedule of the level.
+    /// Without either, agents are spawned as fast as possible to keep 10000 in the level.
+    #[clap(long)]
+    spawn_schedule: Option<String>,
+
+    /// JSON file with paths or orbits for the targets of the level.
+    /// Requires updating the flow field with `--update-nav` or `--density-nav`.
+    #[clap(long)]
+    target_motion: Option<String>,
+
+    /// Add a player that agents chase, moved with the keyboard in the viewer or by a bot.
+    /// Requires updating the flow field with `--update-nav` or `--density-nav`.
+    #[clap(long, value_enum)]
+    player: Option<PlayerControl>,
+
+    /// Record how the crowd behaves next to the output, e.g. to statistics-quality.json:
+    /// overlaps, local densities, speed versus density and the time and path length of
+    /// every agent that reaches a target.
+    #[clap(long, default_value = "false")]
+    quality: bool,
+
+    /// Leave the timings and metrics of this many ticks at the start out of the statistics.
+    /// Has to be less than the ticks of `bench`. The quality, queue, overlap and evacuation
+    /// files still include these ticks.
+    #[clap(long, default_value = "0")]
+    warmup: u32,
+
+    /// Where to write the timings, per tick crowd metrics and metadata of the run.
+    #[clap(long, default_value = "statistics.json")]
+    output: PathBuf,
+
+    /// Draw the timings and their histograms to charts next to the output,
+    /// e.g. statistics-timings.png and statistics-histograms.png.
+    #[clap(long, value_enum)]
+    plot: Option<PlotFormat>,
+
+    /// Simulation ticks per second. The viewer also renders at this rate.
+    #[clap(long, default_value_t = FRAME_RATE as f32)]
+    tick_rate: f32,
+
+    /// How many movement steps to split each tick to.
+    #[clap(long, default_value = "1", value_parser = clap::value_parser!(u32).range(1..))]
+    substeps: u32,
+
     #[clap(subcommand)]
     command: Option<Command>,
 }
 
-#[derive(Subcommand, Eq, PartialEq, Resource, Clone, Copy)]
+#[derive(Subcommand, PartialEq, Resource, Clone)]
 enum Command {
     Viewer,
     Editor,
@@ -56,6 +180,46 @@ enum Command {
         #[clap(short, long, default_value = "500")]
         ticks: u32,
     },
+    /// Place agents all over the level and run until all of them have reached a target.
+    /// The evacuation time, agents per target and the cumulative exit curve
+    /// are written next to the output, e.g. to statistics-evacuation.json.
+    Evacuate {
+        #[clap(short, long, default_value = "1000")]
+        agents: u32,
+
+        /// Stop after this many simulated seconds even if agents remain.
+        #[clap(long, default_value = "600")]
+        max_time: f32,
+    },
+    /// Convert a Tiled map (.tmx or .json) to a level.
+    /// The level is saved with the name given with `--level` or the name of the map file.
+    Import {
+        /// Path to the Tiled map.
+        file: String,
+
+        /// Width of one tile in the level.
+        #[clap(long, default_value = "4")]
+        tile_size: f32,
+
+        /// Name of the tile layer that contains the walls.
+        #[clap(long, default_value = "collision")]
+        collision_layer: String,
+    },
+    /// Check levels for problems, like self-intersecting walls or targets inside walls.
+    /// Checks the level given with `--level` or all levels if it is not given.
+    Validate,
+    /// Generate a level procedurally. The size is set with `--level-size` (default 100).
+    /// The level is saved with the name given with `--level` or "<algorithm>-<seed>".
+    Generate {
+        algorithm: level::generation::Algorithm,
+
+        #[clap(long, default_value = "0")]
+        seed: u64,
+
+        /// Number of shapes, maze cells per side or pillars per side, depending on the algorithm.
+        #[clap(long)]
+        count: Option<usize>,
+    },
 }
 
 fn main() -> anyhow::Result<()> {
@@ -66,13 +230,142 @@ fn main() -> anyhow::Result<()> {
     let mut app = App::new();
 
     let command = cli.command.unwrap_or(Command::Viewer);
-    app.insert_resource(command);
 
-    match command {
-        Command::Bench { ticks } => {
-            app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>())
-                .insert_resource(BenchTicks(ticks))
-                .add_systems(First, exit_bench);
+    if let Command::Import {
+        file,
+        tile_size,
+        collision_layer,
+    } = &command
+    {
+        let file = Path::new(file);
+        let mut level = level::tiled::load_level(file, *tile_size, collision_layer)?;
+        if let Some(level_size) = cli.level_size {
+            level.scale_to(level_size);
+        }
+        let name = match cli.level {
+            Some(name) => name,
+            None => file
+                .file_stem()
+                .and_then(|s| s.to_str())
+                .unwrap_or("unnamed")
+                .to_owned(),
+        };
+        level.save(&format!("levels/{name}.level"))?;
+        println!(
+            "Saved '{name}' with {} walls, {} spawn points and {} targets",
+            level.walls.len(),
+            level.spawn_points.len(),
+            level.targets.len()
+        );
+        return Ok(());
+    }
+
+    if let Command::Generate {
+        algorithm,
+        seed,
+        count,
+    } = &command
+    {
+        let size = cli.level_size.unwrap_or(100.);
+        let level = level::generation::generate(*algorithm, size, *seed, *count)?;
+        let name = match cli.level {
+            Some(name) => name,
+            None => format!(
+                "{}-{seed}",
+                algorithm.to_possible_value().unwrap().get_name()
+            ),
+        };
+        level.save(&format!("levels/{name}.level"))?;
+        println!("Saved '{name}' with {} walls", level.walls.len());
+        return Ok(());
+    }
+
+    if command == Command::Validate {
+        let names = match cli.level {
+            Some(name) => vec![name],
+            None => fs::read_dir("levels")?
+                .filter_map(|entry| {
+                    let path = entry.ok()?.path();
+                    (path.extension()? == "level")
+                        .then(|| path.file_stem()?.to_str().map(str::to_owned))?
+                })
+                .sorted()
+                .collect(),
+        };
+
+        let mut problem_count = 0;
+        for name in names {
+            let level = load_level(&name, cli.level_size)?;
+            let problems = level::validation::validate(&level);
+            if problems.is_empty() {
+                println!("{name}: OK");
+            }
+            for problem in &problems {
+                println!("{name}: {problem}");
+            }
+            problem_count += problems.len();
+        }
+
+        if problem_count > 0 {
+            bail!("Found {problem_count} problems");
+        }
+        return Ok(());
+    }
+
+    if cli.tick_rate <= 0. {
+        bail!("Tick rate must be positive");
+    }
+    if cli.target_capacity.is_some_and(|capacity| capacity <= 0.) {
+        bail!("Target capacity must be positive");
+    }
+    if cli.stay_at_target && cli.target_capacity.is_some() {
+        // Agents that passed would block the gate for everyone behind them
+        bail!("Agents have to leave through targets with a capacity");
+    }
+
+    if let Command::Bench { ticks } = command {
+        if cli.warmup >= ticks {
+            bail!("Warmup of {} ticks leaves no ticks to measure", cli.warmup);
+        }
+    }
+
+    let level = match &cli.level {
+        Some(level_path) => load_level(level_path, cli.level_size)?,
+        None => Level::default(),
+    };
+
+    if cli.player.is_some() && !cli.update_nav && !cli.density_nav {
+        bail!("The player needs --update-nav or --density-nav to update the flow field");
+    }
+    if cli.player == Some(PlayerControl::Keyboard) && command != Command::Viewer {
+        bail!("Keyboard control needs the viewer, use --player bot instead");
+    }
+
+    let evacuation = match command {
+        Command::Evacuate { agents, max_time } => {
+            if cli.spawn_schedule.is_some() {
+                bail!("Evacuation places all agents at the start, it can't use a spawn schedule");
+            }
+            if cli.stay_at_target {
+                bail!("Agents have to leave through targets to evacuate");
+            }
+            if level.targets.is_empty() {
+                bail!("Evacuation needs a level with targets");
+            }
+            Some(EvacuationSettings { agents, max_time })
+        }
+        _ => None,
+    };
+
+    app.insert_resource(command.clone());
+
+    match &command {
+        Command::Bench { .. } | Command::Evacuate { .. } => {
+            app.add_plugins(MinimalPlugins.build().disable::<TimePlugin>());
+            if let Command::Bench { ticks } = command {
+                app.insert_resource(BenchTicks(ticks))
+                    .add_systems(First, exit_bench);
+            }
         }
         _ => {
             app.add_plugins((
@@ -97,7 +390,7 @@ fn main() -> anyhow::Result<()> {
     if command == Command::Viewer {
         app.add_plugins(MouseFollowPlugin)
             .insert_resource(FramepaceSettings {
-                limiter: Limiter::from_framerate(FRAME_RATE as f64),
+                limiter: Limiter::from_framerate(cli.tick_rate as f64),
             });
     }
 
@@ -106,26 +399,88 @@ fn main() -> anyhow::Result<()> {
     }
 
     if command != Command::Editor {
+        let spawn_schedule = match &cli.spawn_schedule {
+            Some(path) => SpawnSchedule::load(Path::new(path))?,
+            None if evacuation.is_some() => SpawnSchedule { waves: Vec::new() },
+            None => level.spawn_schedule.clone().unwrap_or_default(),
+        };
+        spawn_schedule
+            .validate(level.spawn_points.len())
+            .context("Invalid spawn schedule")?;
+
+        let target_motions = match &cli.target_motion {
+            Some(path) => {
+                if !cli.update_nav && !cli.density_nav {
+                    bail!("Moving targets need --update-nav or --density-nav to update the flow field");
+                }
+                let motions = TargetMotions::load(Path::new(path))?;
+                motions.validate(&level).context("Invalid target motions")?;
+                motions
+            }
+            None => TargetMotions::default(),
+        };
+
+        let default = AgentParamsDistribution::default();
+        let agent_params = AgentParamsDistribution {
+            max_speed: cli.max_speed.unwrap_or(default.max_speed),
+            acceleration: cli.acceleration.unwrap_or(default.acceleration),
+            preferred_spacing: cli.preferred_spacing.unwrap_or(default.preferred_spacing),
+        };
+        agent_params.validate()?;
+
+        // Every file of the run is written next to the output when exiting
+        if let Some(dir) = cli.output.parent() {
+            fs::create_dir_all(dir).with_context(|| {
+                format!("Failed to create output directory '{}'", dir.display())
+            })?;
+        }
+
         app.add_plugins((
             SimulationPlugin {
                 update_nav: cli.update_nav,
+                density_nav: cli.density_nav,
+                hard_collisions: cli.hard_collisions,
+                depenetration: cli.depenetration.map(|iterations| DepenetrationSettings {
+                    iterations,
+                    max_overlap: cli.max_overlap,
+                }),
+                boids_weights: BoidsWeights {
+                    separation: cli.separation_weight,
+                    alignment: cli.alignment_weight,
+                    cohesion: cli.cohesion_weight,
+                },
+                agent_params,
+                tick: SimulationTick {
+                    length: 1. / cli.tick_rate,
+                    substeps: cli.substeps,
+                },
+                arrival: Arrival::new(
+                    cli.arrival_radius,
+                    cli.stay_at_target,
+                    cli.target_capacity.map(|capacity| GateSettings {
+                        capacity,
+                        queue_radius: cli.queue_radius,
+                    }),
+                ),
+                spawn_schedule,
+                evacuation,
+                target_motions,
+                player: cli.player,
+                quality: cli.quality,
+            },
+            StatisticsPlugin {
+                settings: StatisticsSettings {
+                    output: cli.output,
+                    warmup: cli.warmup,
+                    plot: cli.plot,
+                },
             },
-            StatisticsPlugin,
         ));
     }
 
-    app.add_plugins(LevelPlugin);
+    app.add_plugins(LevelPlugin).insert_resource(level);
     if let Some(level_path) = cli.level {
-        let file = File::open(format!("levels/{level_path}.level"))?;
-        let mut level: Level = rmp_serde::from_read(file)?;
-
-        if let Some(level_size) = cli.level_size {
-            level.scale_to(level_size);
-        }
-        app.insert_resource(level)
-            .insert_resource(LevelPath(level_path));
-    } else {
-        app.insert_resource(Level::default());
+        app.insert_resource(LevelPath(level_path));
     }
 
     app.run();
@@ -133,6 +488,26 @@ fn main() -> anyhow::Result<()> {
     Ok(())
 }
 
+fn load_level(name: &str, level_size: Option<f32>) -> anyhow::Result<Level> {
+    let mut level = Level::load(&format!("levels/{name}.level"))
+        .with_context(|| format!("Failed to load level '{name}'"))?;
+    if let Some(level_size) = level_size {
+        level.scale_to(level_size);
+    }
+    Ok(level)
+}
+
+/// Parses "4" or "3..5" to a range.
+fn parse_range(s: &str) -> anyhow::Result<RangeInclusive<f32>> {
+    let (min, max) = s.split_once("..").unwrap_or((s, s));
+    let min = min.trim().parse::<f32>()?;
+    let max = max.trim().parse::<f32>()?;
+    if !(min > 0. && min <= max) {
+        bail!("Expected a positive value or a range MIN..MAX, got '{s}'");
+    }
+    Ok(min..=max)
+}
+
 #[derive(Resource)]
 struct BenchTicks(u32);
 
@@ -0,0 +1,400 @@
+use bevy::{
+    prelude::*,
+    utils::{HashMap, Instant},
+};
+
+use crate::{
+    level::Target,
+    statistics::{is_exiting, Statistics},
+    utils::Velocity,
+};
+
+use super::{
+    movement::flow_direction,
+    navigation::{FlowField, NavGrid, NavGridInner},
+    spawning::{AgentParams, Enemy},
+    SimulationSet, SimulationTick,
+};
+
+mod gates;
+
+pub use gates::GateSettings;
+
+use gates::{Line, QueueMetrics, AT_GATE_DISTANCE};
+
+/// Tracks what agents are doing and what happens when they reach a target.
+/// By default agents are despawned right when they step on a target cell.
+pub struct ArrivalPlugin {
+    pub arrival: Arrival,
+}
+
+impl Plugin for ArrivalPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.arrival.clone())
+            .init_resource::<QueueMetrics>()
+            .add_event::<AgentExited>()
+            .add_systems(
+                PreUpdate,
+                update_agent_states.in_set(SimulationSet::Despawn),
+            );
+
+        if self.arrival.gates.is_some() {
+            app.add_systems(Last, gates::write_queue_metrics.run_if(is_exiting));
+        }
+    }
+}
+
+/// What an agent is doing, updated at the start of every tick.
+#[derive(Component, Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AgentState {
+    /// Following the flow field at full speed.
+    #[default]
+    Moving,
+    /// Within the arrival radius of a target, slowing down.
+    Arriving,
+    /// Stays at the target, only with `Arrival::stay`.
+    Waiting,
+    /// Within the arrival radius but blocked by other agents.
+    Queuing,
+    /// In the line of a target that is at capacity, only with `Arrival::gates`.
+    /// Agents that can't get closer to their place leave the line.
+    InLine,
+    /// The flow field doesn't lead anywhere from here.
+    Idle,
+}
+
+/// Sent when an agent passes a target and is despawned.
+#[derive(Event, Clone, Copy, Debug)]
+pub struct AgentExited {
+    pub agent: Entity,
+    pub target: Entity,
+}
+
+/// Agents slower than this fraction of their maximum speed near a target are queuing.
+const QUEUING_SPEED: f32 = 0.1;
+/// Arriving agents keep at least this fraction of their maximum speed so they reach the target.
+const MIN_ARRIVAL_SPEED: f32 = 0.2;
+/// Agents in a line stop when they are this close to their place.
+const LINE_GOAL_TOLERANCE: f32 = 0.1;
+
+#[derive(Resource, Clone, Debug, Default)]
+pub struct Arrival {
+    /// Agents closer than this to a target slow down. Zero keeps full speed until the target.
+    pub radius: f32,
+    /// Keep agents waiting at the target instead of despawning them.
+    pub stay: bool,
+    /// Limit the throughput of targets. Without this targets take any number of agents.
+    pub gates: Option<GateSettings>,
+    /// Positions of the targets, updated every tick.
+    targets: Vec<Vec2>,
+    /// Entities of the targets, in the same order as `targets`.
+    target_entities: Vec<Entity>,
+    /// Lines of the targets, in the same order as `targets`.
+    lines: Vec<Line>,
+    /// Where agents in a line should stand. Agents that can't see their place
+    /// follow the flow field instead.
+    line_goals: HashMap<Entity, Vec2>,
+    /// Simulated seconds, for wait times.
+    time: f32,
+}
+
+impl Arrival {
+    pub fn new(radius: f32, stay: bool, gates: Option<GateSettings>) -> Self {
+        Self {
+            radius,
+            stay,
+            gates,
+            ..default()
+        }
+    }
+
+    fn distance(&self, pos: Vec2) -> f32 {
+        self.targets
+            .iter()
+            .map(|target| target.distance(pos))
+            .fold(f32::INFINITY, f32::min)
+    }
+
+    /// Direction and speed that an agent wants to move in before avoiding others.
+    /// `direction` is the direction from the flow field.
+    pub fn preferred_motion(
+        &self,
+        entity: Entity,
+        state: AgentState,
+        pos: Vec2,
+        direction: Vec2,
+        max_speed: f32,
+    ) -> (Vec2, f32) {
+        match state {
+            AgentState::Moving | AgentState::Idle => (direction, max_speed),
+            AgentState::Arriving | AgentState::Queuing => (
+                direction,
+                max_speed * (self.distance(pos) / self.radius).clamp(MIN_ARRIVAL_SPEED, 1.),
+            ),
+            AgentState::InLine => match self.line_goals.get(&entity) {
+                Some(&goal) => {
+                    let diff = goal - pos;
+                    let distance = diff.length();
+                    if distance < LINE_GOAL_TOLERANCE {
+                        (Vec2::ZERO, 0.)
+                    } else {
+                        (diff / distance, max_speed * distance.min(1.))
+                    }
+                }
+                // Without a queue area agents push towards the target
+                None => (direction, max_speed * MIN_ARRIVAL_SPEED),
+            },
+            AgentState::Waiting => (direction, 0.),
+        }
+    }
+
+    /// Index of the nearest target whose line an agent at `pos` should join.
+    /// Targets behind walls are left out, so agents don't line up on the wrong side.
+    fn line_to_join(&self, pos: Vec2, nav_grid: &NavGridInner) -> Option<usize> {
+        let radius = self.gates?.queue_radius.max(AT_GATE_DISTANCE);
+        self.targets
+            .iter()
+            .enumerate()
+            .map(|(i, target)| (i, target.distance(pos)))
+            .filter(|&(i, distance)| {
+                distance <= radius && nav_grid.line_of_sight(pos, self.targets[i])
+            })
+            .min_by(|a, b| a.1.total_cmp(&b.1))
+            .map(|(i, _)| i)
+    }
+}
+
+type EnemyQuery<'w, 's> = Query<
+    'w,
+    's,
+    (
+        Entity,
+        &'static Transform,
+        &'static Velocity,
+        &'static AgentParams,
+        &'static mut AgentState,
+    ),
+    With<Enemy>,
+>;
+
+fn update_agent_states(
+    target_q: Query<(Entity, &Transform), With<Target>>,
+    mut enemy_q: EnemyQuery,
+    nav_grid: Res<NavGrid>,
+    flow_field: Res<FlowField>,
+    tick: Res<SimulationTick>,
+    mut arrival: ResMut<Arrival>,
+    mut metrics: ResMut<QueueMetrics>,
+    mut commands: Commands,
+    mut exits: EventWriter<AgentExited>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+
+    let arrival = &mut *arrival;
+    arrival.time += tick.length;
+    (arrival.target_entities, arrival.targets) = target_q
+        .iter()
+        .map(|(entity, t)| (entity, t.translation.truncate()))
+        .unzip();
+    if arrival.gates.is_some() {
+        arrival
+            .lines
+            .resize_with(arrival.targets.len(), Line::default);
+    }
+
+    let target_indices = arrival
+        .targets
+        .iter()
+        .map(|&pos| nav_grid.pos_to_index(pos))
+        .collect::<Vec<_>>();
+
+    for (entity, transform, velocity, params, mut state) in &mut enemy_q {
+        // Gates take agents out of lines
+        if *state == AgentState::InLine {
+            continue;
+        }
+
+        let pos = transform.translation.truncate();
+        let nav_idx = nav_grid.pos_to_index(pos);
+
+        // With gates targets are passed only through the lines
+        let reached_target = target_indices
+            .iter()
+            .position(|&idx| idx == nav_idx)
+            .filter(|_| arrival.gates.is_none());
+
+        let new_state = if let Some(line) = arrival.line_to_join(pos, &nav_grid) {
+            let time = arrival.time;
+            arrival.lines[line].join(entity, time);
+            AgentState::InLine
+        } else if let Some(target) = reached_target {
+            if !arrival.stay {
+                commands.entity(entity).despawn();
+                exits.send(AgentExited {
+                    agent: entity,
+                    target: arrival.target_entities[target],
+                });
+                continue;
+            }
+            AgentState::Waiting
+        } else if arrival.radius > 0. && arrival.distance(pos) < arrival.radius {
+            if velocity.0.length() < params.max_speed * QUEUING_SPEED {
+                AgentState::Queuing
+            } else {
+                AgentState::Arriving
+            }
+        } else if flow_direction(&flow_field, &nav_grid, pos) == Vec2::ZERO {
+            AgentState::Idle
+        } else {
+            AgentState::Moving
+        };
+
+        // Avoids triggering change detection every tick
+        state.set_if_neq(new_state);
+    }
+
+    if let Some(settings) = arrival.gates {
+        gates::update_gates(
+            arrival,
+            settings,
+            &mut enemy_q,
+            &mut commands,
+            &mut exits,
+            &mut metrics,
+            &nav_grid,
+            tick.length,
+        );
+    }
+
+    stats.add("agent_states", start.elapsed());
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ndarray::Array2;
+
+    use crate::{
+        simulation::{navigation::Flow, spawning::AgentParamsDistribution},
+        utils::Vertices,
+    };
+
+    use super::*;
+
+    /// Agents that exited so far, in order.
+    #[derive(Resource, Default)]
+    pub(super) struct Exits(pub Vec<AgentExited>);
+
+    fn record_exits(mut events: EventReader<AgentExited>, mut exits: ResMut<Exits>) {
+        exits.0.extend(events.read().copied());
+    }
+
+    /// Runs only `update_agent_states`, agents stay where they are put.
+    pub(super) fn test_app(arrival: Arrival, walls: &[Vertices], flow: Flow) -> App {
+        let nav_grid = NavGridInner::new(20., walls);
+        let flow_field = Array2::from_elem(nav_grid.walkable.raw_dim(), (0., flow));
+
+        let mut app = App::new();
+        app.add_event::<AgentExited>()
+            .insert_resource(arrival)
+            .insert_resource(NavGrid(Arc::new(nav_grid)))
+            .insert_resource(FlowField(flow_field))
+            .insert_resource(SimulationTick {
+                length: 1. / 60.,
+                substeps: 1,
+            })
+            .init_resource::<QueueMetrics>()
+            .init_resource::<Statistics>()
+            .init_resource::<Exits>()
+            .add_systems(Update, (update_agent_states, record_exits).chain());
+        app
+    }
+
+    pub(super) fn spawn_target(app: &mut App, pos: Vec2) -> Entity {
+        app.world
+            .spawn((Target, Transform::from_translation(pos.extend(0.))))
+            .id()
+    }
+
+    pub(super) fn spawn_agent(app: &mut App, pos: Vec2) -> Entity {
+        let params = AgentParamsDistribution::default().sample(&mut rand::thread_rng());
+        app.world
+            .spawn((
+                Enemy,
+                Transform::from_translation(pos.extend(0.)),
+                Velocity::default(),
+                params,
+                AgentState::default(),
+            ))
+            .id()
+    }
+
+    pub(super) fn exits(app: &App) -> &[AgentExited] {
+        &app.world.resource::<Exits>().0
+    }
+
+    #[test]
+    fn slows_down_near_targets() {
+        let arrival = Arrival {
+            radius: 10.,
+            targets: vec![Vec2::ZERO],
+            ..default()
+        };
+        let entity = Entity::from_raw(0);
+        let speed = |state, pos| arrival.preferred_motion(entity, state, pos, Vec2::X, 2.).1;
+
+        assert_eq!(speed(AgentState::Moving, Vec2::new(5., 0.)), 2.);
+        assert_eq!(speed(AgentState::Arriving, Vec2::new(5., 0.)), 1.);
+        assert_eq!(speed(AgentState::Queuing, Vec2::new(5., 0.)), 1.);
+        // Never slower than the minimum, so agents still reach the target
+        assert_eq!(
+            speed(AgentState::Arriving, Vec2::new(0.5, 0.)),
+            2. * MIN_ARRIVAL_SPEED
+        );
+        assert_eq!(speed(AgentState::Waiting, Vec2::ZERO), 0.);
+    }
+
+    #[test]
+    fn agents_wait_at_targets_with_stay() {
+        let mut app = test_app(Arrival::new(0., true, None), &[], Flow::East);
+        spawn_target(&mut app, Vec2::new(10., 10.));
+        let agent = spawn_agent(&mut app, Vec2::new(10.1, 10.1));
+        let other = spawn_agent(&mut app, Vec2::new(5., 10.));
+        app.update();
+
+        assert_eq!(
+            app.world.get::<AgentState>(agent),
+            Some(&AgentState::Waiting)
+        );
+        assert_eq!(
+            app.world.get::<AgentState>(other),
+            Some(&AgentState::Moving)
+        );
+        assert!(exits(&app).is_empty());
+    }
+
+    #[test]
+    fn agents_exit_at_targets() {
+        let mut app = test_app(Arrival::default(), &[], Flow::East);
+        let target = spawn_target(&mut app, Vec2::new(10., 10.));
+        let agent = spawn_agent(&mut app, Vec2::new(10.1, 10.1));
+        app.update();
+
+        assert!(app.world.get_entity(agent).is_none());
+        let exits = exits(&app);
+        assert_eq!(exits.len(), 1);
+        assert_eq!((exits[0].agent, exits[0].target), (agent, target));
+    }
+
+    #[test]
+    fn agents_without_flow_are_idle() {
+        let mut app = test_app(Arrival::default(), &[], Flow::None);
+        spawn_target(&mut app, Vec2::new(10., 10.));
+        let agent = spawn_agent(&mut app, Vec2::new(3., 3.));
+        app.update();
+
+        assert_eq!(app.world.get::<AgentState>(agent), Some(&AgentState::Idle));
+    }
+}
@@ -0,0 +1,299 @@
+use std::{collections::VecDeque, fs};
+
+use bevy::prelude::*;
+use serde::Serialize;
+
+use crate::{
+    simulation::{navigation::NavGridInner, spawning::ENEMY_RADIUS},
+    statistics::StatisticsSettings,
+};
+
+use super::{AgentExited, AgentState, Arrival, EnemyQuery};
+
+/// Agents this close to a target can pass it.
+pub(super) const AT_GATE_DISTANCE: f32 = ENEMY_RADIUS * 2.;
+/// Agents further than this from their place that haven't got closer to it in
+/// `LINE_PATIENCE` seconds leave the line, e.g. when walls or the crowd block them.
+const LINE_STALL_DISTANCE: f32 = ENEMY_RADIUS * 4.;
+const LINE_PATIENCE: f32 = 3.;
+/// Getting this much closer to the place counts as progress.
+const LINE_PROGRESS: f32 = 0.1;
+
+/// Limits how fast agents can pass targets, like exit doors or ticket gates.
+#[derive(Clone, Copy, Debug)]
+pub struct GateSettings {
+    /// Agents that can pass one target per second.
+    pub capacity: f32,
+    /// Agents closer than this to a target line up behind each other.
+    /// Zero makes them crowd at the target instead.
+    pub queue_radius: f32,
+}
+
+/// Agents waiting to pass one target, in the order they arrived.
+#[derive(Clone, Debug, Default)]
+pub(super) struct Line {
+    agents: VecDeque<LineAgent>,
+    /// Agents that can pass right away, grows by `GateSettings::capacity` per second.
+    passes: f32,
+}
+
+#[derive(Clone, Debug)]
+struct LineAgent {
+    entity: Entity,
+    joined: f32,
+    /// Closest distance to its place so far and when it got there.
+    closest: f32,
+    progressed: f32,
+}
+
+impl Line {
+    pub(super) fn join(&mut self, entity: Entity, time: f32) {
+        self.agents.push_back(LineAgent {
+            entity,
+            joined: time,
+            closest: f32::INFINITY,
+            progressed: time,
+        });
+    }
+}
+
+/// Lengths of the lines of every tick and how long each agent waited before passing.
+#[derive(Resource, Default, Serialize)]
+pub(super) struct QueueMetrics {
+    queue_length: Vec<u32>,
+    wait_times: Vec<f32>,
+}
+
+/// Lets agents at the front of the lines pass and tells the others where to stand.
+pub(super) fn update_gates(
+    arrival: &mut Arrival,
+    settings: GateSettings,
+    enemy_q: &mut EnemyQuery,
+    commands: &mut Commands,
+    exits: &mut EventWriter<AgentExited>,
+    metrics: &mut QueueMetrics,
+    nav_grid: &NavGridInner,
+    dt: f32,
+) {
+    let time = arrival.time;
+    let max_passes = 1. + settings.capacity * dt;
+
+    let mut queue_length = 0;
+    let targets = arrival.targets.iter().zip(&arrival.target_entities);
+    for (line, (&target, &target_entity)) in arrival.lines.iter_mut().zip(targets) {
+        // Unused capacity is not saved for later
+        line.passes = (line.passes + settings.capacity * dt).min(max_passes);
+        line.agents.retain(|agent| enemy_q.contains(agent.entity));
+
+        while line.passes >= 1. {
+            // Usually the first in line, unless the crowd has pushed it away from the gate
+            let Some(i) = line.agents.iter().position(|agent| {
+                enemy_q.get(agent.entity).is_ok_and(|(_, transform, ..)| {
+                    transform.translation.truncate().distance(target) <= AT_GATE_DISTANCE
+                })
+            }) else {
+                break;
+            };
+            let LineAgent { entity, joined, .. } = line.agents.remove(i).unwrap();
+
+            line.passes -= 1.;
+            metrics.wait_times.push(time - joined);
+            commands.entity(entity).despawn();
+            exits.send(AgentExited {
+                agent: entity,
+                target: target_entity,
+            });
+        }
+
+        queue_length += line.agents.len() as u32;
+    }
+    metrics.queue_length.push(queue_length);
+
+    arrival.line_goals.clear();
+    if settings.queue_radius <= 0. {
+        return;
+    }
+    let mut stalled = Vec::new();
+    for (line, &target) in arrival.lines.iter_mut().zip(&arrival.targets) {
+        // Every place is next to the one ahead, towards the agent, so lines can bend around.
+        // Lines don't fold back though, or places would pile up around the target
+        let mut ahead = target;
+        let mut outward = Vec2::ZERO;
+        for (i, agent) in line.agents.iter_mut().enumerate() {
+            let Ok((_, transform, _, params, _)) = enemy_q.get(agent.entity) else {
+                continue;
+            };
+            let pos = transform.translation.truncate();
+            let goal = if i == 0 {
+                target
+            } else {
+                let mut direction = (pos - ahead).normalize_or_zero();
+                if direction.dot(outward) < 0. {
+                    direction = outward;
+                }
+                let spacing = params.preferred_spacing.max(ENEMY_RADIUS * 2.);
+                ahead + direction * spacing
+            };
+            if goal != ahead {
+                outward = (goal - ahead).normalize();
+            }
+            ahead = goal;
+
+            let distance = pos.distance(goal);
+            if distance < agent.closest - LINE_PROGRESS {
+                agent.closest = distance;
+                agent.progressed = time;
+            }
+            if distance > LINE_STALL_DISTANCE && time - agent.progressed > LINE_PATIENCE {
+                stalled.push(agent.entity);
+                continue;
+            }
+            // Walking straight to a place behind a wall would pin the agent to the wall
+            if nav_grid.line_of_sight(pos, goal) {
+                arrival.line_goals.insert(agent.entity, goal);
+            }
+        }
+    }
+
+    // They can join again at the end of the line
+    for entity in stalled {
+        for line in &mut arrival.lines {
+            line.agents.retain(|agent| agent.entity != entity);
+        }
+        if let Ok((.., mut state)) = enemy_q.get_mut(entity) {
+            *state = AgentState::Moving;
+        }
+    }
+}
+
+pub(super) fn write_queue_metrics(metrics: Res<QueueMetrics>, statistics: Res<StatisticsSettings>) {
+    fs::write(
+        statistics.path_next_to_output("queues", "json"),
+        serde_json::to_string(&*metrics).unwrap(),
+    )
+    .unwrap();
+
+    let max_length = metrics.queue_length.iter().copied().max().unwrap_or(0);
+    let mean_length =
+        metrics.queue_length.iter().sum::<u32>() as f32 / metrics.queue_length.len().max(1) as f32;
+    let max_wait = metrics.wait_times.iter().copied().fold(0., f32::max);
+    let mean_wait = if metrics.wait_times.is_empty() {
+        0.
+    } else {
+        metrics.wait_times.iter().sum::<f32>() / metrics.wait_times.len() as f32
+    };
+    println!(
+        "Queue length mean {mean_length:.1}, max {max_length}. Wait time of {} agents mean {mean_wait:.2} s, max {max_wait:.2} s",
+        metrics.wait_times.len()
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{
+        simulation::{
+            arrival::tests::{exits, spawn_agent, spawn_target, test_app},
+            navigation::Flow,
+        },
+        utils::{square, WithOffset},
+    };
+
+    use super::*;
+
+    fn gates(capacity: f32, queue_radius: f32) -> Arrival {
+        Arrival::new(
+            0.,
+            false,
+            Some(GateSettings {
+                capacity,
+                queue_radius,
+            }),
+        )
+    }
+
+    fn line(app: &App) -> Vec<Entity> {
+        app.world.resource::<Arrival>().lines[0]
+            .agents
+            .iter()
+            .map(|agent| agent.entity)
+            .collect()
+    }
+
+    #[test]
+    fn passes_agents_at_capacity() {
+        let mut app = test_app(gates(2., 0.), &[], Flow::East);
+        spawn_target(&mut app, Vec2::new(10., 10.));
+        for _ in 0..10 {
+            spawn_agent(&mut app, Vec2::new(10., 10.));
+        }
+
+        for _ in 0..60 {
+            app.update();
+        }
+        assert_eq!(exits(&app).len(), 2);
+        assert_eq!(line(&app).len(), 8);
+    }
+
+    #[test]
+    fn passes_agents_in_join_order() {
+        // Nobody passes until the capacity is raised
+        let mut app = test_app(gates(0.001, 0.), &[], Flow::East);
+        spawn_target(&mut app, Vec2::new(10., 10.));
+        let mut joined = Vec::new();
+        for i in 0..5 {
+            joined.push(spawn_agent(&mut app, Vec2::new(10., 9.6 + i as f32 * 0.2)));
+            app.update();
+        }
+        assert_eq!(line(&app), joined);
+
+        // The first one was pushed away from the gate, so the next one goes first
+        app.world
+            .get_mut::<Transform>(joined[0])
+            .unwrap()
+            .translation
+            .y = 15.;
+        app.world.resource_mut::<Arrival>().gates = gates(60., 0.).gates;
+        for _ in 0..5 {
+            app.update();
+        }
+        let passed = exits(&app)
+            .iter()
+            .map(|exit| exit.agent)
+            .collect::<Vec<_>>();
+        assert_eq!(passed, joined[1..]);
+        assert_eq!(line(&app), [joined[0]]);
+    }
+
+    #[test]
+    fn agents_behind_walls_leave_the_line() {
+        let wall = square(3.).with_offset(Vec2::new(14.5, 12.5));
+        let mut app = test_app(gates(0.001, 8.), &[wall], Flow::East);
+        spawn_target(&mut app, Vec2::new(10., 10.));
+        let front = spawn_agent(&mut app, Vec2::new(10., 10.));
+        let stuck = spawn_agent(&mut app, Vec2::new(10., 14.));
+        let hidden = spawn_agent(&mut app, Vec2::new(17.5, 12.5));
+        app.update();
+
+        // Close enough, but the wall is in the way
+        assert_eq!(
+            app.world.get::<AgentState>(hidden),
+            Some(&AgentState::Moving)
+        );
+        assert_eq!(line(&app), [front, stuck]);
+
+        app.world.get_mut::<Transform>(stuck).unwrap().translation = Vec3::new(17.5, 13.5, 0.);
+        let ticks = (LINE_PATIENCE * 60.) as usize;
+        for _ in 0..ticks - 5 {
+            app.update();
+        }
+        assert_eq!(line(&app), [front, stuck]);
+        for _ in 0..10 {
+            app.update();
+        }
+        assert_eq!(line(&app), [front]);
+        assert_eq!(
+            app.world.get::<AgentState>(stuck),
+            Some(&AgentState::Moving)
+        );
+    }
+}
@@ -1,5 +1,6 @@
-use bevy::prelude::*;
+use bevy::{prelude::*, utils::Instant};
 
+/// Pushes overlapping agents apart and out of walls after movement.
 pub struct CollisionPlugin;
 
 use bevy_rapier2d::{
@@ -7,7 +8,13 @@ use bevy_rapier2d::{
     rapier::prelude::{ColliderBuilder, Isometry},
 };
 
-use super::SimulationSet;
+use crate::{level::Wall, statistics::Statistics, utils::winding_number};
+
+use super::{
+    navigation::NavGrid,
+    spawning::{Enemy, ENEMY_RADIUS},
+    SimulationSet, SimulationStep,
+};
 
 impl Plugin for CollisionPlugin {
     fn build(&self, app: &mut App) {
@@ -21,12 +28,13 @@ impl Plugin for CollisionPlugin {
                 ..default()
             })
             .add_systems(
-                PreUpdate,
+                SimulationStep,
                 (
                     init_colliders,
                     apply_collider_user_changes,
                     sync_removals,
                     update_query_pipeline,
+                    resolve_collisions,
                 )
                     .chain()
                     .in_set(SimulationSet::ApplyColliders),
@@ -170,6 +178,75 @@ fn update_query_pipeline(mut context: ResMut<RapierContext>) {
     context.update_query_pipeline();
 }
 
+/// Moves agents out of walls and each other. Corrections are calculated from the positions
+/// before any of them are applied, so the result doesn't depend on iteration order.
+/// They are applied with `NavGrid::slide`, so pushes from other agents can't move agents
+/// into walls.
+fn resolve_collisions(
+    context: Res<RapierContext>,
+    mut enemy_q: Query<(Entity, &mut Transform), With<Enemy>>,
+    wall_q: Query<(&Wall, &Transform), Without<Enemy>>,
+    nav_grid: Res<NavGrid>,
+    mut stats: ResMut<Statistics>,
+    mut corrections: Local<Vec<(Entity, Vec2)>>,
+) {
+    let start = Instant::now();
+
+    let shape = Collider::ball(ENEMY_RADIUS);
+    corrections.clear();
+
+    for (entity, transform) in &enemy_q {
+        let pos = transform.translation.truncate();
+        let mut correction = Vec2::ZERO;
+
+        let filter = QueryFilter::new().exclude_collider(entity);
+        context.intersections_with_shape(pos, 0., &shape, filter, |other| {
+            if let Ok((_, other_transform)) = enemy_q.get(other) {
+                // Both agents move half of the way
+                let diff = pos - other_transform.translation.truncate();
+                let distance = diff.length();
+                if distance > 0. {
+                    correction += diff / distance * (ENEMY_RADIUS * 2. - distance).max(0.) * 0.5;
+                }
+            } else if let Ok((wall, wall_transform)) = wall_q.get(other) {
+                let is_other = |e: Entity| e == other;
+                let wall_filter = QueryFilter::new().predicate(&is_other);
+                if let Some((_, projection)) = context.project_point(pos, true, wall_filter) {
+                    let diff = pos - projection.point;
+                    let distance = diff.length();
+                    // Polylines have no inside, so agents whose center has crossed an edge
+                    // are pushed back through it instead of further in
+                    let local_pos = pos - wall_transform.translation.truncate();
+                    let inside = winding_number(local_pos, &wall.0) != 0;
+                    if distance > 0. {
+                        let push = if inside {
+                            -(ENEMY_RADIUS + distance)
+                        } else {
+                            (ENEMY_RADIUS - distance).max(0.)
+                        };
+                        correction += diff / distance * push;
+                    }
+                }
+            }
+            true
+        });
+
+        if correction != Vec2::ZERO {
+            corrections.push((entity, correction));
+        }
+    }
+
+    for &(entity, correction) in corrections.iter() {
+        if let Ok((_, mut transform)) = enemy_q.get_mut(entity) {
+            let moved = nav_grid.slide(transform.translation.truncate(), correction);
+            transform.translation.x += moved.x;
+            transform.translation.y += moved.y;
+        }
+    }
+
+    stats.add("hard_collisions", start.elapsed());
+}
+
 // pub fn ray_cast_target(
 //     rapier_ctx: &RapierContext,
 //     origin: Vec2,
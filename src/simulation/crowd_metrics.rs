@@ -0,0 +1,133 @@
+use bevy::{prelude::*, utils::Instant};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+
+use crate::{
+    level::Level,
+    statistics::{Metrics, Statistics},
+    utils::Velocity,
+};
+
+use super::{
+    flocking::{array::SpatialStructure, MAX_PREFERRED_DISTANCE},
+    movement::ENEMY_SPEED,
+    spawning::{Enemy, ENEMY_RADIUS},
+    SimulationSet,
+};
+
+/// Records the state of the crowd at the end of every tick to `Metrics`.
+pub struct CrowdMetricsPlugin;
+
+impl Plugin for CrowdMetricsPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init)
+            .add_systems(PreUpdate, record.after(SimulationSet::Step));
+    }
+}
+
+/// Agents slower than this are counted as stuck.
+const STUCK_SPEED: f32 = ENEMY_SPEED * 0.01;
+
+#[derive(Resource, Deref, DerefMut)]
+struct MetricsGrid(SpatialStructure);
+
+fn init(level: Res<Level>, mut commands: Commands) {
+    // Jammed cells are where the overlaps are, so none of their agents can be skipped
+    commands.insert_resource(MetricsGrid(SpatialStructure::uncapped(level.size)));
+}
+
+/// Neighbor count and the largest overlap between two agents.
+#[derive(Default, Clone, Copy)]
+struct Surroundings {
+    neighbors: u32,
+    max_overlap: f32,
+}
+
+fn record(
+    enemy_q: Query<(Entity, &Transform, &Velocity), With<Enemy>>,
+    new_enemy_q: Query<(), Added<Enemy>>,
+    mut grid: ResMut<MetricsGrid>,
+    mut previous_count: Local<u32>,
+    mut metrics: ResMut<Metrics>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+
+    let count = enemy_q.iter().len() as u32;
+    let spawned = new_enemy_q.iter().count() as u32;
+    let despawned = (*previous_count + spawned).saturating_sub(count);
+    *previous_count = count;
+
+    let mut total_speed = 0.;
+    let mut max_speed = 0f32;
+    let mut stuck = 0;
+    grid.reset();
+    for (entity, transform, velocity) in &enemy_q {
+        let speed = velocity.0.length();
+        total_speed += speed;
+        max_speed = max_speed.max(speed);
+        if speed < STUCK_SPEED {
+            stuck += 1;
+        }
+        let pos = transform.translation.truncate();
+        grid.insert_position(entity, pos);
+    }
+
+    let Surroundings {
+        neighbors,
+        max_overlap,
+    } = surroundings(&grid);
+
+    let mean = |total: f32| if count > 0 { total / count as f32 } else { 0. };
+    metrics.add("agents", count as f32);
+    metrics.add("spawned", spawned as f32);
+    metrics.add("despawned", despawned as f32);
+    metrics.add("mean_speed", mean(total_speed));
+    metrics.add("max_speed", max_speed);
+    metrics.add("mean_neighbors", mean(neighbors as f32));
+    metrics.add("stuck", stuck as f32);
+    metrics.add("max_overlap", max_overlap);
+
+    stats.add("metrics", start.elapsed());
+}
+
+/// Neighbors are the other agents within `MAX_PREFERRED_DISTANCE`, which every spatial
+/// backend finds. Returns the total of the neighbor counts and the largest overlap.
+fn surroundings(grid: &SpatialStructure) -> Surroundings {
+    #[cfg(not(feature = "parallel"))]
+    let iter = grid.grid.iter();
+    #[cfg(feature = "parallel")]
+    let iter = grid.grid.par_iter();
+
+    let cells = iter
+        .enumerate()
+        .filter(|(_, items)| !items.is_empty())
+        .map(|(cell, items)| {
+            let mut surroundings = Surroundings::default();
+            let Some(neighbors) = grid.get(cell) else {
+                return surroundings;
+            };
+            for &(entity, pos, _) in items {
+                for &(other_entity, other_pos, _) in neighbors.iter().flat_map(|v| v.iter()) {
+                    if other_entity == entity {
+                        continue;
+                    }
+                    let distance = pos.distance(other_pos);
+                    if distance < MAX_PREFERRED_DISTANCE {
+                        surroundings.neighbors += 1;
+                    }
+                    surroundings.max_overlap =
+                        surroundings.max_overlap.max(ENEMY_RADIUS * 2. - distance);
+                }
+            }
+            surroundings
+        })
+        .collect::<Vec<_>>();
+
+    cells
+        .into_iter()
+        .fold(Surroundings::default(), |total, cell| Surroundings {
+            neighbors: total.neighbors + cell.neighbors,
+            max_overlap: total.max_overlap.max(cell.max_overlap),
+        })
+}
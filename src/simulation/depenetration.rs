@@ -0,0 +1,223 @@
+use std::fs;
+
+use bevy::{prelude::*, utils::Instant};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    level::Level,
+    statistics::{is_exiting, Statistics, StatisticsSettings},
+    utils::Velocity,
+};
+
+use super::{
+    flocking::array::SpatialStructure,
+    navigation::NavGrid,
+    spawning::{Enemy, ENEMY_RADIUS},
+    SimulationSet, SimulationStep, SimulationTick,
+};
+
+/// Pushes overlapping agents apart after movement, position based dynamics style.
+/// All corrections of an iteration are calculated from the same positions (Jacobi style),
+/// so it's safe to calculate them in parallel.
+pub struct DepenetrationPlugin {
+    pub settings: DepenetrationSettings,
+}
+
+impl Plugin for DepenetrationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone())
+            .init_resource::<OverlapMetrics>()
+            .add_systems(Startup, init)
+            .add_systems(
+                SimulationStep,
+                depenetrate.in_set(SimulationSet::ApplyColliders),
+            )
+            .add_systems(Last, write_overlap_metrics.run_if(is_exiting));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct DepenetrationSettings {
+    /// Maximum number of correction passes per substep.
+    /// Dense crowds, e.g. right at spawn points, may need more passes than this
+    /// to get under `max_overlap`, which is why the overlaps are recorded.
+    pub iterations: u32,
+    /// Stop early when no pair of agents overlaps more than this.
+    pub max_overlap: f32,
+}
+
+/// Overlaps of each substep, before and after depenetration.
+#[derive(Resource, Default, Serialize)]
+struct OverlapMetrics {
+    max_overlap_before: Vec<f32>,
+    max_overlap_after: Vec<f32>,
+    overlapping_pairs_after: Vec<u32>,
+    iterations: Vec<u32>,
+}
+
+/// Without a cell capacity, so agents in dense crowds aren't skipped.
+#[derive(Resource, Deref, DerefMut)]
+struct DepenetrationGrid(SpatialStructure);
+
+fn init(level: Res<Level>, mut commands: Commands) {
+    commands.insert_resource(DepenetrationGrid(SpatialStructure::uncapped(level.size)));
+}
+
+fn depenetrate(
+    mut enemy_q: Query<(Entity, &mut Transform, &mut Velocity), With<Enemy>>,
+    nav_grid: Res<NavGrid>,
+    settings: Res<DepenetrationSettings>,
+    tick: Res<SimulationTick>,
+    mut grid: ResMut<DepenetrationGrid>,
+    mut metrics: ResMut<OverlapMetrics>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+
+    let mut max_overlap_before = 0.;
+    let mut max_overlap = 0.;
+    let mut overlapping_pairs = 0;
+    let mut iterations = 0;
+
+    // The last pass only measures the result
+    for iteration in 0..=settings.iterations {
+        grid.reset();
+        for (entity, transform, _) in &enemy_q {
+            let pos = transform.translation.truncate();
+            grid.insert_position(entity, pos);
+        }
+
+        let corrections = find_corrections(&grid);
+
+        max_overlap = corrections.iter().map(|c| c.max_overlap).fold(0., f32::max);
+        // Every pair is seen from both sides
+        overlapping_pairs = corrections.iter().map(|c| c.overlaps).sum::<u32>() / 2;
+        if iteration == 0 {
+            max_overlap_before = max_overlap;
+        }
+
+        if max_overlap <= settings.max_overlap || iteration == settings.iterations {
+            break;
+        }
+
+        for correction in corrections {
+            if let Ok((_, mut transform, mut velocity)) = enemy_q.get_mut(correction.entity) {
+                let pos = transform.translation.truncate();
+                let moved = nav_grid.slide(pos, correction.delta);
+                transform.translation.x += moved.x;
+                transform.translation.y += moved.y;
+                // Like in position based dynamics, so agents don't push back into the same spot
+                velocity.0 += moved / tick.dt();
+            }
+        }
+        iterations += 1;
+    }
+
+    metrics.max_overlap_before.push(max_overlap_before);
+    metrics.max_overlap_after.push(max_overlap);
+    metrics.overlapping_pairs_after.push(overlapping_pairs);
+    metrics.iterations.push(iterations);
+
+    stats.add("depenetration", start.elapsed());
+}
+
+struct Correction {
+    entity: Entity,
+    delta: Vec2,
+    max_overlap: f32,
+    overlaps: u32,
+}
+
+fn find_corrections(grid: &SpatialStructure) -> Vec<Correction> {
+    #[cfg(not(feature = "parallel"))]
+    let iter = grid.grid.iter();
+    #[cfg(feature = "parallel")]
+    let iter = grid.grid.par_iter();
+
+    let cells = iter
+        .enumerate()
+        .filter(|(_, items)| !items.is_empty())
+        .map(|(cell, items)| {
+            let Some(neighbors) = grid.get(cell) else {
+                return Vec::new();
+            };
+            items
+                .iter()
+                .filter_map(|&(entity, pos, _)| {
+                    let mut delta = Vec2::ZERO;
+                    let mut max_overlap = 0f32;
+                    let mut overlaps = 0;
+                    for &(other_entity, other_pos, _) in neighbors.iter().flat_map(|v| v.iter()) {
+                        if other_entity == entity {
+                            continue;
+                        }
+                        let diff = pos - other_pos;
+                        let distance = diff.length();
+                        let overlap = ENEMY_RADIUS * 2. - distance;
+                        if overlap <= 0. {
+                            continue;
+                        }
+                        max_overlap = max_overlap.max(overlap);
+                        overlaps += 1;
+                        let direction = if distance > 0. {
+                            diff / distance
+                        } else {
+                            coincident_direction(entity, other_entity)
+                        };
+                        // Both agents move half of the way
+                        delta += direction * overlap * 0.5;
+                    }
+                    (overlaps > 0).then_some(Correction {
+                        entity,
+                        delta,
+                        max_overlap,
+                        overlaps,
+                    })
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    cells.into_iter().flatten().collect()
+}
+
+/// Agents at the exact same position have no direction to separate in.
+/// Picks one from the entities, opposite for the two of them.
+fn coincident_direction(entity: Entity, other: Entity) -> Vec2 {
+    let (a, b) = (
+        entity.index().min(other.index()),
+        entity.index().max(other.index()),
+    );
+    let angle = (a.wrapping_mul(31) ^ b) as f32 * 2.399_963;
+    let direction = Vec2::from_angle(angle);
+    if entity.index() < other.index() {
+        direction
+    } else {
+        -direction
+    }
+}
+
+fn write_overlap_metrics(
+    metrics: Res<OverlapMetrics>,
+    settings: Res<DepenetrationSettings>,
+    statistics: Res<StatisticsSettings>,
+) {
+    fs::write(
+        statistics.path_next_to_output("overlap", "json"),
+        serde_json::to_string(&*metrics).unwrap(),
+    )
+    .unwrap();
+
+    let max_after = metrics.max_overlap_after.iter().copied().fold(0., f32::max);
+    let max_before = metrics
+        .max_overlap_before
+        .iter()
+        .copied()
+        .fold(0., f32::max);
+    println!(
+        "Max overlap {max_before:.4} before and {max_after:.4} after depenetration (tolerance {})",
+        settings.max_overlap
+    );
+}
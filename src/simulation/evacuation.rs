@@ -0,0 +1,290 @@
+use std::fs;
+
+use bevy::{
+    app::AppExit,
+    prelude::*,
+    utils::{HashMap, Instant},
+};
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::{
+    level::{Level, Target},
+    statistics::{is_exiting, Statistics, StatisticsSettings},
+};
+
+use super::{
+    arrival::AgentExited,
+    navigation::{Flow, FlowField, NavGrid},
+    rng::FastRng,
+    spawning::{placement, AgentParamsDistribution, Enemy, EnemyBundle, SPAWN_SPACING},
+    SimulationSet, SimulationTick,
+};
+
+/// Places agents all over the level at the start and ends the run when all of them have
+/// reached a target, instead of spawning them from the spawn points.
+pub struct EvacuationPlugin {
+    pub settings: EvacuationSettings,
+}
+
+impl Plugin for EvacuationPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.settings.clone())
+            .init_resource::<EvacuationMetrics>()
+            .add_systems(
+                PreUpdate,
+                place_agents.run_if(not_placed).in_set(SimulationSet::Spawn),
+            )
+            .add_systems(PostUpdate, track_exits.run_if(placed))
+            .add_systems(First, exit_evacuation)
+            .add_systems(Last, write_evacuation_metrics.run_if(is_exiting));
+    }
+}
+
+#[derive(Resource, Clone, Debug)]
+pub struct EvacuationSettings {
+    /// Agents to place. Fewer are placed if they don't fit.
+    pub agents: u32,
+    /// Simulated seconds after which the run is stopped even if agents remain,
+    /// e.g. when they are stuck.
+    pub max_time: f32,
+}
+
+/// Progress of the evacuation, written next to the statistics, e.g. to
+/// statistics-evacuation.json.
+#[derive(Resource, Default, Serialize)]
+struct EvacuationMetrics {
+    /// Agents that were placed, `None` before placing.
+    agents: Option<u32>,
+    /// Seconds from placing the agents until the last one left.
+    /// `None` if some agents didn't make it in time.
+    evacuation_time: Option<f32>,
+    /// How many agents left through each target.
+    exits: Vec<ExitUsage>,
+    /// Seconds since placing and agents that have left by then, for every tick.
+    cumulative_exits: Vec<(f32, u32)>,
+    #[serde(skip)]
+    exits_by_target: HashMap<Entity, u32>,
+    /// Seconds since placing, summed in f64 so long runs don't drift.
+    #[serde(skip)]
+    time: f64,
+}
+
+#[derive(Serialize)]
+struct ExitUsage {
+    position: Vec2,
+    agents: u32,
+}
+
+fn not_placed(metrics: Res<EvacuationMetrics>) -> bool {
+    metrics.agents.is_none()
+}
+
+fn placed(metrics: Res<EvacuationMetrics>) -> bool {
+    metrics.agents.is_some()
+}
+
+/// Waits for the flow field, so agents are placed only where they can reach a target.
+fn place_agents(
+    level: Res<Level>,
+    settings: Res<EvacuationSettings>,
+    agent_params: Res<AgentParamsDistribution>,
+    nav_grid: Res<NavGrid>,
+    flow_field: Res<FlowField>,
+    mut metrics: ResMut<EvacuationMetrics>,
+    mut commands: Commands,
+    mut rng: Local<FastRng>,
+    mut stats: ResMut<Statistics>,
+) {
+    if flow_field.is_empty() {
+        return;
+    }
+    let start = Instant::now();
+
+    let reachable = |pos: Vec2| {
+        let idx = nav_grid.pos_to_index(pos);
+        nav_grid.walkable.get(idx).copied().unwrap_or(false)
+            && flow_field.get(idx).is_some_and(|flow| *flow != Flow::None)
+    };
+    let bounds = Rect::new(0., 0., level.size, level.size);
+    let positions = placement::scatter(
+        bounds,
+        settings.agents as usize,
+        SPAWN_SPACING,
+        reachable,
+        &mut rng.0,
+    );
+    for &pos in &positions {
+        let params = agent_params.sample(&mut rng.0);
+        commands.spawn(EnemyBundle::new(pos, params, &mut rng.0));
+    }
+
+    let agents = positions.len() as u32;
+    if agents < settings.agents {
+        println!(
+            "Only {agents} of {} agents fit in the level",
+            settings.agents
+        );
+    }
+    metrics.agents = Some(agents);
+
+    stats.add("spawn", start.elapsed());
+}
+
+fn track_exits(
+    enemy_q: Query<(), With<Enemy>>,
+    tick: Res<SimulationTick>,
+    mut exits: EventReader<AgentExited>,
+    mut metrics: ResMut<EvacuationMetrics>,
+) {
+    for exit in exits.read() {
+        *metrics.exits_by_target.entry(exit.target).or_default() += 1;
+    }
+
+    metrics.time += tick.length as f64;
+    let time = metrics.time as f32;
+    let exited = metrics.exits_by_target.values().sum();
+    metrics.cumulative_exits.push((time, exited));
+
+    if enemy_q.is_empty() && metrics.evacuation_time.is_none() {
+        metrics.evacuation_time = Some(time);
+    }
+}
+
+fn exit_evacuation(
+    mut exit: ResMut<Events<AppExit>>,
+    settings: Res<EvacuationSettings>,
+    metrics: Res<EvacuationMetrics>,
+) {
+    if metrics.evacuation_time.is_some() || metrics.time >= settings.max_time as f64 {
+        exit.send(AppExit);
+    }
+}
+
+fn write_evacuation_metrics(
+    target_q: Query<(Entity, &Transform), With<Target>>,
+    statistics: Res<StatisticsSettings>,
+    mut metrics: ResMut<EvacuationMetrics>,
+) {
+    metrics.exits = target_q
+        .iter()
+        .map(|(entity, transform)| ExitUsage {
+            position: transform.translation.truncate(),
+            agents: metrics.exits_by_target.get(&entity).copied().unwrap_or(0),
+        })
+        .collect();
+
+    fs::write(
+        statistics.path_next_to_output("evacuation", "json"),
+        serde_json::to_string(&*metrics).unwrap(),
+    )
+    .unwrap();
+
+    let agents = metrics.agents.unwrap_or(0);
+    let (time, exited) = metrics.cumulative_exits.last().copied().unwrap_or_default();
+    match metrics.evacuation_time {
+        Some(time) => println!("Evacuated {agents} agents in {time:.2} s"),
+        None => println!(
+            "Evacuation stopped after {time:.2} s with {} of {agents} agents remaining",
+            agents - exited
+        ),
+    }
+    println!(
+        "Agents per exit: {}",
+        metrics.exits.iter().map(|exit| exit.agents).join(", ")
+    );
+}
+
+// Skipped with ORCA and social forces, which jam the last agents around the one cell
+// targets of an open level, so they don't all get out in time
+#[cfg(all(
+    test,
+    not(any(feature = "steering_orca", feature = "steering_social_force"))
+))]
+mod tests {
+    use bevy::time::TimePlugin;
+
+    use crate::{
+        level::LevelPlugin,
+        simulation::{spawning::SpawnSchedule, BoidsWeights, SimulationPlugin},
+        statistics::{StatisticsPlugin, StatisticsSettings},
+    };
+
+    use super::*;
+
+    #[test]
+    fn evacuates_through_both_targets() {
+        const AGENTS: u32 = 50;
+        let level = Level {
+            size: 30.,
+            spawn_points: Vec::new(),
+            // At the edges, so agents can't circle around the exits at full speed
+            targets: vec![Vec2::new(0.75, 15.25), Vec2::new(29.25, 15.25)],
+            walls: Vec::new(),
+            spawn_schedule: None,
+        };
+        let output = std::env::temp_dir().join("evacuation-test/statistics.json");
+        std::fs::create_dir_all(output.parent().unwrap()).unwrap();
+
+        let mut app = App::new();
+        app.add_plugins((
+            MinimalPlugins.build().disable::<TimePlugin>(),
+            SimulationPlugin {
+                update_nav: false,
+                density_nav: false,
+                hard_collisions: false,
+                depenetration: None,
+                boids_weights: BoidsWeights {
+                    separation: 1.,
+                    alignment: 0.1,
+                    cohesion: 0.5,
+                },
+                agent_params: AgentParamsDistribution::default(),
+                tick: SimulationTick {
+                    length: 1. / 60.,
+                    substeps: 1,
+                },
+                arrival: default(),
+                spawn_schedule: SpawnSchedule { waves: Vec::new() },
+                evacuation: Some(EvacuationSettings {
+                    agents: AGENTS,
+                    max_time: 60.,
+                }),
+                target_motions: default(),
+                player: None,
+                quality: false,
+            },
+            StatisticsPlugin {
+                settings: StatisticsSettings {
+                    output,
+                    warmup: 0,
+                    plot: None,
+                },
+            },
+            LevelPlugin,
+        ))
+        .insert_resource(level);
+
+        for _ in 0..60 * 60 {
+            app.update();
+            if !app.world.resource::<Events<AppExit>>().is_empty() {
+                break;
+            }
+        }
+
+        let metrics = app.world.resource::<EvacuationMetrics>();
+        assert_eq!(metrics.agents, Some(AGENTS));
+        assert!(metrics.evacuation_time.is_some());
+        assert_eq!(metrics.exits.len(), 2);
+        assert!(metrics.exits.iter().all(|exit| exit.agents > 0));
+        assert_eq!(
+            metrics.exits.iter().map(|exit| exit.agents).sum::<u32>(),
+            AGENTS
+        );
+        assert!(metrics
+            .cumulative_exits
+            .windows(2)
+            .all(|w| w[0].0 < w[1].0 && w[0].1 <= w[1].1));
+        assert_eq!(metrics.cumulative_exits.last().unwrap().1, AGENTS);
+    }
+}
@@ -17,25 +17,116 @@ cfg_if::cfg_if! {
         mod rstar;
         use rstar::{init, movement};
     } else {
-        mod array;
         use array::{init, movement};
     }
 }
 
-use super::{spawning::ENEMY_RADIUS, SimulationSet};
+// Also used by depenetration, so it's always compiled
+#[cfg_attr(
+    any(
+        feature = "spatial_hash",
+        feature = "spatial_hash_std",
+        feature = "spatial_kdtree",
+        feature = "spatial_kdtree_kiddo",
+        feature = "spatial_kdbush",
+        feature = "spatial_rstar"
+    ),
+    allow(dead_code)
+)]
+pub mod array;
+
+use crate::REFERENCE_DT;
+
+use super::{spawning::ENEMY_RADIUS, SimulationSet, SimulationStep};
 
 pub struct FlockingPlugin;
 
 impl Plugin for FlockingPlugin {
     fn build(&self, app: &mut App) {
         app.add_systems(Startup, init)
-            .add_systems(PreUpdate, movement.in_set(SimulationSet::Movement));
+            .add_systems(SimulationStep, movement.in_set(SimulationSet::Movement));
     }
 }
 
+/// Default for `AgentParams::preferred_spacing`
 #[cfg(not(feature = "distance_func2"))]
-const PREFERRED_DISTANCE: f32 = ENEMY_RADIUS * 2.;
+pub const PREFERRED_DISTANCE: f32 = ENEMY_RADIUS * 2.;
 #[cfg(feature = "distance_func2")]
-const PREFERRED_DISTANCE: f32 = ENEMY_RADIUS * 2.2;
+pub const PREFERRED_DISTANCE: f32 = ENEMY_RADIUS * 2.2;
+
+/// How far away neighbors are searched. Separation only affects neighbors closer than
+/// `PREFERRED_DISTANCE`, but alignment and cohesion need to see further.
+#[cfg(not(feature = "flocking_boids"))]
+const NEIGHBOR_RADIUS: f32 = PREFERRED_DISTANCE;
+#[cfg(feature = "flocking_boids")]
+const NEIGHBOR_RADIUS: f32 = PREFERRED_DISTANCE * 2.;
+
+/// Grid backends only see neighbors this close for sure.
+pub const MAX_PREFERRED_DISTANCE: f32 = NEIGHBOR_RADIUS;
 
+#[cfg(not(feature = "flocking_boids"))]
 const SAFETY_MARGIN: f32 = 0.000001;
+
+/// Separation is tuned as a displacement per `REFERENCE_DT`, this scales it to a step of `dt`.
+#[cfg(not(feature = "flocking_boids"))]
+fn scale_to_step(delta: Vec2, dt: f32) -> Vec2 {
+    delta * (dt / REFERENCE_DT)
+}
+
+/// Strengths of the boids steering terms, only used with the `flocking_boids` feature.
+#[derive(Resource, Clone, Copy, Debug)]
+#[cfg_attr(not(feature = "flocking_boids"), allow(dead_code))]
+pub struct BoidsWeights {
+    /// Keeps agents apart. 1 is the same as without `flocking_boids`.
+    pub separation: f32,
+    /// Fraction of the difference to the average velocity of neighbors removed per tick.
+    pub alignment: f32,
+    /// Acceleration towards the center of neighbors per unit of distance to it.
+    pub cohesion: f32,
+}
+
+/// Accumulates separation, alignment and cohesion from the neighbors of one agent.
+#[cfg(feature = "flocking_boids")]
+#[derive(Default)]
+struct Boids {
+    separation: Vec2,
+    separation_count: f32,
+    velocity_sum: Vec2,
+    position_sum: Vec2,
+    count: f32,
+}
+
+#[cfg(feature = "flocking_boids")]
+impl Boids {
+    /// Agents at the exact same position, like the agent itself, are ignored.
+    fn add(&mut self, pos: Vec2, pref_dist: f32, other_pos: Vec2, other_vel: Vec2) {
+        let pos_delta = pos - other_pos;
+        let distance = pos_delta.length();
+        if distance == 0. || distance > NEIGHBOR_RADIUS {
+            return;
+        }
+        if distance < pref_dist {
+            self.separation += (pref_dist - distance).powi(2) / distance * pos_delta;
+            self.separation_count += 1.;
+        }
+        self.velocity_sum += other_vel;
+        self.position_sum += other_pos;
+        self.count += 1.;
+    }
+
+    /// How much the agent should move during a step of `dt` seconds.
+    fn delta(&self, pos: Vec2, velocity: Vec2, weights: &BoidsWeights, dt: f32) -> Vec2 {
+        // The weights are tuned per `REFERENCE_DT`
+        let steps = dt / REFERENCE_DT;
+        let mut delta = Vec2::ZERO;
+        if self.separation_count > 0. {
+            delta += self.separation / self.separation_count * 2. * weights.separation * steps;
+        }
+        if self.count > 0. {
+            let alignment = (self.velocity_sum / self.count - velocity) * weights.alignment * steps;
+            let cohesion = (self.position_sum / self.count - pos) * weights.cohesion * dt;
+            delta += (alignment + cohesion) * dt;
+        }
+        delta
+    }
+}
@@ -4,16 +4,22 @@ use bevy::{ecs::system::SystemState, prelude::*, utils::Instant};
 use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 
 use crate::simulation::movement;
-use crate::{statistics::Statistics, utils::Velocity, DELTA_TIME};
+use crate::{simulation::SimulationTick, statistics::Statistics, utils::Velocity};
 
 use crate::simulation::{
-    navigation::{Flow, FlowField, NavGrid},
-    spawning::Enemy,
+    navigation::NavGrid,
+    spawning::{AgentParams, Enemy},
 };
 
 use crate::level::*;
 
-use super::{PREFERRED_DISTANCE, SAFETY_MARGIN};
+#[cfg(not(feature = "flocking_boids"))]
+use super::scale_to_step;
+use super::NEIGHBOR_RADIUS;
+#[cfg(all(not(feature = "flocking_boids"), feature = "branchless"))]
+use super::SAFETY_MARGIN;
+#[cfg(feature = "flocking_boids")]
+use super::{Boids, BoidsWeights};
 
 pub fn init(level: Res<Level>, mut commands: Commands) {
     println!("USING: spatial array");
@@ -25,33 +31,35 @@ pub fn movement(world: &mut World) {
 
     movement::move_with_flow_field(world);
 
+    #[cfg(feature = "flocking_boids")]
+    let weights = *world.resource::<BoidsWeights>();
+
+    let dt = world.resource::<SimulationTick>().dt();
+
     let mut system_state: SystemState<(
-        Query<(Entity, &mut Transform, &mut Velocity), With<Enemy>>,
+        Query<(Entity, &mut Transform, &mut Velocity, &AgentParams), With<Enemy>>,
         Res<NavGrid>,
-        Res<FlowField>,
         ResMut<SpatialStructure>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
-    let (enemy_q, nav_grid, flow_field, mut spatial, mut stats) = system_state.get_mut(world);
+    let (enemy_q, nav_grid, mut spatial, mut stats) = system_state.get_mut(world);
 
     spatial.reset();
 
     cfg_if::cfg_if! {
-        if #[cfg(feature = "flocking_alignment")] {
+        if #[cfg(feature = "flocking_boids")] {
             enemy_q
                 .iter()
-                .for_each(|(entity, tr, vel)| spatial.insert((entity, tr.translation.truncate(), vel.0.normalize_or_zero())));
+                .for_each(|(entity, tr, vel, _)| spatial.insert((entity, tr.translation.truncate(), vel.0)));
         } else {
             enemy_q
                 .iter()
-                .for_each(|(entity, tr, _)| spatial.insert((entity, tr.translation.truncate())));
+                .for_each(|(entity, tr, _, _)| spatial.insert((entity, tr.translation.truncate())));
         }
     }
 
     stats.add("insert", start.elapsed());
 
-    let pref_dist = PREFERRED_DISTANCE;
-
     #[cfg(not(feature = "parallel"))]
     let iter = spatial.grid.iter();
     #[cfg(feature = "parallel")]
@@ -64,13 +72,25 @@ pub fn movement(world: &mut World) {
                 return;
             };
             for &(entity, pos, _) in items {
-                let Ok((_, mut translation, mut velocity)) =
+                let Ok((_, mut translation, mut velocity, params)) =
                     (unsafe { enemy_q.get_unchecked(entity) })
                 else {
                     continue;
                 };
+                let pref_dist = params.preferred_spacing;
+
+                #[cfg(feature = "flocking_boids")]
+                let total_delta = {
+                    let mut boids = Boids::default();
+                    for &(other_entity, other_pos, other_vel) in neighbors.iter().flat_map(|v| v.iter()) {
+                        if other_entity != entity {
+                            boids.add(pos, pref_dist, other_pos, other_vel);
+                        }
+                    }
+                    boids.delta(pos, velocity.0, &weights, dt)
+                };
 
-                #[cfg(not(feature = "distance_func2"))]
+                #[cfg(all(not(feature = "flocking_boids"), not(feature = "distance_func2")))]
                 let total_delta = {
                     let mut total_force = Vec2::ZERO;
                     let mut valid_neighbors = 0;
@@ -91,30 +111,10 @@ pub fn movement(world: &mut World) {
                     total_force
                 };
 
-                #[cfg(feature = "distance_func2")]
+                #[cfg(all(not(feature = "flocking_boids"), feature = "distance_func2"))]
                 let total_delta = {
                     cfg_if::cfg_if!{
-                        if #[cfg(all(feature = "branchless", feature = "floatneighbors", feature = "flocking_alignment"))] {
-                            let (valid_neighbors, mut total_delta, total_dir) = neighbors
-                                .iter()
-                                .flat_map(|v| v.iter())
-                                .map(|&(other_entity, other_pos, dir)| {
-                                    let pos_delta = pos - other_pos;
-                                    let distance = pos_delta.length();
-                                    let magnitude = (pref_dist - distance).powi(2);
-                                    let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
-                                    let force = magnitude * direction;
-                                    let valid =
-                                        f32::from(other_entity != entity && distance < pref_dist);
-                                    (valid, valid * force, valid * dir, valid * distance)
-                                })
-                                .fold((0., Vec2::ZERO, Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1, acc.2 + x.2));
-                            total_delta /= valid_neighbors;
-                            if let Some(dir) = total_dir.try_normalize() {
-                                total_delta = dir * 0.4 * total_delta.length() + total_delta * 0.6;
-                            }
-                            total_delta * 2.
-                        } else if #[cfg(all(feature = "branchless", feature = "floatneighbors"))] {
+                        if #[cfg(all(feature = "branchless", feature = "floatneighbors"))] {
                             let (valid_neighbors, mut total_delta) = neighbors
                                 .iter()
                                 .flat_map(|v| v.iter())
@@ -170,33 +170,38 @@ pub fn movement(world: &mut World) {
                     }
                 };
 
-                if let Some(flow) = flow_field.get(nav_grid.pos_to_index(pos + total_delta)) {
-                    if *flow != Flow::None {
-                        translation.translation.x += total_delta.x;
-                        translation.translation.y += total_delta.y;
-                        velocity.0 += total_delta / DELTA_TIME;
-                    }
-                }
+                #[cfg(not(feature = "flocking_boids"))]
+                let total_delta = scale_to_step(total_delta, dt);
+
+                let moved = nav_grid.slide(pos, total_delta);
+                translation.translation.x += moved.x;
+                translation.translation.y += moved.y;
+                velocity.0 += moved / dt;
             }
         });
 
     stats.add("movement", start.elapsed());
 }
 
-const SPATIAL_CELL_SIZE: f32 = PREFERRED_DISTANCE;
+const SPATIAL_CELL_SIZE: f32 = NEIGHBOR_RADIUS;
 const SPATIAL_CELL_SIZE_INV: f32 = 1.0 / SPATIAL_CELL_SIZE;
 
-#[derive(Debug, Clone, Default, Resource)]
+#[derive(Debug, Clone, Resource)]
 pub struct SpatialStructure {
     level_size: f32,
     size: usize,
-    #[cfg(not(feature = "flocking_alignment"))]
+    /// Agents past this many in one cell are left out.
+    cell_capacity: usize,
+    #[cfg(not(feature = "flocking_boids"))]
     pub grid: Vec<Vec<(Entity, Vec2, ())>>,
-    #[cfg(feature = "flocking_alignment")]
+    /// Also contains velocities
+    #[cfg(feature = "flocking_boids")]
     pub grid: Vec<Vec<(Entity, Vec2, Vec2)>>,
 }
 
 const DEFAULT_CELL_CAPACITY: usize = 16;
+/// Steering doesn't need to see every agent in a jammed cell, and skipping them keeps it fast.
+const MAX_CELL_ITEMS: usize = 100;
 
 impl SpatialStructure {
     pub fn new(level_size: f32) -> Self {
@@ -204,20 +209,37 @@ impl SpatialStructure {
         Self {
             level_size,
             size,
+            cell_capacity: MAX_CELL_ITEMS,
             grid: vec![Vec::with_capacity(DEFAULT_CELL_CAPACITY); size * size],
         }
     }
 
+    /// Keeps every agent, for when all of them have to be seen, like in depenetration.
+    pub fn uncapped(level_size: f32) -> Self {
+        Self {
+            cell_capacity: usize::MAX,
+            ..Self::new(level_size)
+        }
+    }
+
     pub fn reset(&mut self) {
         self.grid.iter_mut().for_each(|a| a.clear());
     }
 
+    /// Inserts an agent without a velocity, for everything but boids steering.
+    pub fn insert_position(&mut self, entity: Entity, pos: Vec2) {
+        #[cfg(not(feature = "flocking_boids"))]
+        self.insert((entity, pos));
+        #[cfg(feature = "flocking_boids")]
+        self.insert((entity, pos, Vec2::ZERO));
+    }
+
     cfg_if::cfg_if! {
-        if #[cfg(not(feature = "flocking_alignment"))] {
+        if #[cfg(not(feature = "flocking_boids"))] {
             pub fn insert(&mut self, (entity, pos): (Entity, Vec2)) {
                 let cell = self.pos_to_cell(pos);
                 let a = unsafe { self.grid.get_unchecked_mut(cell) };
-                if a.len() < 100 {
+                if a.len() < self.cell_capacity {
                     a.push((entity, pos, ()));
                 }
             }
@@ -243,11 +265,11 @@ impl SpatialStructure {
                 }
             }
         } else {
-            pub fn insert(&mut self, (entity, pos, dir): (Entity, Vec2, Vec2)) {
+            pub fn insert(&mut self, (entity, pos, vel): (Entity, Vec2, Vec2)) {
                 let cell = self.pos_to_cell(pos);
                 let a = unsafe { self.grid.get_unchecked_mut(cell) };
-                if a.len() < 100 {
-                    a.push((entity, pos, dir));
+                if a.len() < self.cell_capacity {
+                    a.push((entity, pos, vel));
                 }
             }
 
@@ -281,4 +303,13 @@ impl SpatialStructure {
             + 1
             + ((pos.y * SPATIAL_CELL_SIZE_INV) as usize + 1) * self.size
     }
+
+    /// Indices of the cells at most `rings` cells away from the cell of `pos` in any direction.
+    pub fn cells_around(&self, pos: Vec2, rings: usize) -> impl Iterator<Item = usize> + '_ {
+        let cell = self.pos_to_cell(pos);
+        let (x, y) = (cell % self.size, cell / self.size);
+        let xs = x.saturating_sub(rings)..=(x + rings).min(self.size - 1);
+        let ys = y.saturating_sub(rings)..=(y + rings).min(self.size - 1);
+        ys.flat_map(move |y| xs.clone().map(move |x| x + y * self.size))
+    }
 }
@@ -6,16 +6,20 @@ use bevy::utils::HashMap;
 use std::collections::HashMap;
 
 use crate::simulation::movement;
-use crate::{statistics::Statistics, utils::Velocity, DELTA_TIME};
+use crate::{simulation::SimulationTick, statistics::Statistics, utils::Velocity};
 
 use crate::simulation::{
-    navigation::{Flow, FlowField, NavGrid},
-    spawning::Enemy,
+    navigation::NavGrid,
+    spawning::{AgentParams, Enemy},
 };
 
 use crate::level::*;
 
-use super::{PREFERRED_DISTANCE, SAFETY_MARGIN};
+use super::NEIGHBOR_RADIUS;
+#[cfg(not(feature = "flocking_boids"))]
+use super::{scale_to_step, SAFETY_MARGIN};
+#[cfg(feature = "flocking_boids")]
+use super::{Boids, BoidsWeights};
 
 pub fn init(level: Res<Level>, mut commands: Commands) {
     println!("USING: spatial hash");
@@ -27,67 +31,100 @@ pub fn movement(world: &mut World) {
 
     movement::move_with_flow_field(world);
 
+    #[cfg(feature = "flocking_boids")]
+    let weights = *world.resource::<BoidsWeights>();
+
+    let dt = world.resource::<SimulationTick>().dt();
+
     let mut system_state: SystemState<(
-        Query<(Entity, &mut Transform, &mut Velocity), With<Enemy>>,
+        Query<(Entity, &mut Transform, &mut Velocity, &AgentParams), With<Enemy>>,
         Res<NavGrid>,
-        Res<FlowField>,
         ResMut<SpatialStructure>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
-    let (mut enemy_q, nav_grid, flow_field, mut spatial, mut stats) = system_state.get_mut(world);
+    let (mut enemy_q, nav_grid, mut spatial, mut stats) = system_state.get_mut(world);
 
     spatial.reset();
 
+    #[cfg(not(feature = "flocking_boids"))]
     enemy_q
         .iter()
-        .for_each(|(entity, tr, _)| spatial.insert((entity, tr.translation.truncate())));
-
-    let pref_dist = PREFERRED_DISTANCE;
+        .for_each(|(entity, tr, _, _)| spatial.insert((entity, tr.translation.truncate(), ())));
+    #[cfg(feature = "flocking_boids")]
+    enemy_q.iter().for_each(|(entity, tr, vel, _)| {
+        spatial.insert((entity, tr.translation.truncate(), vel.0))
+    });
 
     spatial.grid.iter().for_each(|(cell, items)| {
         let neighbors = spatial.neighbors(*cell);
 
-        for &(entity, pos) in items {
-            let Ok((_, mut translation, mut velocity)) = enemy_q.get_mut(entity) else {
+        for &(entity, pos, _) in items {
+            let Ok((_, mut translation, mut velocity, params)) = enemy_q.get_mut(entity) else {
                 continue;
             };
-
-            let (valid_neighbors, mut total_delta) = neighbors
-                .iter()
-                .flatten()
-                .flat_map(|v| v.iter())
-                .map(|&(other_entity, other_pos)| {
-                    let pos_delta = pos - other_pos;
-                    let distance = pos_delta.length();
-                    let magnitude = (pref_dist - distance).powi(2);
-                    let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
-                    let force = magnitude * direction;
-                    let valid = f32::from(other_entity != entity && distance < pref_dist);
-                    (valid, valid * force)
-                })
-                .fold((0., Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1));
-            total_delta /= valid_neighbors;
-            total_delta *= 2.;
-
-            if let Some(flow) = flow_field.get(nav_grid.pos_to_index(pos + total_delta)) {
-                if *flow != Flow::None {
-                    translation.translation.x += total_delta.x;
-                    translation.translation.y += total_delta.y;
-                    velocity.0 += total_delta / DELTA_TIME;
+            let pref_dist = params.preferred_spacing;
+
+            #[cfg(feature = "flocking_boids")]
+            let total_delta = {
+                let mut boids = Boids::default();
+                for &(other_entity, other_pos, other_vel) in neighbors
+                    .iter()
+                    .flatten()
+                    .flat_map(|v| v.iter())
+                    .chain(items)
+                {
+                    if other_entity != entity {
+                        boids.add(pos, pref_dist, other_pos, other_vel);
+                    }
                 }
-            }
+                boids.delta(pos, velocity.0, &weights, dt)
+            };
+
+            #[cfg(not(feature = "flocking_boids"))]
+            let total_delta = {
+                let (valid_neighbors, mut total_delta) = neighbors
+                    .iter()
+                    .flatten()
+                    .flat_map(|v| v.iter())
+                    .map(|&(other_entity, other_pos, _)| {
+                        let pos_delta = pos - other_pos;
+                        let distance = pos_delta.length();
+                        let magnitude = (pref_dist - distance).powi(2);
+                        let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
+                        let force = magnitude * direction;
+                        let valid = f32::from(other_entity != entity && distance < pref_dist);
+                        (valid, valid * force)
+                    })
+                    .fold((0., Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+                total_delta /= valid_neighbors;
+                total_delta * 2.
+            };
+
+            #[cfg(not(feature = "flocking_boids"))]
+            let total_delta = scale_to_step(total_delta, dt);
+
+            let moved = nav_grid.slide(pos, total_delta);
+            translation.translation.x += moved.x;
+            translation.translation.y += moved.y;
+            velocity.0 += moved / dt;
         }
     });
     stats.add("movement", start.elapsed());
 }
 
-const SPATIAL_CELL_SIZE: f32 = PREFERRED_DISTANCE;
+const SPATIAL_CELL_SIZE: f32 = NEIGHBOR_RADIUS;
 const SPATIAL_CELL_SIZE_INV: f32 = 1.0 / SPATIAL_CELL_SIZE;
 
+#[cfg(not(feature = "flocking_boids"))]
+type Item = (Entity, Vec2, ());
+/// Also contains the velocity
+#[cfg(feature = "flocking_boids")]
+type Item = (Entity, Vec2, Vec2);
+
 #[derive(Debug, Clone, Default, Resource)]
 pub struct SpatialStructure {
     level_size: f32,
-    pub grid: HashMap<(i32, i32), Vec<(Entity, Vec2)>>,
+    pub grid: HashMap<(i32, i32), Vec<Item>>,
 }
 
 const DEFAULT_CELL_CAPACITY: usize = 16;
@@ -104,19 +141,19 @@ impl SpatialStructure {
         self.grid.clear();
     }
 
-    pub fn insert(&mut self, (entity, pos): (Entity, Vec2)) {
-        let cell = self.pos_to_cell(pos);
+    pub fn insert(&mut self, item: Item) {
+        let cell = self.pos_to_cell(item.1);
         let list = self
             .grid
             .entry(cell)
             .or_insert_with(|| Vec::with_capacity(DEFAULT_CELL_CAPACITY));
 
         if list.len() < 100 {
-            list.push((entity, pos));
+            list.push(item);
         }
     }
 
-    pub fn neighbors(&self, cell: (i32, i32)) -> [Option<&Vec<(Entity, Vec2)>>; 8] {
+    pub fn neighbors(&self, cell: (i32, i32)) -> [Option<&Vec<Item>>; 8] {
         let (x, y) = cell;
         [
             self.grid.get(&(x - 1, y + 1)),
@@ -2,14 +2,17 @@ use bevy::{ecs::system::SystemState, prelude::*, utils::Instant};
 
 use crate::simulation::movement;
 use crate::simulation::spawning::MAX_ENEMIES;
-use crate::{statistics::Statistics, utils::Velocity, DELTA_TIME};
+use crate::{simulation::SimulationTick, statistics::Statistics, utils::Velocity};
 
 use crate::simulation::{
-    navigation::{Flow, FlowField, NavGrid},
-    spawning::Enemy,
+    navigation::NavGrid,
+    spawning::{AgentParams, Enemy},
 };
 
-use super::{PREFERRED_DISTANCE, SAFETY_MARGIN};
+#[cfg(not(feature = "flocking_boids"))]
+use super::{scale_to_step, SAFETY_MARGIN};
+#[cfg(feature = "flocking_boids")]
+use super::{Boids, BoidsWeights, NEIGHBOR_RADIUS};
 
 pub fn init(mut commands: Commands) {
     println!("USING: kdbush");
@@ -21,57 +24,76 @@ pub fn movement(world: &mut World) {
 
     movement::move_with_flow_field(world);
 
+    #[cfg(feature = "flocking_boids")]
+    let weights = *world.resource::<BoidsWeights>();
+
+    let dt = world.resource::<SimulationTick>().dt();
+
     let mut system_state: SystemState<(
-        Query<(&mut Transform, &mut Velocity), With<Enemy>>,
+        Query<(&mut Transform, &mut Velocity, &AgentParams), With<Enemy>>,
         Res<NavGrid>,
-        Res<FlowField>,
         ResMut<SpatialStructure>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
-    let (mut enemy_q, nav_grid, flow_field, mut spatial, mut stats) = system_state.get_mut(world);
+    let (mut enemy_q, nav_grid, mut spatial, mut stats) = system_state.get_mut(world);
 
     spatial.tree = KDBush::new(MAX_ENEMIES as usize, 32);
     let positions = enemy_q
         .iter()
         .enumerate()
-        .map(|(i, (tr, _))| {
+        .map(|(i, (tr, _, _))| {
             let pos = tr.translation.truncate();
             spatial.tree.add_point(i, pos.x as f64, pos.y as f64);
             pos
         })
         .collect::<Vec<_>>();
     spatial.tree.build_index();
+    #[cfg(feature = "flocking_boids")]
+    let velocities = enemy_q.iter().map(|(_, vel, _)| vel.0).collect::<Vec<_>>();
 
-    let pref_dist = PREFERRED_DISTANCE;
-
-    for (mut translation, mut velocity) in enemy_q.iter_mut() {
+    for (mut translation, mut velocity, params) in enemy_q.iter_mut() {
         let pos = translation.translation.truncate();
-
-        let mut valid_neighbors = 0.;
-        let mut total_delta = Vec2::ZERO;
-        spatial
-            .tree
-            .within(pos.x as f64, pos.y as f64, pref_dist as f64, |id| {
-                let other_pos = positions[id];
-                let pos_delta = pos - other_pos;
-                let distance = pos_delta.length();
-                let magnitude = (pref_dist - distance).powi(2);
-                let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
-                let force = magnitude * direction;
-                let valid = f32::from(distance != 0.);
-                valid_neighbors += valid;
-                total_delta += valid * force;
-            });
-        total_delta /= valid_neighbors;
-        total_delta *= 2.;
-
-        if let Some(flow) = flow_field.get(nav_grid.pos_to_index(pos + total_delta)) {
-            if *flow != Flow::None {
-                translation.translation.x += total_delta.x;
-                translation.translation.y += total_delta.y;
-                velocity.0 += total_delta / DELTA_TIME;
-            }
-        }
+        let pref_dist = params.preferred_spacing;
+
+        #[cfg(feature = "flocking_boids")]
+        let total_delta = {
+            let mut boids = Boids::default();
+            spatial
+                .tree
+                .within(pos.x as f64, pos.y as f64, NEIGHBOR_RADIUS as f64, |id| {
+                    boids.add(pos, pref_dist, positions[id], velocities[id])
+                });
+            boids.delta(pos, velocity.0, &weights, dt)
+        };
+
+        #[cfg(not(feature = "flocking_boids"))]
+        let total_delta = {
+            let mut valid_neighbors = 0.;
+            let mut total_delta = Vec2::ZERO;
+            spatial
+                .tree
+                .within(pos.x as f64, pos.y as f64, pref_dist as f64, |id| {
+                    let other_pos = positions[id];
+                    let pos_delta = pos - other_pos;
+                    let distance = pos_delta.length();
+                    let magnitude = (pref_dist - distance).powi(2);
+                    let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
+                    let force = magnitude * direction;
+                    let valid = f32::from(distance != 0.);
+                    valid_neighbors += valid;
+                    total_delta += valid * force;
+                });
+            total_delta /= valid_neighbors;
+            total_delta * 2.
+        };
+
+        #[cfg(not(feature = "flocking_boids"))]
+        let total_delta = scale_to_step(total_delta, dt);
+
+        let moved = nav_grid.slide(pos, total_delta);
+        translation.translation.x += moved.x;
+        translation.translation.y += moved.y;
+        velocity.0 += moved / dt;
     }
 
     stats.add("movement", start.elapsed());
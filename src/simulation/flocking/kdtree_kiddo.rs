@@ -3,14 +3,17 @@ use itertools::Itertools;
 use kiddo::float::{distance::SquaredEuclidean, kdtree::KdTree};
 
 use crate::simulation::movement;
-use crate::{statistics::Statistics, utils::Velocity, DELTA_TIME};
+use crate::{simulation::SimulationTick, statistics::Statistics, utils::Velocity};
 
 use crate::simulation::{
-    navigation::{Flow, FlowField, NavGrid},
-    spawning::Enemy,
+    navigation::NavGrid,
+    spawning::{AgentParams, Enemy},
 };
 
-use super::{PREFERRED_DISTANCE, SAFETY_MARGIN};
+#[cfg(not(feature = "flocking_boids"))]
+use super::{scale_to_step, SAFETY_MARGIN};
+#[cfg(feature = "flocking_boids")]
+use super::{Boids, BoidsWeights, NEIGHBOR_RADIUS};
 
 pub fn init(mut commands: Commands) {
     println!("USING: kiddo kdtree");
@@ -22,59 +25,80 @@ pub fn movement(world: &mut World) {
 
     movement::move_with_flow_field(world);
 
+    #[cfg(feature = "flocking_boids")]
+    let weights = *world.resource::<BoidsWeights>();
+
+    let dt = world.resource::<SimulationTick>().dt();
+
     let mut system_state: SystemState<(
-        Query<(&mut Transform, &mut Velocity), With<Enemy>>,
+        Query<(&mut Transform, &mut Velocity, &AgentParams), With<Enemy>>,
         Res<NavGrid>,
-        Res<FlowField>,
         ResMut<SpatialStructure>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
-    let (mut enemy_q, nav_grid, flow_field, mut spatial, mut stats) = system_state.get_mut(world);
+    let (mut enemy_q, nav_grid, mut spatial, mut stats) = system_state.get_mut(world);
 
     spatial.tree = KdTree::with_capacity(enemy_q.iter().len());
     let positions = enemy_q
         .iter()
         .enumerate()
-        .map(|(i, (tr, _))| {
+        .map(|(i, (tr, _, _))| {
             let pos = tr.translation.truncate();
             spatial.tree.add(&pos.to_array(), i);
             pos
         })
         .collect_vec();
+    #[cfg(feature = "flocking_boids")]
+    let velocities = enemy_q.iter().map(|(_, vel, _)| vel.0).collect_vec();
 
     let insert_elapsed = start.elapsed();
 
-    let pref_dist = PREFERRED_DISTANCE;
-
-    for (mut translation, mut velocity) in enemy_q.iter_mut() {
+    for (mut translation, mut velocity, params) in enemy_q.iter_mut() {
         let pos = translation.translation.truncate();
-
-        let (valid_neighbors, mut total_delta) = spatial
-            .tree
-            .within_unsorted_iter::<SquaredEuclidean>(&pos.to_array(), pref_dist)
-            .map(|n| {
-                let other_pos = positions[n.item];
-                let pos_delta = pos - other_pos;
-                let distance = n.distance.sqrt();
-                let distance_recip = (distance + SAFETY_MARGIN).recip();
-                let valid = i32::from(pos_delta != Vec2::ZERO);
-                (
-                    valid,
-                    valid as f32 * pos_delta * (distance_recip * (pref_dist - distance)),
-                )
-            })
-            .fold((0, Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1));
-
-        let jitter_remove_add = 3;
-        total_delta /= (valid_neighbors + jitter_remove_add) as f32 * 0.5;
-
-        if let Some(flow) = flow_field.get(nav_grid.pos_to_index(pos + total_delta)) {
-            if *flow != Flow::None {
-                translation.translation.x += total_delta.x;
-                translation.translation.y += total_delta.y;
-                velocity.0 += total_delta / DELTA_TIME;
+        let pref_dist = params.preferred_spacing;
+
+        #[cfg(feature = "flocking_boids")]
+        let total_delta = {
+            let mut boids = Boids::default();
+            for n in spatial
+                .tree
+                .within_unsorted_iter::<SquaredEuclidean>(&pos.to_array(), NEIGHBOR_RADIUS.powi(2))
+            {
+                boids.add(pos, pref_dist, positions[n.item], velocities[n.item]);
             }
-        }
+            boids.delta(pos, velocity.0, &weights, dt)
+        };
+
+        #[cfg(not(feature = "flocking_boids"))]
+        let total_delta = {
+            let (valid_neighbors, mut total_delta) = spatial
+                .tree
+                .within_unsorted_iter::<SquaredEuclidean>(&pos.to_array(), pref_dist)
+                .map(|n| {
+                    let other_pos = positions[n.item];
+                    let pos_delta = pos - other_pos;
+                    let distance = n.distance.sqrt();
+                    let distance_recip = (distance + SAFETY_MARGIN).recip();
+                    let valid = i32::from(pos_delta != Vec2::ZERO);
+                    (
+                        valid,
+                        valid as f32 * pos_delta * (distance_recip * (pref_dist - distance)),
+                    )
+                })
+                .fold((0, Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+
+            let jitter_remove_add = 3;
+            total_delta /= (valid_neighbors + jitter_remove_add) as f32 * 0.5;
+            total_delta
+        };
+
+        #[cfg(not(feature = "flocking_boids"))]
+        let total_delta = scale_to_step(total_delta, dt);
+
+        let moved = nav_grid.slide(pos, total_delta);
+        translation.translation.x += moved.x;
+        translation.translation.y += moved.y;
+        velocity.0 += moved / dt;
     }
 
     stats.add("movement", start.elapsed() - insert_elapsed);
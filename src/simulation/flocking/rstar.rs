@@ -1,14 +1,17 @@
 use bevy::{ecs::system::SystemState, prelude::*, utils::Instant};
 
 use crate::simulation::movement;
-use crate::{statistics::Statistics, utils::Velocity, DELTA_TIME};
+use crate::{simulation::SimulationTick, statistics::Statistics, utils::Velocity};
 
 use crate::simulation::{
-    navigation::{Flow, FlowField, NavGrid},
-    spawning::Enemy,
+    navigation::NavGrid,
+    spawning::{AgentParams, Enemy},
 };
 
-use super::{PREFERRED_DISTANCE, SAFETY_MARGIN};
+#[cfg(not(feature = "flocking_boids"))]
+use super::{scale_to_step, SAFETY_MARGIN};
+#[cfg(feature = "flocking_boids")]
+use super::{Boids, BoidsWeights, NEIGHBOR_RADIUS};
 
 pub fn init(mut commands: Commands) {
     info!("USING: rstar");
@@ -20,60 +23,90 @@ pub fn movement(world: &mut World) {
 
     movement::move_with_flow_field(world);
 
+    #[cfg(feature = "flocking_boids")]
+    let weights = *world.resource::<BoidsWeights>();
+
+    let dt = world.resource::<SimulationTick>().dt();
+
     let mut system_state: SystemState<(
-        Query<(Entity, &mut Transform, &mut Velocity), With<Enemy>>,
+        Query<(Entity, &mut Transform, &mut Velocity, &AgentParams), With<Enemy>>,
         Res<NavGrid>,
-        Res<FlowField>,
         ResMut<SpatialStructure>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
-    let (mut enemy_q, nav_grid, flow_field, mut spatial, mut stats) = system_state.get_mut(world);
+    let (mut enemy_q, nav_grid, mut spatial, mut stats) = system_state.get_mut(world);
 
+    #[cfg(not(feature = "flocking_boids"))]
     let positions = enemy_q
         .iter()
-        .map(|(_, tr, _)| tr.translation.truncate().to_array())
+        .map(|(_, tr, _, _)| tr.translation.truncate().to_array())
+        .collect::<Vec<_>>();
+    #[cfg(feature = "flocking_boids")]
+    let positions = enemy_q
+        .iter()
+        .map(|(_, tr, vel, _)| GeomWithData::new(tr.translation.truncate().to_array(), vel.0))
         .collect::<Vec<_>>();
     spatial.tree = RTree::bulk_load(positions);
 
-    let pref_dist = PREFERRED_DISTANCE;
-
-    for (_, mut translation, mut velocity) in enemy_q.iter_mut() {
+    for (_, mut translation, mut velocity, params) in enemy_q.iter_mut() {
         let pos = translation.translation.truncate();
-
-        let (valid_neighbors, mut total_delta) = spatial
-            .tree
-            .locate_within_distance(pos.to_array(), pref_dist.powi(2))
-            .map(|other_pos| {
-                let pos_delta = pos - Vec2::from(*other_pos);
-                let distance = pos_delta.length();
-                let magnitude = (pref_dist - distance).powi(2);
-                let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
-                let force = magnitude * direction;
-                let valid = f32::from(distance != 0.);
-                (valid, valid * force)
-            })
-            .fold((0., Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1));
-
-        total_delta /= valid_neighbors;
-        total_delta *= 2.;
-
-        if let Some(flow) = flow_field.get(nav_grid.pos_to_index(pos + total_delta)) {
-            if *flow != Flow::None {
-                translation.translation.x += total_delta.x;
-                translation.translation.y += total_delta.y;
-                velocity.0 += total_delta / DELTA_TIME;
+        let pref_dist = params.preferred_spacing;
+
+        #[cfg(feature = "flocking_boids")]
+        let total_delta = {
+            let mut boids = Boids::default();
+            for other in spatial
+                .tree
+                .locate_within_distance(pos.to_array(), NEIGHBOR_RADIUS.powi(2))
+            {
+                boids.add(pos, pref_dist, Vec2::from(*other.geom()), other.data);
             }
-        }
+            boids.delta(pos, velocity.0, &weights, dt)
+        };
+
+        #[cfg(not(feature = "flocking_boids"))]
+        let total_delta = {
+            let (valid_neighbors, mut total_delta) = spatial
+                .tree
+                .locate_within_distance(pos.to_array(), pref_dist.powi(2))
+                .map(|other_pos| {
+                    let pos_delta = pos - Vec2::from(*other_pos);
+                    let distance = pos_delta.length();
+                    let magnitude = (pref_dist - distance).powi(2);
+                    let direction = 1. / (distance + SAFETY_MARGIN) * pos_delta;
+                    let force = magnitude * direction;
+                    let valid = f32::from(distance != 0.);
+                    (valid, valid * force)
+                })
+                .fold((0., Vec2::ZERO), |acc, x| (acc.0 + x.0, acc.1 + x.1));
+
+            total_delta /= valid_neighbors;
+            total_delta * 2.
+        };
+
+        #[cfg(not(feature = "flocking_boids"))]
+        let total_delta = scale_to_step(total_delta, dt);
+
+        let moved = nav_grid.slide(pos, total_delta);
+        translation.translation.x += moved.x;
+        translation.translation.y += moved.y;
+        velocity.0 += moved / dt;
     }
 
     stats.add("movement", start.elapsed());
 }
 
+#[cfg(feature = "flocking_boids")]
+use rstar::primitives::GeomWithData;
 use rstar::RTree;
 
 #[derive(Debug, Clone, Resource)]
 pub struct SpatialStructure {
+    #[cfg(not(feature = "flocking_boids"))]
     tree: RTree<[f32; 2]>,
+    /// Also contains velocities
+    #[cfg(feature = "flocking_boids")]
+    tree: RTree<GeomWithData<[f32; 2], Vec2>>,
 }
 
 impl SpatialStructure {
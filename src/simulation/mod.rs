@@ -1,11 +1,49 @@
-use bevy::prelude::*;
+use bevy::{ecs::schedule::ScheduleLabel, prelude::*};
 
-use self::{flocking::FlockingPlugin, navigation::NavigationPlugin, spawning::SpawningPlugin};
+use self::{
+    arrival::{Arrival, ArrivalPlugin},
+    collision::CollisionPlugin,
+    crowd_metrics::CrowdMetricsPlugin,
+    depenetration::{DepenetrationPlugin, DepenetrationSettings},
+    evacuation::{EvacuationPlugin, EvacuationSettings},
+    navigation::NavigationPlugin,
+    player::{PlayerControl, PlayerPlugin},
+    quality::QualityPlugin,
+    spawning::{AgentParamsDistribution, SpawnSchedule, SpawningPlugin},
+    target_motion::{TargetMotionPlugin, TargetMotions},
+};
 
+pub mod arrival;
 mod collision;
+mod crowd_metrics;
+pub mod depenetration;
+pub mod evacuation;
+// The spatial array of flocking is shared with the other steering modes
+#[cfg_attr(
+    any(feature = "steering_orca", feature = "steering_social_force"),
+    allow(dead_code)
+)]
 mod flocking;
+#[cfg_attr(
+    any(feature = "steering_orca", feature = "steering_social_force"),
+    allow(dead_code)
+)]
 mod movement;
 
+pub use flocking::BoidsWeights;
+
+cfg_if::cfg_if! {
+    if #[cfg(feature = "steering_orca")] {
+        mod orca;
+        use orca::OrcaPlugin as SteeringPlugin;
+    } else if #[cfg(feature = "steering_social_force")] {
+        mod social_force;
+        use social_force::SocialForcePlugin as SteeringPlugin;
+    } else {
+        use flocking::FlockingPlugin as SteeringPlugin;
+    }
+}
+
 // #[cfg(navigation1)]
 pub mod navigation;
 // #[cfg(navigation2)]
@@ -15,45 +53,318 @@ pub mod navigation;
 //     pub use super::navigation2::*;
 // }
 
+pub mod player;
+mod quality;
 mod rng;
 pub mod spawning;
+pub mod target_motion;
 
 pub struct SimulationPlugin {
     pub update_nav: bool,
+    pub density_nav: bool,
+    pub hard_collisions: bool,
+    pub depenetration: Option<DepenetrationSettings>,
+    pub boids_weights: BoidsWeights,
+    pub agent_params: AgentParamsDistribution,
+    pub tick: SimulationTick,
+    pub arrival: Arrival,
+    pub spawn_schedule: SpawnSchedule,
+    /// Place agents everywhere at the start instead of following the spawn schedule.
+    pub evacuation: Option<EvacuationSettings>,
+    pub target_motions: TargetMotions,
+    /// Add a player that the agents chase instead of going to the targets.
+    pub player: Option<PlayerControl>,
+    /// Record crowd quality metrics next to the statistics, e.g. to statistics-quality.json.
+    pub quality: bool,
 }
 
 impl Plugin for SimulationPlugin {
     fn build(&self, app: &mut App) {
         app.add_plugins((
-            FlockingPlugin,
+            SteeringPlugin,
             NavigationPlugin {
                 update: self.update_nav,
+                density: self.density_nav,
             },
-            SpawningPlugin,
-            // CollisionPlugin,
+            SpawningPlugin {
+                agent_params: self.agent_params.clone(),
+                schedule: self.spawn_schedule.clone(),
+            },
+            ArrivalPlugin {
+                arrival: self.arrival.clone(),
+            },
+            CrowdMetricsPlugin,
         ))
+        .init_schedule(SimulationStep)
         .configure_sets(
             PreUpdate,
             (
+                SimulationSet::MoveTargets,
                 SimulationSet::Despawn,
                 SimulationSet::Spawn,
                 SimulationSet::Flush,
                 SimulationSet::GenNavigation,
-                SimulationSet::Movement,
-                SimulationSet::ApplyColliders,
+                SimulationSet::Step,
             )
                 .chain(),
         )
-        .add_systems(PreUpdate, apply_deferred.in_set(SimulationSet::Flush));
+        .configure_sets(
+            SimulationStep,
+            (SimulationSet::Movement, SimulationSet::ApplyColliders).chain(),
+        )
+        .add_systems(
+            PreUpdate,
+            (
+                apply_deferred.in_set(SimulationSet::Flush),
+                run_substeps.in_set(SimulationSet::Step),
+            ),
+        )
+        .insert_resource(self.boids_weights)
+        .insert_resource(self.tick);
+
+        if self.hard_collisions {
+            app.add_plugins(CollisionPlugin);
+        }
+
+        if let Some(settings) = &self.depenetration {
+            app.add_plugins(DepenetrationPlugin {
+                settings: settings.clone(),
+            });
+        }
+
+        if !self.target_motions.targets.is_empty() {
+            app.add_plugins(TargetMotionPlugin {
+                motions: self.target_motions.clone(),
+            });
+        }
+
+        if let Some(control) = self.player {
+            app.add_plugins(PlayerPlugin { control });
+        }
+
+        if self.quality {
+            app.add_plugins(QualityPlugin);
+        }
+
+        if let Some(settings) = &self.evacuation {
+            app.add_plugins(EvacuationPlugin {
+                settings: settings.clone(),
+            });
+        }
     }
 }
 
 #[derive(SystemSet, Hash, PartialEq, Eq, Clone, Debug)]
 pub enum SimulationSet {
+    /// Moves targets and the player before the flow field is updated.
+    MoveTargets,
     Despawn,
     Spawn,
     Flush,
     GenNavigation,
+    /// Runs `SimulationStep` once per substep.
+    Step,
+    /// In `SimulationStep`
     Movement,
+    /// In `SimulationStep`
     ApplyColliders,
 }
+
+/// Movement and collisions, ran `SimulationTick::substeps` times per tick.
+#[derive(ScheduleLabel, Hash, PartialEq, Eq, Clone, Debug)]
+pub struct SimulationStep;
+
+/// Length of one tick of the simulation. Can be changed between ticks.
+/// Steering is tuned for `crate::REFERENCE_DT` and scales to other step lengths,
+/// so crowds behave the same at e.g. 30 Hz and 120 Hz.
+#[derive(Resource, Clone, Copy, Debug)]
+pub struct SimulationTick {
+    /// Seconds
+    pub length: f32,
+    /// Movement is split to this many smaller steps per tick for accuracy.
+    pub substeps: u32,
+}
+
+impl SimulationTick {
+    /// Length of one substep
+    pub fn dt(&self) -> f32 {
+        self.length / self.substeps as f32
+    }
+}
+
+fn run_substeps(world: &mut World) {
+    let substeps = world.resource::<SimulationTick>().substeps;
+    for _ in 0..substeps {
+        world.run_schedule(SimulationStep);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use ndarray::Array2;
+
+    use crate::{
+        level::Level,
+        statistics::Statistics,
+        utils::{Velocity, Vertices},
+    };
+
+    use super::{
+        arrival::AgentState,
+        navigation::{Flow, FlowField, NavGridInner},
+        spawning::{AgentParams, AgentParamsDistribution, Enemy},
+        *,
+    };
+
+    /// Positions of a tight group walking east after `ticks` ticks, with the real steering
+    /// and `run_substeps`.
+    fn simulate_crowd(tick: SimulationTick, ticks: u32) -> Vec<Vec2> {
+        let params = AgentParamsDistribution::default().sample(&mut rand::thread_rng());
+        let agents = (0..9)
+            .map(|i| {
+                let pos = Vec2::new(10., 20.) + Vec2::new((i % 3) as f32, (i / 3) as f32) * 0.6;
+                (pos, params)
+            })
+            .collect();
+        simulate(tick, ticks, Vec::new(), agents)
+    }
+
+    /// Positions of agents walking east between `walls` after `ticks` ticks.
+    fn simulate(
+        tick: SimulationTick,
+        ticks: u32,
+        walls: Vec<Vertices>,
+        agents: Vec<(Vec2, AgentParams)>,
+    ) -> Vec<Vec2> {
+        let level = Level {
+            size: 40.,
+            walls,
+            ..default()
+        };
+        let nav_grid = NavGridInner::new(level.size, &level.walls);
+        let flow_field = Array2::from_elem(nav_grid.walkable.raw_dim(), (0., Flow::East));
+
+        let mut app = App::new();
+        app.add_plugins((TaskPoolPlugin::default(), SteeringPlugin))
+            .init_schedule(SimulationStep)
+            .configure_sets(
+                SimulationStep,
+                (SimulationSet::Movement, SimulationSet::ApplyColliders).chain(),
+            )
+            .insert_resource(tick)
+            .insert_resource(level)
+            .insert_resource(navigation::NavGrid(Arc::new(nav_grid)))
+            .insert_resource(FlowField(flow_field))
+            .insert_resource(Arrival::default())
+            .insert_resource(BoidsWeights {
+                separation: 1.,
+                alignment: 0.1,
+                cohesion: 0.5,
+            })
+            .init_resource::<Statistics>();
+
+        let agents = agents
+            .into_iter()
+            .map(|(pos, params)| {
+                app.world
+                    .spawn((
+                        Enemy,
+                        SpatialBundle::from_transform(Transform::from_translation(pos.extend(0.))),
+                        Velocity::default(),
+                        params,
+                        AgentState::default(),
+                    ))
+                    .id()
+            })
+            .collect::<Vec<_>>();
+
+        app.world.run_schedule(Startup);
+        for _ in 0..ticks {
+            run_substeps(&mut app.world);
+        }
+
+        agents
+            .into_iter()
+            .map(|agent| {
+                app.world
+                    .get::<Transform>(agent)
+                    .unwrap()
+                    .translation
+                    .truncate()
+            })
+            .collect()
+    }
+
+    fn max_distance(a: &[Vec2], b: &[Vec2]) -> f32 {
+        a.iter()
+            .zip(b)
+            .map(|(a, b)| a.distance(*b))
+            .fold(0., f32::max)
+    }
+
+    fn tick(rate: f32, substeps: u32) -> SimulationTick {
+        SimulationTick {
+            length: 1. / rate,
+            substeps,
+        }
+    }
+
+    #[test]
+    fn substeps_match_a_higher_tick_rate() {
+        let fast = simulate_crowd(tick(120., 1), 120);
+        let substepped = simulate_crowd(tick(30., 4), 30);
+        assert!(max_distance(&fast, &substepped) < 1e-3);
+    }
+
+    /// Skipped without `new_move_clamp`, as the legacy clamp reduces speeding by a fixed
+    /// amount per step, so crowds drift apart at different tick rates.
+    #[cfg(feature = "new_move_clamp")]
+    #[test]
+    fn crowd_is_stable_across_tick_rates() {
+        // The group moves and spreads out, but ends up about the same at every tick rate
+        let reference = simulate_crowd(tick(60., 1), 60);
+        let center = reference.iter().sum::<Vec2>() / reference.len() as f32;
+        assert!(center.x > 11.6);
+        for rate in [30., 120.] {
+            let other = simulate_crowd(tick(rate, 1), rate as u32);
+            let difference = max_distance(&reference, &other);
+            assert!(
+                difference < spawning::ENEMY_RADIUS * 0.8,
+                "{difference} at {rate} Hz"
+            );
+        }
+    }
+
+    /// Skipped without `new_move_clamp`, as the legacy clamp lets agents speed up to five
+    /// times their `max_speed`, so slow agents aren't reliably slower.
+    #[cfg(feature = "new_move_clamp")]
+    #[test]
+    fn fast_agents_overtake_slow_ones() {
+        use crate::utils::{rectangle, WithOffset};
+
+        let corridor = vec![
+            rectangle(Vec2::new(40., 2.)).with_offset(Vec2::new(20., 17.)),
+            rectangle(Vec2::new(40., 2.)).with_offset(Vec2::new(20., 23.)),
+        ];
+        let params = AgentParamsDistribution::default().sample(&mut rand::thread_rng());
+        let slow = AgentParams {
+            max_speed: params.max_speed / 2.,
+            ..params
+        };
+        // The fast agent starts right behind the slow one and has to pass it
+        let positions = simulate(
+            tick(60., 1),
+            300,
+            corridor,
+            vec![(Vec2::new(5., 19.8), params), (Vec2::new(6.5, 20.), slow)],
+        );
+        assert!(
+            positions[0].x > positions[1].x + 1.,
+            "fast agent at {}, slow agent at {}",
+            positions[0],
+            positions[1]
+        );
+    }
+}
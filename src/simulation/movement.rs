@@ -1,23 +1,45 @@
 use bevy::{ecs::system::SystemState, prelude::*, utils::Instant};
 
-use crate::{statistics::Statistics, utils::Velocity, DELTA_TIME};
+use crate::{statistics::Statistics, utils::Velocity, REFERENCE_DT};
 
-use super::spawning::Enemy;
+use super::{
+    arrival::{AgentState, Arrival},
+    navigation::{Flow, FlowField, NavGrid, NavGridInner},
+    spawning::{AgentParams, Enemy},
+    SimulationTick,
+};
 
-const ENEMY_SPEED: f32 = 4.;
-use super::navigation::{Flow, FlowField, NavGrid, NavGridInner};
+/// Default for `AgentParams::max_speed`
+pub const ENEMY_SPEED: f32 = 4.;
+/// Default for `AgentParams::acceleration`
+#[cfg(not(feature = "new_movement"))]
+pub const ENEMY_ACCELERATION: f32 = ENEMY_SPEED * 0.4 / REFERENCE_DT;
+#[cfg(feature = "new_movement")]
+pub const ENEMY_ACCELERATION: f32 = ENEMY_SPEED * 0.05 / REFERENCE_DT;
 
 pub fn move_with_flow_field(world: &mut World) {
     let start = Instant::now();
 
+    let dt = world.resource::<SimulationTick>().dt();
+
     let mut system_state: SystemState<(
-        Query<(&mut Transform, &mut Velocity), With<Enemy>>,
+        Query<
+            (
+                Entity,
+                &mut Transform,
+                &mut Velocity,
+                &AgentParams,
+                &AgentState,
+            ),
+            With<Enemy>,
+        >,
         Res<NavGrid>,
         Option<Res<FlowField>>,
+        Res<Arrival>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
 
-    let (mut enemy_q, nav_grid, flow_field, mut stats) = system_state.get_mut(world);
+    let (mut enemy_q, nav_grid, flow_field, arrival, mut stats) = system_state.get_mut(world);
 
     let Some(flow_field) = flow_field.as_ref() else {
         return;
@@ -28,58 +50,107 @@ pub fn move_with_flow_field(world: &mut World) {
     #[cfg(feature = "parallel")]
     let iter = enemy_q.par_iter_mut();
 
-    iter.for_each(|(mut transform, mut velocity)| {
-        #[cfg(not(feature = "new_movement"))]
-        let max_speed_change = ENEMY_SPEED * 0.4;
-        #[cfg(feature = "new_movement")]
-        let max_speed_change = ENEMY_SPEED * 0.05;
-
+    iter.for_each(|(entity, mut transform, mut velocity, params, state)| {
         let pos = transform.translation.truncate();
-        let idx = nav_grid.pos_to_index(pos);
-        // #[cfg(navigation1)]
-        let add_vel = flow_field.get(idx).copied().map_or_else(
-            || Vec2::ZERO,
-            |flow| {
-                if flow == Flow::Source {
-                    (NavGridInner::index_to_pos(idx) - pos).normalize_or_zero() * max_speed_change
-                } else if flow == Flow::None {
-                    Vec2::ZERO
-                } else {
-                    flow.to_dir() * max_speed_change
-                }
-            },
+        let (direction, max_speed) = arrival.preferred_motion(
+            entity,
+            *state,
+            pos,
+            flow_direction(flow_field, &nav_grid, pos),
+            params.max_speed,
         );
+        let new_vel = steer(velocity.0, direction, max_speed, params.acceleration, dt);
+
+        let moved = nav_grid.slide(pos, new_vel * dt);
+        transform.translation.x += moved.x;
+        transform.translation.y += moved.y;
+        velocity.0 = moved / dt;
+    });
 
-        let mut new_vel = velocity.0 + add_vel;
-
-        cfg_if::cfg_if! {
-            if #[cfg(feature = "new_move_clamp")] {
-                let length = new_vel.length();
-                let speeding = length - ENEMY_SPEED;
-                if speeding > 0. {
-                    new_vel = new_vel / length * (length - speeding * 0.5)
-                }
-            } else {
-                let length = new_vel.length();
-                // If over maximum, scale it down slowly
-                let max = (length - ENEMY_SPEED * 0.5).clamp(ENEMY_SPEED, ENEMY_SPEED * 5.0);
-                new_vel = new_vel / length * max;
+    stats.add("move_thing", start.elapsed());
+}
+
+/// Accelerates `velocity` towards `direction` for a step of `dt` seconds.
+/// Speed over `max_speed` is reduced gradually.
+fn steer(velocity: Vec2, direction: Vec2, max_speed: f32, acceleration: f32, dt: f32) -> Vec2 {
+    // Per tick factors were tuned at `REFERENCE_DT`
+    let steps = dt / REFERENCE_DT;
+
+    let mut new_vel = velocity + direction * acceleration * dt;
+
+    cfg_if::cfg_if! {
+        if #[cfg(feature = "new_move_clamp")] {
+            let length = new_vel.length();
+            let speeding = length - max_speed;
+            if speeding > 0. {
+                // Halves the speeding every reference tick
+                new_vel = new_vel / length * (length - speeding * (1. - 0.5f32.powf(steps)))
             }
+        } else {
+            let length = new_vel.length();
+            // If over maximum, scale it down slowly
+            let max = (length - max_speed * 0.5 * steps).clamp(max_speed, max_speed * 5.0);
+            new_vel = new_vel.normalize_or_zero() * max;
+        }
+    }
+
+    new_vel
+}
+
+/// Direction the flow field guides an agent at `pos` to, or zero if there is no way forward.
+pub fn flow_direction(flow_field: &FlowField, nav_grid: &NavGridInner, pos: Vec2) -> Vec2 {
+    let idx = nav_grid.pos_to_index(pos);
+    // #[cfg(navigation1)]
+    flow_field
+        .get(idx)
+        .copied()
+        .map_or(Vec2::ZERO, |flow| match flow {
+            Flow::Source => (NavGridInner::index_to_pos(idx) - pos).normalize_or_zero(),
+            Flow::None => Vec2::ZERO,
+            flow => flow.to_dir(),
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Position and velocity after steering towards +x for `duration` seconds.
+    fn simulate(velocity: Vec2, dt: f32, duration: f32) -> (Vec2, Vec2) {
+        let mut pos = Vec2::ZERO;
+        let mut vel = velocity;
+        for _ in 0..(duration / dt).round() as u32 {
+            vel = steer(vel, Vec2::X, ENEMY_SPEED, ENEMY_ACCELERATION, dt);
+            pos += vel * dt;
         }
+        (pos, vel)
+    }
 
-        let pos = pos + new_vel * DELTA_TIME;
-        let valid = flow_field
-            .get(nav_grid.pos_to_index(pos))
-            .is_some_and(|flow| *flow != Flow::None);
+    fn assert_close(a: Vec2, b: Vec2, what: &str) {
+        assert!(
+            a.distance(b) <= b.length() * 0.05,
+            "{what}: {a} differs from {b} by more than 5%"
+        );
+    }
 
-        if valid {
-            transform.translation.x = pos.x;
-            transform.translation.y = pos.y;
-            velocity.0 = new_vel;
-        } else {
-            velocity.0 = Vec2::ZERO;
+    #[test]
+    fn accelerating_is_stable_across_tick_lengths() {
+        let (reference_pos, reference_vel) = simulate(Vec2::ZERO, REFERENCE_DT, 1.);
+        for tick_rate in [20., 30., 120., 240.] {
+            let (pos, vel) = simulate(Vec2::ZERO, 1. / tick_rate, 1.);
+            assert_close(pos, reference_pos, &format!("position at {tick_rate} Hz"));
+            assert_close(vel, reference_vel, &format!("velocity at {tick_rate} Hz"));
         }
-    });
+    }
 
-    stats.add("move_thing", start.elapsed());
+    #[test]
+    fn slowing_down_is_stable_across_tick_lengths() {
+        let pushed = Vec2::X * ENEMY_SPEED * 2.;
+        let (reference_pos, reference_vel) = simulate(pushed, REFERENCE_DT, 0.2);
+        for tick_rate in [30., 120., 240.] {
+            let (pos, vel) = simulate(pushed, 1. / tick_rate, 0.2);
+            assert_close(pos, reference_pos, &format!("position at {tick_rate} Hz"));
+            assert_close(vel, reference_vel, &format!("velocity at {tick_rate} Hz"));
+        }
+    }
 }
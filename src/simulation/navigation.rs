@@ -2,7 +2,7 @@ use crate::{
     level::{Level, LevelStartupSet, Target},
     mouse_follow::MousePosition,
     statistics::Statistics,
-    utils::{inflate_polygon, is_point_in_polygon, ToUsizeArr, ToVec2, Vertices},
+    utils::{inflate_polygon, is_clockwise, ToUsizeArr, ToVec2, Vertices},
 };
 use bevy::{
     ecs::system::SystemState,
@@ -12,22 +12,34 @@ use bevy::{
 };
 use futures_lite::future;
 use ndarray::Array2;
+#[cfg(feature = "parallel")]
+use rayon::iter::{IndexedParallelIterator, IntoParallelRefIterator, ParallelIterator};
 use std::{collections::VecDeque, f32::consts::SQRT_2, sync::Arc, time::Duration};
 
-use super::{spawning::ENEMY_RADIUS, SimulationSet};
+use super::{player::Player, spawning::ENEMY_RADIUS, SimulationSet};
+
+mod density;
+mod wall_edges;
+
+use density::{init_density, update_density, Density, DensityField};
+use wall_edges::WallEdges;
 
 pub const NAV_SCALE: f32 = ENEMY_RADIUS;
 pub const NAV_SCALE_INV: f32 = 1. / NAV_SCALE;
+/// How much walls are expanded to keep agents from clipping into them.
+pub const WALL_INFLATION: f32 = ENEMY_RADIUS * 1.3;
 
 pub struct NavigationPlugin {
     pub update: bool,
+    /// Make the flow field avoid crowded areas. Implies `update`.
+    pub density: bool,
 }
 
 impl Plugin for NavigationPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<FlowField>()
             .insert_resource(RunInTask(false))
-            .insert_resource(RunOnce(!self.update))
+            .insert_resource(RunOnce(!self.update && !self.density))
             .init_resource::<FlowFieldGenerate>()
             .add_systems(PreStartup, init_nav_grid.after(LevelStartupSet::Spawn))
             .add_systems(
@@ -41,6 +53,17 @@ impl Plugin for NavigationPlugin {
                     .run_if(once)
                     .in_set(SimulationSet::GenNavigation),
             );
+
+        if self.density {
+            app.add_systems(PreStartup, init_density.after(init_nav_grid))
+                .add_systems(
+                    PreUpdate,
+                    update_density
+                        .before(generate_flow_field_system)
+                        .before(start_flow_field_generation_task)
+                        .in_set(SimulationSet::GenNavigation),
+                );
+        }
     }
 }
 
@@ -71,13 +94,16 @@ pub struct NavGridInner {
     #[allow(dead_code)]
     size: f32,
     inflated_walls: Vec<Vertices>,
+    wall_edges: WallEdges,
     pub walkable: Array2<bool>,
     /// Contains bitsets of directions that can be moved in from a given index
     pub grid: Array2<u8>,
 }
 
-fn init_nav_grid(mut commands: Commands, level: Res<Level>) {
+fn init_nav_grid(mut commands: Commands, level: Res<Level>, mut stats: ResMut<Statistics>) {
+    let start = Instant::now();
     let nav_grid = NavGridInner::new(level.size, &level.walls);
+    stats.add("nav_grid", start.elapsed());
     commands.insert_resource(NavGrid(Arc::new(nav_grid)));
 }
 
@@ -178,28 +204,35 @@ impl Flow {
 
 impl NavGridInner {
     pub fn new(size: f32, walls: &[Vertices]) -> Self {
-        // Expand walls
+        // Expand walls. Holes are clockwise, so inside of a wall the winding numbers sum to 1
         let walls = walls
             .iter()
-            .filter_map(|w| inflate_polygon(w, ENEMY_RADIUS * 1.3))
+            .enumerate()
+            .flat_map(|(i, w)| {
+                inflate_polygon(w, WALL_INFLATION).unwrap_or_else(|err| {
+                    error!("Failed to inflate wall {i}, using it as is: {err}");
+                    let mut w = w.clone();
+                    if is_clockwise(&w) {
+                        w.reverse();
+                    }
+                    vec![w]
+                })
+            })
             .collect::<Vec<_>>();
 
         let scale = NAV_SCALE;
         let scaled_size = (size / scale) as usize + 2;
-        let mut walkable = Array2::from_elem((scaled_size, scaled_size), false);
-        for x in 1..scaled_size - 1 {
-            for y in 1..scaled_size - 1 {
-                let pos = Self::index_to_pos_impl(Vec2::new(x as f32, y as f32));
-                let mut w = true;
-                for vertices in &walls {
-                    if is_point_in_polygon(pos, vertices) {
-                        w = false;
-                        break;
-                    }
-                }
-                walkable[[x, y]] = w;
-            }
-        }
+        let walkable = Self::rasterize_walls(scaled_size, &walls);
+
+        // Level edges as a clockwise ring, free space is inside
+        let mut edge_rings = walls.clone();
+        edge_rings.push(vec![
+            Vec2::ZERO,
+            Vec2::new(0., size),
+            Vec2::splat(size),
+            Vec2::new(size, 0.),
+        ]);
+        let wall_edges = WallEdges::new(size, &edge_rings);
         let mut grid = Array2::from_elem((scaled_size, scaled_size), 0);
         for x in 1..scaled_size - 1 {
             for y in 1..scaled_size - 1 {
@@ -224,11 +257,113 @@ impl NavGridInner {
         Self {
             size,
             inflated_walls: walls,
+            wall_edges,
             walkable,
             grid,
         }
     }
 
+    /// Marks cells whose center is inside a wall as not walkable.
+    /// Gives the same result as summing `winding_number` of every wall for every cell,
+    /// but only visits the wall edges that cross each row.
+    fn rasterize_walls(scaled_size: usize, walls: &[Vertices]) -> Array2<bool> {
+        let row_y = |y: usize| Self::index_to_pos_impl(Vec2::new(0., y as f32)).y;
+        let cell_x = |x: usize| Self::index_to_pos_impl(Vec2::new(x as f32, 0.)).x;
+
+        // Bucket edges by the rows they span
+        let mut rows = vec![Vec::new(); scaled_size];
+        for vertices in walls {
+            for (a, b) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+                let to_row = |y: f32| (y - NAV_SCALE * 0.5) * NAV_SCALE_INV + 1.;
+                let first = to_row(a.y.min(b.y)).floor().max(1.) as usize;
+                let last = (to_row(a.y.max(b.y)).ceil() as usize).min(scaled_size - 2);
+                for row in rows.iter_mut().take(last + 1).skip(first) {
+                    row.push((*a, *b));
+                }
+            }
+        }
+
+        let rasterize_row = |(y, edges): (usize, &Vec<(Vec2, Vec2)>)| {
+            let mut diff = vec![0; scaled_size];
+            if y == 0 || y == scaled_size - 1 {
+                return diff;
+            }
+            let pos_y = row_y(y);
+            for &(a, b) in edges {
+                // Same rules as in `winding_number`
+                let winding = if a.y <= pos_y && b.y > pos_y {
+                    1
+                } else if a.y > pos_y && b.y <= pos_y {
+                    -1
+                } else {
+                    continue;
+                };
+                let counts = |x: usize| {
+                    let side = (b - a).perp_dot(Vec2::new(cell_x(x), pos_y) - a);
+                    if winding > 0 {
+                        side > 0.
+                    } else {
+                        side < 0.
+                    }
+                };
+
+                // Cells left of the edge count. Start from the crossing point and fix rounding.
+                let cross_x = a.x + (pos_y - a.y) / (b.y - a.y) * (b.x - a.x);
+                let mut end = ((cross_x - NAV_SCALE * 0.5) * NAV_SCALE_INV + 1.)
+                    .ceil()
+                    .clamp(1., (scaled_size - 1) as f32) as usize;
+                while end > 1 && !counts(end - 1) {
+                    end -= 1;
+                }
+                while end < scaled_size - 1 && counts(end) {
+                    end += 1;
+                }
+                diff[1] += winding;
+                diff[end] -= winding;
+            }
+            diff
+        };
+
+        #[cfg(not(feature = "parallel"))]
+        let iter = rows.iter();
+        #[cfg(feature = "parallel")]
+        let iter = rows.par_iter();
+
+        let diffs = iter.enumerate().map(rasterize_row).collect::<Vec<_>>();
+
+        let mut walkable = Array2::from_elem((scaled_size, scaled_size), false);
+        for (y, diff) in diffs.iter().enumerate().take(scaled_size - 1).skip(1) {
+            let mut winding = 0;
+            for x in 1..scaled_size - 1 {
+                winding += diff[x];
+                walkable[[x, y]] = winding == 0;
+            }
+        }
+        walkable
+    }
+
+    /// Returns how much `pos` can be moved towards `delta` without going inside inflated walls
+    /// or outside the level. Movement into a wall slides along it.
+    pub fn slide(&self, pos: Vec2, delta: Vec2) -> Vec2 {
+        self.wall_edges.slide(pos, delta)
+    }
+
+    /// Closest point on the inflated walls or the level border that is at most `max_distance`
+    /// away from `pos`.
+    #[cfg_attr(not(feature = "steering_social_force"), allow(dead_code))]
+    pub fn closest_wall_point(&self, pos: Vec2, max_distance: f32) -> Option<Vec2> {
+        self.wall_edges.closest_point(pos, max_distance)
+    }
+
+    /// Whether the straight line between the cells of `from` and `to` is walkable.
+    pub fn line_of_sight(&self, from: Vec2, to: Vec2) -> bool {
+        let (start, end) = (self.pos_to_index(from), self.pos_to_index(to));
+        if self.walkable.get(start).is_none() || self.walkable.get(end).is_none() {
+            return false;
+        }
+        start == end || self.raycast_walkable_dda(start, end)
+    }
+
     pub fn pos_to_index(&self, pos: Vec2) -> [usize; 2] {
         let pos = (pos * NAV_SCALE_INV + Vec2::ONE).floor();
         [pos.x as usize, pos.y as usize]
@@ -296,12 +431,14 @@ fn check_neighbor(
     dist: f32,
     grid_val: u8,
     idx: [usize; 2],
+    density: Option<&DensityField>,
     flow_field: &mut Array2<(f32, Flow)>,
     queue: &mut VecDeque<(f32, [usize; 2])>,
 ) {
     if grid_val & flow.mask() != 0 {
         let neigh_idx = neighbor_idx(idx, flow);
-        let new_dist = dist + flow.distance();
+        let cost = density.map_or(flow.distance(), |d| d.cost(neigh_idx, flow));
+        let new_dist = dist + cost;
         let f = &mut flow_field[neigh_idx];
         if f.0 > new_dist {
             *f = (new_dist, flow);
@@ -343,14 +480,18 @@ fn check_neighbor_raycast(
 
 const NAV_LINE_OF_SIGHT_DIST: f32 = 30.;
 
+/// With `density`, crowded cells cost more to pass through. Line of sight still ignores it.
 pub fn generate_flow_field_impl(
     nav_grid: Arc<NavGridInner>,
+    density: Option<Arc<DensityField>>,
     sources: Vec<[usize; 2]>,
 ) -> (Duration, FlowFieldInner) {
     let start = Instant::now();
     let mut flow_field = Array2::from_elem(nav_grid.grid.raw_dim(), (f32::INFINITY, Flow::None));
 
     // Do a first pass with normal BFS
+    // (cells are visited again when a cheaper path is found, so it works with density costs too)
+    let density = density.as_deref();
     let mut queue = VecDeque::new();
     for source in sources.iter() {
         queue.push_back((0., *source));
@@ -364,7 +505,15 @@ pub fn generate_flow_field_impl(
         let grid_val = nav_grid.grid[idx];
         macro_rules! check_neighbor {
             ($flow:expr) => {
-                check_neighbor($flow, dist, grid_val, idx, &mut flow_field, &mut queue)
+                check_neighbor(
+                    $flow,
+                    dist,
+                    grid_val,
+                    idx,
+                    density,
+                    &mut flow_field,
+                    &mut queue,
+                )
             };
         }
         check_neighbor!(Flow::North);
@@ -416,10 +565,13 @@ fn generate_flow_field_system(world: &mut World) {
         Res<NavGrid>,
         ResMut<FlowField>,
         Query<&Transform, With<Target>>,
+        Query<&Transform, With<Player>>,
         Option<Res<MousePosition>>,
+        Option<Res<Density>>,
         ResMut<Statistics>,
     )> = SystemState::new(world);
-    let (nav_grid, mut flow_field, target_q, mouse_pos, mut stats) = system_state.get_mut(world);
+    let (nav_grid, mut flow_field, target_q, player_q, mouse_pos, density, mut stats) =
+        system_state.get_mut(world);
 
     let mut targets = vec![];
 
@@ -429,6 +581,14 @@ fn generate_flow_field_system(world: &mut World) {
         }
     }
 
+    if targets.is_empty() {
+        targets.extend(
+            player_q
+                .iter()
+                .map(|tr| nav_grid.pos_to_index(tr.translation.truncate())),
+        );
+    }
+
     if targets.is_empty() {
         targets.extend(
             target_q
@@ -437,7 +597,9 @@ fn generate_flow_field_system(world: &mut World) {
         );
     }
 
-    let (duration, flow_field_inner) = generate_flow_field_impl(Arc::clone(&nav_grid), targets);
+    let density = density.map(|d| Arc::clone(&d));
+    let (duration, flow_field_inner) =
+        generate_flow_field_impl(Arc::clone(&nav_grid), density, targets);
     stats.add("flow_field", duration);
     flow_field.0 = flow_field_inner;
 }
@@ -452,6 +614,8 @@ fn start_flow_field_generation_task(
     nav_grid: Res<NavGrid>,
     mut gen: ResMut<FlowFieldGenerate>,
     target_q: Query<&Transform, With<Target>>,
+    player_q: Query<&Transform, With<Player>>,
+    density: Option<Res<Density>>,
     time: Res<Time<Virtual>>,
     // mut stats: ResMut<Statistics>,
 ) {
@@ -461,15 +625,23 @@ fn start_flow_field_generation_task(
         return;
     }
     // When the last player dies, just continue going towards the latest corpse
-    let targets = target_q
+    let mut targets = player_q
         .iter()
         .map(|tr| nav_grid.pos_to_index(tr.translation.truncate()))
         .collect::<Vec<_>>();
+    if targets.is_empty() {
+        targets.extend(
+            target_q
+                .iter()
+                .map(|tr| nav_grid.pos_to_index(tr.translation.truncate())),
+        );
+    }
 
     let nav_grid = Arc::clone(&nav_grid);
+    let density = density.map(|d| Arc::clone(&d));
 
     let task_pool = AsyncComputeTaskPool::get();
-    let task = task_pool.spawn(async move { generate_flow_field_impl(nav_grid, targets) });
+    let task = task_pool.spawn(async move { generate_flow_field_impl(nav_grid, density, targets) });
 
     gen.task = Some(task);
     gen.last_started = time.elapsed();
@@ -492,3 +664,79 @@ fn handle_flow_field_task(
 
     // *stats.last_mut("flow_field").unwrap() += start.elapsed();
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::winding_number;
+
+    #[test]
+    fn bundled_level_walls_are_not_walkable() {
+        for entry in std::fs::read_dir("levels").unwrap() {
+            let path = entry.unwrap().path();
+            let level = Level::load(path.to_str().unwrap()).unwrap();
+            let nav_grid = NavGridInner::new(level.size, &level.walls);
+
+            for (i, wall) in level.walls.iter().enumerate() {
+                assert!(inflate_polygon(wall, WALL_INFLATION).is_ok());
+                for vertex in wall {
+                    let idx = nav_grid.pos_to_index(*vertex);
+                    // Some levels have walls that go past the edges
+                    let Some(walkable) = nav_grid.walkable.get(idx) else {
+                        continue;
+                    };
+                    assert!(
+                        !walkable,
+                        "{}: wall {i} vertex {vertex} is walkable",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn rasterization_matches_winding_number() {
+        for entry in std::fs::read_dir("levels").unwrap() {
+            let path = entry.unwrap().path();
+            let mut level = Level::load(path.to_str().unwrap()).unwrap();
+            // Checking every cell separately is slow
+            level.scale_to(60.);
+            let nav_grid = NavGridInner::new(level.size, &level.walls);
+
+            let (width, height) = nav_grid.walkable.dim();
+            for x in 1..width - 1 {
+                for y in 1..height - 1 {
+                    let pos = NavGridInner::index_to_pos([x, y]);
+                    let winding = nav_grid
+                        .inflated_walls()
+                        .iter()
+                        .map(|vertices| winding_number(pos, vertices))
+                        .sum::<i32>();
+                    assert_eq!(
+                        nav_grid.walkable[[x, y]],
+                        winding == 0,
+                        "{}: cell {x}, {y}",
+                        path.display()
+                    );
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn line_of_sight_is_blocked_by_walls() {
+        let wall = vec![
+            Vec2::new(8., 8.),
+            Vec2::new(12., 8.),
+            Vec2::new(12., 12.),
+            Vec2::new(8., 12.),
+        ];
+        let nav_grid = NavGridInner::new(20., &[wall]);
+
+        assert!(nav_grid.line_of_sight(Vec2::new(3., 10.), Vec2::new(3., 10.)));
+        assert!(nav_grid.line_of_sight(Vec2::new(3., 3.), Vec2::new(17., 3.)));
+        assert!(!nav_grid.line_of_sight(Vec2::new(3., 10.), Vec2::new(17., 10.)));
+        assert!(!nav_grid.line_of_sight(Vec2::new(3., 10.), Vec2::new(30., 10.)));
+    }
+}
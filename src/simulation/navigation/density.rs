@@ -0,0 +1,138 @@
+use std::sync::Arc;
+
+use bevy::{prelude::*, utils::Instant};
+use ndarray::{Array2, Axis};
+
+use crate::{
+    simulation::{movement::ENEMY_SPEED, spawning::Enemy},
+    statistics::Statistics,
+    utils::Velocity,
+};
+
+use super::{Flow, NavGrid, NAV_SCALE};
+
+/// Agents per square unit under which agents move at full speed.
+const DENSITY_MIN: f32 = 0.3;
+/// Agents per square unit over which agents move with the crowd.
+const DENSITY_MAX: f32 = 1.0;
+/// Agents are spread to this many cells in every direction,
+/// so a lone agent stays well below `DENSITY_MIN`.
+const BLUR_RADIUS: usize = 2;
+/// Even a jammed cell can be passed eventually.
+const MIN_RELATIVE_SPEED: f32 = 0.1;
+
+/// Agent density and average velocity of each navigation cell, like in continuum crowds.
+/// Used to make the flow field avoid congestion.
+#[derive(Resource, Default, Deref, DerefMut)]
+pub struct Density(pub Arc<DensityField>);
+
+#[derive(Default, Clone)]
+pub struct DensityField {
+    /// Agents per square unit
+    density: Array2<f32>,
+    average_velocity: Array2<Vec2>,
+}
+
+impl DensityField {
+    /// Cost of moving one step in the direction of `flow` from cell `idx`.
+    /// Same as `Flow::distance` when the cell isn't crowded, and grows when agents in the cell
+    /// are slower than the maximum speed in that direction.
+    #[inline]
+    pub fn cost(&self, idx: [usize; 2], flow: Flow) -> f32 {
+        let distance = flow.distance();
+        let density = self.density[idx];
+        if density <= DENSITY_MIN {
+            return distance;
+        }
+        let t = ((density - DENSITY_MIN) / (DENSITY_MAX - DENSITY_MIN)).min(1.);
+        let flow_speed =
+            (self.average_velocity[idx].dot(flow.to_dir()) / ENEMY_SPEED).clamp(0., 1.);
+        let speed = 1. + (flow_speed - 1.) * t;
+        distance / speed.max(MIN_RELATIVE_SPEED)
+    }
+}
+
+pub(super) fn init_density(mut commands: Commands, nav_grid: Res<NavGrid>) {
+    let dim = nav_grid.walkable.raw_dim();
+    commands.insert_resource(Density(Arc::new(DensityField {
+        density: Array2::zeros(dim),
+        average_velocity: Array2::from_elem(dim, Vec2::ZERO),
+    })));
+}
+
+pub(super) fn update_density(
+    enemy_q: Query<(&Transform, &Velocity), With<Enemy>>,
+    nav_grid: Res<NavGrid>,
+    mut density: ResMut<Density>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+
+    // Clones only if a flow field task still uses the previous field
+    let field = Arc::make_mut(&mut density.0);
+    let (width, height) = field.density.dim();
+
+    field.density.fill(0.);
+    field.average_velocity.fill(Vec2::ZERO);
+    for (transform, velocity) in &enemy_q {
+        let [x, y] = nav_grid.pos_to_index(transform.translation.truncate());
+        if x < width && y < height {
+            field.density[[x, y]] += 1.;
+            field.average_velocity[[x, y]] += velocity.0;
+        }
+    }
+
+    blur(&mut field.density, BLUR_RADIUS);
+    blur(&mut field.average_velocity, BLUR_RADIUS);
+
+    let cells = (BLUR_RADIUS * 2 + 1).pow(2) as f32;
+    let cell_area = NAV_SCALE * NAV_SCALE;
+    for (density, velocity) in field
+        .density
+        .iter_mut()
+        .zip(field.average_velocity.iter_mut())
+    {
+        if *density > 0. {
+            *velocity /= *density;
+        }
+        *density /= cells * cell_area;
+    }
+
+    stats.add("density", start.elapsed());
+}
+
+/// Replaces every cell with the sum of the cells at most `radius` away on both axes.
+fn blur<T>(grid: &mut Array2<T>, radius: usize)
+where
+    T: Copy + Default + std::ops::AddAssign + std::ops::SubAssign,
+{
+    for axis in [Axis(0), Axis(1)] {
+        for mut lane in grid.lanes_mut(axis) {
+            let sums = sliding_sum(&lane.to_vec(), radius);
+            lane.iter_mut()
+                .zip(sums)
+                .for_each(|(cell, sum)| *cell = sum);
+        }
+    }
+}
+
+fn sliding_sum<T>(values: &[T], radius: usize) -> Vec<T>
+where
+    T: Copy + Default + std::ops::AddAssign + std::ops::SubAssign,
+{
+    let mut sums = Vec::with_capacity(values.len());
+    let mut sum = T::default();
+    for value in values.iter().take(radius) {
+        sum += *value;
+    }
+    for i in 0..values.len() {
+        if let Some(value) = values.get(i + radius) {
+            sum += *value;
+        }
+        if i > radius {
+            sum -= values[i - radius - 1];
+        }
+        sums.push(sum);
+    }
+    sums
+}
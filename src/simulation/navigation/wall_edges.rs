@@ -0,0 +1,207 @@
+use bevy::prelude::*;
+
+use crate::utils::Vertices;
+
+const CELL_SIZE: f32 = 2.;
+/// How far from a wall a sliding agent is kept, so it doesn't end up exactly on the edge.
+const SKIN: f32 = 0.001;
+/// How many times the movement can be deflected by walls in one move, e.g. in corners.
+const MAX_SLIDES: usize = 3;
+
+/// Grid of wall edges for finding which walls a movement crosses.
+#[derive(Default)]
+pub struct WallEdges {
+    size: usize,
+    edges: Vec<(Vec2, Vec2)>,
+    /// Indices of edges that overlap each cell
+    cells: Vec<Vec<u32>>,
+}
+
+impl WallEdges {
+    /// Free space must be on the right side of the edges,
+    /// so outer rings should be counter-clockwise and holes clockwise.
+    pub fn new(level_size: f32, walls: &[Vertices]) -> Self {
+        let size = (level_size / CELL_SIZE).ceil() as usize + 1;
+        let mut wall_edges = Self {
+            size,
+            edges: Vec::new(),
+            cells: vec![Vec::new(); size * size],
+        };
+        for vertices in walls {
+            for (a, b) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+                if a != b {
+                    wall_edges.insert(*a, *b);
+                }
+            }
+        }
+        wall_edges
+    }
+
+    fn cell(&self, pos: Vec2) -> [usize; 2] {
+        let cell = (pos / CELL_SIZE)
+            .floor()
+            .clamp(Vec2::ZERO, Vec2::splat((self.size - 1) as f32));
+        [cell.x as usize, cell.y as usize]
+    }
+
+    fn insert(&mut self, a: Vec2, b: Vec2) {
+        let index = self.edges.len() as u32;
+        self.edges.push((a, b));
+        let [x0, y0] = self.cell(a.min(b));
+        let [x1, y1] = self.cell(a.max(b));
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                self.cells[x * self.size + y].push(index);
+            }
+        }
+    }
+
+    /// Finds the first edge that is crossed from the free side when moving from `from` to `to`.
+    /// Returns the fraction of the movement before the hit and the edge.
+    fn first_hit(&self, from: Vec2, to: Vec2) -> Option<(f32, Vec2, Vec2)> {
+        let [x0, y0] = self.cell(from.min(to));
+        let [x1, y1] = self.cell(from.max(to));
+
+        let mut hit: Option<(f32, Vec2, Vec2)> = None;
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                for &i in &self.cells[x * self.size + y] {
+                    let (a, b) = self.edges[i as usize];
+                    let edge = b - a;
+                    // Agents that are already inside a wall are allowed to get out
+                    let side_from = edge.perp_dot(from - a);
+                    let side_to = edge.perp_dot(to - a);
+                    if side_from > 0. || side_to <= 0. {
+                        continue;
+                    }
+                    let t = side_from / (side_from - side_to);
+                    let along = (from + (to - from) * t - a).dot(edge) / edge.length_squared();
+                    if !(0. ..=1.).contains(&along) {
+                        continue;
+                    }
+                    if !hit.is_some_and(|(best, _, _)| best <= t) {
+                        hit = Some((t, a, b));
+                    }
+                }
+            }
+        }
+        hit
+    }
+
+    /// Finds the closest point on any edge that is at most `max_distance` away from `pos`.
+    pub fn closest_point(&self, pos: Vec2, max_distance: f32) -> Option<Vec2> {
+        if self.cells.is_empty() {
+            return None;
+        }
+        let [x0, y0] = self.cell(pos - max_distance);
+        let [x1, y1] = self.cell(pos + max_distance);
+
+        let mut closest: Option<(f32, Vec2)> = None;
+        for x in x0..=x1 {
+            for y in y0..=y1 {
+                for &i in &self.cells[x * self.size + y] {
+                    let (a, b) = self.edges[i as usize];
+                    let edge = b - a;
+                    let t = ((pos - a).dot(edge) / edge.length_squared()).clamp(0., 1.);
+                    let point = a + edge * t;
+                    let distance_sq = point.distance_squared(pos);
+                    if distance_sq <= max_distance * max_distance
+                        && !closest.is_some_and(|(best, _)| best <= distance_sq)
+                    {
+                        closest = Some((distance_sq, point));
+                    }
+                }
+            }
+        }
+        closest.map(|(_, point)| point)
+    }
+
+    /// Returns how much `pos` can be moved towards `delta`.
+    /// Movement into a wall is projected along the wall instead of stopping.
+    pub fn slide(&self, pos: Vec2, mut delta: Vec2) -> Vec2 {
+        if !delta.is_finite() || self.cells.is_empty() {
+            return Vec2::ZERO;
+        }
+        let mut moved = Vec2::ZERO;
+        for _ in 0..MAX_SLIDES {
+            let Some((t, a, b)) = self.first_hit(pos + moved, pos + moved + delta) else {
+                return moved + delta;
+            };
+            let tangent = (b - a).normalize();
+            // Free space is on the right
+            let normal = -tangent.perp();
+            moved += delta * t + normal * SKIN;
+            delta = tangent * (delta * (1. - t)).dot(tangent);
+        }
+        moved
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{square, WithOffset};
+
+    /// 4x4 square from (8, 8) to (12, 12), so each edge spans several cells.
+    fn square_wall() -> WallEdges {
+        WallEdges::new(20., &[square(4.).with_offset(Vec2::splat(10.))])
+    }
+
+    #[test]
+    fn stops_at_wall() {
+        let edges = square_wall();
+        let (t, a, b) = edges
+            .first_hit(Vec2::new(5., 10.), Vec2::new(10., 10.))
+            .unwrap();
+        assert!((t - 0.6).abs() < 1e-5);
+        assert_eq!((a, b), (Vec2::new(8., 12.), Vec2::new(8., 8.)));
+
+        let moved = edges.slide(Vec2::new(5., 10.), Vec2::new(5., 0.));
+        assert!(moved.abs_diff_eq(Vec2::new(3. - SKIN, 0.), 1e-5));
+    }
+
+    #[test]
+    fn slides_along_wall() {
+        let edges = square_wall();
+        let moved = edges.slide(Vec2::new(5., 9.), Vec2::new(5., 2.));
+        assert!(moved.abs_diff_eq(Vec2::new(3. - SKIN, 2.), 1e-4));
+        // Not blocked by the wall past its end
+        let moved = edges.slide(Vec2::new(5., 13.), Vec2::new(10., 0.));
+        assert_eq!(moved, Vec2::new(10., 0.));
+    }
+
+    #[test]
+    fn starting_on_edge() {
+        let edges = square_wall();
+        let pos = Vec2::new(8., 10.);
+        assert!(edges.slide(pos, Vec2::new(1., 0.)).x <= 0.);
+        assert_eq!(edges.slide(pos, Vec2::new(-1., 0.)), Vec2::new(-1., 0.));
+        // Agents inside a wall can get out
+        let inside = Vec2::new(10., 10.);
+        assert_eq!(edges.slide(inside, Vec2::new(5., 0.)), Vec2::new(5., 0.));
+    }
+
+    #[test]
+    fn stops_in_corner() {
+        // L shaped wall with the inner corner at (2, 2)
+        let l = vec![
+            Vec2::new(0., 0.),
+            Vec2::new(10., 0.),
+            Vec2::new(10., 2.),
+            Vec2::new(2., 2.),
+            Vec2::new(2., 10.),
+            Vec2::new(0., 10.),
+        ];
+        let edges = WallEdges::new(12., &[l]);
+        let pos = Vec2::new(4., 5.);
+        let end = pos + edges.slide(pos, Vec2::new(-4., -6.));
+        assert!(end.cmpge(Vec2::splat(2.)).all());
+        assert!(end.abs_diff_eq(Vec2::splat(2.), 0.01));
+
+        assert_eq!(
+            edges.closest_point(Vec2::new(4., 3.), 1.5),
+            Some(Vec2::new(4., 2.))
+        );
+        assert_eq!(edges.closest_point(Vec2::new(6., 6.), 1.5), None);
+    }
+}
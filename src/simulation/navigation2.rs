@@ -1,7 +1,7 @@
 use crate::{
     level::{Level, Target},
     statistics::Statistics,
-    utils::{inflate_polygon, is_point_in_polygon, ToVec2, Vertices},
+    utils::{inflate_polygon, is_clockwise, winding_number, ToVec2, Vertices},
 };
 use bevy::{ecs::system::SystemState, prelude::*};
 use ndarray::Array2;
@@ -51,10 +51,20 @@ fn init_nav_grid(mut commands: Commands, level: Res<Level>) {
 
 impl NavGridInner {
     pub fn new(size: f32, walls: &[Vertices]) -> Self {
-        // Expand walls
+        // Expand walls. Holes are clockwise, so inside of a wall the winding numbers sum to 1
         let walls = walls
             .iter()
-            .filter_map(|w| inflate_polygon(w, ENEMY_RADIUS * 1.3))
+            .enumerate()
+            .flat_map(|(i, w)| {
+                inflate_polygon(w, ENEMY_RADIUS * 1.3).unwrap_or_else(|err| {
+                    error!("Failed to inflate wall {i}, using it as is: {err}");
+                    let mut w = w.clone();
+                    if is_clockwise(&w) {
+                        w.reverse();
+                    }
+                    vec![w]
+                })
+            })
             .collect::<Vec<_>>();
 
         let scale = NAV_SCALE;
@@ -63,14 +73,11 @@ impl NavGridInner {
         for x in 1..scaled_size - 1 {
             for y in 1..scaled_size - 1 {
                 let pos = Self::index_to_pos_impl(Vec2::new(x as f32, y as f32));
-                let mut w = true;
-                for vertices in &walls {
-                    if is_point_in_polygon(pos, vertices) {
-                        w = false;
-                        break;
-                    }
-                }
-                walkable[[x, y]] = w;
+                let winding = walls
+                    .iter()
+                    .map(|vertices| winding_number(pos, vertices))
+                    .sum::<i32>();
+                walkable[[x, y]] = winding == 0;
             }
         }
         let mut grid = Array2::from_elem((scaled_size, scaled_size), 0);
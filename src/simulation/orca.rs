@@ -0,0 +1,349 @@
+use bevy::{prelude::*, utils::Instant};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{level::Level, statistics::Statistics, utils::Velocity};
+
+use super::{
+    arrival::{AgentState, Arrival},
+    flocking::array::SpatialStructure,
+    movement::flow_direction,
+    navigation::{FlowField, NavGrid},
+    spawning::{AgentParams, Enemy, ENEMY_RADIUS},
+    SimulationSet, SimulationStep, SimulationTick,
+};
+
+/// Steers agents with optimal reciprocal collision avoidance (ORCA) instead of flocking.
+/// Each agent picks the velocity closest to the flow field direction that doesn't collide
+/// with its neighbors within `TIME_HORIZON`, assuming they do the same.
+/// Walls are still handled by sliding along them.
+pub struct OrcaPlugin;
+
+impl Plugin for OrcaPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init)
+            .add_systems(SimulationStep, movement.in_set(SimulationSet::Movement));
+    }
+}
+
+/// How far into the future collisions with other agents are avoided, in seconds.
+const TIME_HORIZON: f32 = 0.5;
+/// How many grid cells around the agent are searched for neighbors.
+const NEIGHBOR_RINGS: usize = 2;
+/// Neighbors further than this are ignored. Everything closer is always within `NEIGHBOR_RINGS`.
+const NEIGHBOR_DISTANCE_SQ: f32 = 2. * 2.;
+/// Only the closest neighbors are considered, like in RVO2.
+const MAX_NEIGHBORS: usize = 10;
+const EPSILON: f32 = 0.00001;
+
+#[derive(Resource, Deref, DerefMut)]
+struct OrcaGrid(SpatialStructure);
+
+fn init(level: Res<Level>, mut commands: Commands) {
+    println!("USING: orca");
+    commands.insert_resource(OrcaGrid(SpatialStructure::new(level.size)));
+}
+
+fn movement(
+    mut enemy_q: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &AgentParams,
+            &AgentState,
+        ),
+        With<Enemy>,
+    >,
+    nav_grid: Res<NavGrid>,
+    flow_field: Option<Res<FlowField>>,
+    arrival: Res<Arrival>,
+    tick: Res<SimulationTick>,
+    mut grid: ResMut<OrcaGrid>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+    let dt = tick.dt();
+
+    let Some(flow_field) = flow_field.as_ref() else {
+        return;
+    };
+
+    grid.reset();
+    for (entity, transform, ..) in &enemy_q {
+        let pos = transform.translation.truncate();
+        grid.insert_position(entity, pos);
+    }
+
+    stats.add("insert", start.elapsed());
+
+    #[cfg(not(feature = "parallel"))]
+    let iter = grid.grid.iter();
+    #[cfg(feature = "parallel")]
+    let iter = grid.grid.par_iter();
+
+    // All agents choose their velocity based on the same state
+    let new_velocities = iter
+        .filter(|items| !items.is_empty())
+        .map(|items| {
+            let mut neighbors = Vec::new();
+            let mut lines = Vec::new();
+            items
+                .iter()
+                .filter_map(|&(entity, pos, _)| {
+                    let (_, _, velocity, params, state) = enemy_q.get(entity).ok()?;
+
+                    neighbors.clear();
+                    for cell in grid.cells_around(pos, NEIGHBOR_RINGS) {
+                        for &(other_entity, other_pos, _) in &grid.grid[cell] {
+                            let distance_sq = pos.distance_squared(other_pos);
+                            if other_entity == entity || distance_sq > NEIGHBOR_DISTANCE_SQ {
+                                continue;
+                            }
+                            if let Ok((_, _, other_velocity, ..)) = enemy_q.get(other_entity) {
+                                neighbors.push((distance_sq, other_pos, other_velocity.0));
+                            }
+                        }
+                    }
+                    if neighbors.len() > MAX_NEIGHBORS {
+                        neighbors.select_nth_unstable_by(MAX_NEIGHBORS, |a, b| a.0.total_cmp(&b.0));
+                        neighbors.truncate(MAX_NEIGHBORS);
+                    }
+
+                    lines.clear();
+                    lines.extend(neighbors.iter().map(|&(_, other_pos, other_vel)| {
+                        orca_line(pos, velocity.0, other_pos, other_vel, dt)
+                    }));
+
+                    let (direction, max_speed) = arrival.preferred_motion(
+                        entity,
+                        *state,
+                        pos,
+                        flow_direction(flow_field, &nav_grid, pos),
+                        params.max_speed,
+                    );
+                    let preferred = direction * max_speed;
+                    Some((entity, solve(&lines, params.max_speed, preferred)))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, new_vel) in new_velocities.into_iter().flatten() {
+        let Ok((_, mut transform, mut velocity, ..)) = enemy_q.get_mut(entity) else {
+            continue;
+        };
+        let pos = transform.translation.truncate();
+        let moved = nav_grid.slide(pos, new_vel * dt);
+        transform.translation.x += moved.x;
+        transform.translation.y += moved.y;
+        velocity.0 = moved / dt;
+    }
+
+    stats.add("movement", start.elapsed());
+}
+
+/// Half-plane of allowed velocities: everything on the left side of the line.
+#[derive(Clone, Copy)]
+struct Line {
+    point: Vec2,
+    direction: Vec2,
+}
+
+/// Constraint that avoids a collision with one neighbor, taking half of the responsibility.
+fn orca_line(pos: Vec2, vel: Vec2, other_pos: Vec2, other_vel: Vec2, dt: f32) -> Line {
+    let relative_pos = other_pos - pos;
+    let relative_vel = vel - other_vel;
+    let distance_sq = relative_pos.length_squared();
+    let combined_radius = ENEMY_RADIUS * 2.;
+    let combined_radius_sq = combined_radius * combined_radius;
+
+    let (direction, u) = if distance_sq > combined_radius_sq {
+        // Vector from the center of the cut-off circle to the relative velocity
+        let w = relative_vel - relative_pos / TIME_HORIZON;
+        let w_length_sq = w.length_squared();
+        let dot = w.dot(relative_pos);
+
+        if dot < 0. && dot * dot > combined_radius_sq * w_length_sq {
+            // Closest to the cut-off circle
+            let w_length = w_length_sq.sqrt();
+            let unit_w = w / w_length;
+            (
+                Vec2::new(unit_w.y, -unit_w.x),
+                (combined_radius / TIME_HORIZON - w_length) * unit_w,
+            )
+        } else {
+            // Closest to one of the legs of the velocity obstacle cone
+            let leg = (distance_sq - combined_radius_sq).sqrt();
+            let direction = if relative_pos.perp_dot(w) > 0. {
+                Vec2::new(
+                    relative_pos.x * leg - relative_pos.y * combined_radius,
+                    relative_pos.x * combined_radius + relative_pos.y * leg,
+                ) / distance_sq
+            } else {
+                -Vec2::new(
+                    relative_pos.x * leg + relative_pos.y * combined_radius,
+                    -relative_pos.x * combined_radius + relative_pos.y * leg,
+                ) / distance_sq
+            };
+            (
+                direction,
+                relative_vel.dot(direction) * direction - relative_vel,
+            )
+        }
+    } else {
+        // Already colliding, get apart during this step
+        let w = relative_vel - relative_pos / dt;
+        let w_length = w.length();
+        let unit_w = if w_length > 0. { w / w_length } else { Vec2::X };
+        (
+            Vec2::new(unit_w.y, -unit_w.x),
+            (combined_radius / dt - w_length) * unit_w,
+        )
+    };
+
+    Line {
+        point: vel + 0.5 * u,
+        direction,
+    }
+}
+
+/// Returns the velocity closest to `preferred` that satisfies all `lines` and `max_speed`.
+/// If that is impossible, the velocity that violates the lines the least is returned.
+fn solve(lines: &[Line], max_speed: f32, preferred: Vec2) -> Vec2 {
+    let mut result = Vec2::ZERO;
+    let failed = linear_program2(lines, max_speed, preferred, false, &mut result);
+    if failed < lines.len() {
+        linear_program3(lines, failed, max_speed, &mut result);
+    }
+    result
+}
+
+/// Optimizes along line `line_no` while satisfying the previous lines.
+fn linear_program1(
+    lines: &[Line],
+    line_no: usize,
+    radius: f32,
+    opt_velocity: Vec2,
+    direction_opt: bool,
+    result: &mut Vec2,
+) -> bool {
+    let line = lines[line_no];
+    let dot = line.point.dot(line.direction);
+    let discriminant = dot * dot + radius * radius - line.point.length_squared();
+    if discriminant < 0. {
+        // The max speed circle doesn't reach the line
+        return false;
+    }
+
+    let sqrt_discriminant = discriminant.sqrt();
+    let mut t_left = -dot - sqrt_discriminant;
+    let mut t_right = -dot + sqrt_discriminant;
+
+    for other in &lines[..line_no] {
+        let denominator = line.direction.perp_dot(other.direction);
+        let numerator = other.direction.perp_dot(line.point - other.point);
+
+        if denominator.abs() <= EPSILON {
+            // Parallel lines
+            if numerator < 0. {
+                return false;
+            }
+            continue;
+        }
+
+        let t = numerator / denominator;
+        if denominator >= 0. {
+            t_right = t_right.min(t);
+        } else {
+            t_left = t_left.max(t);
+        }
+
+        if t_left > t_right {
+            return false;
+        }
+    }
+
+    let t = if direction_opt {
+        if opt_velocity.dot(line.direction) > 0. {
+            t_right
+        } else {
+            t_left
+        }
+    } else {
+        line.direction
+            .dot(opt_velocity - line.point)
+            .clamp(t_left, t_right)
+    };
+    *result = line.point + t * line.direction;
+    true
+}
+
+/// Returns the index of the first line that couldn't be satisfied, or `lines.len()` on success.
+fn linear_program2(
+    lines: &[Line],
+    radius: f32,
+    opt_velocity: Vec2,
+    direction_opt: bool,
+    result: &mut Vec2,
+) -> usize {
+    *result = if direction_opt {
+        // `opt_velocity` is a unit direction here
+        opt_velocity * radius
+    } else if opt_velocity.length_squared() > radius * radius {
+        opt_velocity.normalize() * radius
+    } else {
+        opt_velocity
+    };
+
+    for (i, line) in lines.iter().enumerate() {
+        if line.direction.perp_dot(line.point - *result) > 0. {
+            let previous = *result;
+            if !linear_program1(lines, i, radius, opt_velocity, direction_opt, result) {
+                *result = previous;
+                return i;
+            }
+        }
+    }
+    lines.len()
+}
+
+/// Minimizes the largest violation of the lines starting from `begin_line`.
+fn linear_program3(lines: &[Line], begin_line: usize, radius: f32, result: &mut Vec2) {
+    let mut distance = 0.;
+    let mut projected = Vec::with_capacity(lines.len());
+
+    for (i, line) in lines.iter().enumerate().skip(begin_line) {
+        if line.direction.perp_dot(line.point - *result) <= distance {
+            continue;
+        }
+
+        projected.clear();
+        for other in &lines[..i] {
+            let determinant = line.direction.perp_dot(other.direction);
+            let point = if determinant.abs() <= EPSILON {
+                if line.direction.dot(other.direction) > 0. {
+                    // Same direction, already covered by `line`
+                    continue;
+                }
+                0.5 * (line.point + other.point)
+            } else {
+                line.point
+                    + other.direction.perp_dot(line.point - other.point) / determinant
+                        * line.direction
+            };
+            projected.push(Line {
+                point,
+                direction: (other.direction - line.direction).normalize_or_zero(),
+            });
+        }
+
+        let previous = *result;
+        let direction = Vec2::new(-line.direction.y, line.direction.x);
+        if linear_program2(&projected, radius, direction, true, result) < projected.len() {
+            // Can only fail because of floating point errors, keep the previous result
+            *result = previous;
+        }
+        distance = line.direction.perp_dot(line.point - *result);
+    }
+}
@@ -0,0 +1,191 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use clap::ValueEnum;
+use rand::Rng;
+
+use crate::{
+    level::{Level, LevelStartupSet},
+    utils::spatial,
+};
+
+use super::{
+    movement::ENEMY_SPEED,
+    navigation::{NavGrid, NavGridInner},
+    rng::FastRng,
+    spawning::Enemy,
+    SimulationSet, SimulationTick,
+};
+
+/// A player that the agents chase. The player replaces the targets as the source
+/// of the flow field, but agents still leave through the targets.
+pub struct PlayerPlugin {
+    pub control: PlayerControl,
+}
+
+impl Plugin for PlayerPlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(PreStartup, spawn_player.after(LevelStartupSet::Spawn));
+
+        match self.control {
+            PlayerControl::Keyboard => {
+                app.add_systems(
+                    PreUpdate,
+                    move_with_keyboard.in_set(SimulationSet::MoveTargets),
+                );
+            }
+            PlayerControl::Bot => {
+                app.add_systems(PreUpdate, move_bot.in_set(SimulationSet::MoveTargets));
+            }
+        }
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PlayerControl {
+    /// Moved with WASD or the arrow keys in the viewer.
+    Keyboard,
+    /// Runs away from nearby agents and wanders around otherwise. Deterministic, so it
+    /// can be benchmarked.
+    Bot,
+}
+
+#[derive(Component, Debug)]
+pub struct Player;
+
+/// A bit faster than the agents, so it isn't caught right away.
+const PLAYER_SPEED: f32 = ENEMY_SPEED * 1.2;
+/// The bot runs from agents closer than this.
+const FLEE_RADIUS: f32 = 12.;
+/// Directions the bot considers moving in.
+const BOT_HEADINGS: usize = 16;
+/// Directions that are blocked by walls this close are not considered.
+const BOT_LOOKAHEAD: f32 = 1.5;
+/// Radians per second the wandering bot turns at most.
+const WANDER_TURN: f32 = 6.;
+/// Largest angle between the heading and the closest allowed direction.
+const MAX_HEADING_SNAP: f32 = TAU / BOT_HEADINGS as f32 / 2.;
+
+/// Starts at the first target of the level, or the middle if there are none.
+fn spawn_player(mut commands: Commands, level: Res<Level>) {
+    let pos = level
+        .targets
+        .first()
+        .copied()
+        .unwrap_or(Vec2::splat(level.size / 2.));
+    commands.spawn((Player, spatial(pos, 3.)));
+}
+
+fn step(transform: &mut Transform, nav_grid: &NavGridInner, direction: Vec2, dt: f32) {
+    let pos = transform.translation.truncate();
+    let moved = nav_grid.slide(pos, direction * PLAYER_SPEED * dt);
+    transform.translation.x += moved.x;
+    transform.translation.y += moved.y;
+}
+
+fn move_with_keyboard(
+    mut player_q: Query<&mut Transform, With<Player>>,
+    keys: Res<ButtonInput<KeyCode>>,
+    nav_grid: Res<NavGrid>,
+    tick: Res<SimulationTick>,
+) {
+    let mut direction = Vec2::ZERO;
+    for (codes, dir) in [
+        ([KeyCode::KeyW, KeyCode::ArrowUp], Vec2::Y),
+        ([KeyCode::KeyS, KeyCode::ArrowDown], Vec2::NEG_Y),
+        ([KeyCode::KeyA, KeyCode::ArrowLeft], Vec2::NEG_X),
+        ([KeyCode::KeyD, KeyCode::ArrowRight], Vec2::X),
+    ] {
+        if keys.any_pressed(codes) {
+            direction += dir;
+        }
+    }
+
+    for mut transform in &mut player_q {
+        step(
+            &mut transform,
+            &nav_grid,
+            direction.normalize_or_zero(),
+            tick.length,
+        );
+    }
+}
+
+fn move_bot(
+    mut player_q: Query<&mut Transform, With<Player>>,
+    enemy_q: Query<&Transform, (With<Enemy>, Without<Player>)>,
+    nav_grid: Res<NavGrid>,
+    tick: Res<SimulationTick>,
+    mut heading: Local<Vec2>,
+    mut rng: Local<FastRng>,
+) {
+    if *heading == Vec2::ZERO {
+        *heading = Vec2::from_angle(rng.gen_range(0. ..TAU));
+    }
+
+    for mut transform in &mut player_q {
+        let pos = transform.translation.truncate();
+        let agents = enemy_q.iter().map(|t| t.translation.truncate());
+        let can_move =
+            |dir: Vec2| nav_grid.slide(pos, dir * BOT_LOOKAHEAD).length() > BOT_LOOKAHEAD * 0.5;
+        let direction = bot_direction(pos, *heading, agents, can_move);
+
+        // Keeps the heading between the allowed directions while wandering freely,
+        // so it can drift. Cornered, it tries somewhere else next tick
+        let turn = rng.gen_range(-1. ..1.) * WANDER_TURN * tick.length;
+        *heading = if direction == Vec2::ZERO {
+            Vec2::from_angle(rng.gen_range(0. ..TAU))
+        } else if direction.dot(*heading) < MAX_HEADING_SNAP.cos() {
+            Vec2::from_angle(turn).rotate(direction)
+        } else {
+            Vec2::from_angle(turn).rotate(*heading)
+        };
+
+        step(&mut transform, &nav_grid, direction, tick.length);
+    }
+}
+
+/// Direction away from the agents near `pos`, or `heading` if there are none.
+/// Picks the closest one of `BOT_HEADINGS` directions that `can_move` allows, so the bot
+/// turns along walls instead of running into them.
+fn bot_direction(
+    pos: Vec2,
+    heading: Vec2,
+    agents: impl Iterator<Item = Vec2>,
+    can_move: impl Fn(Vec2) -> bool,
+) -> Vec2 {
+    // Closer agents are more urgent to get away from
+    let threat = agents
+        .filter_map(|agent| {
+            let away = pos - agent;
+            let distance = away.length();
+            (distance > 0. && distance < FLEE_RADIUS).then(|| away / (distance * distance))
+        })
+        .sum::<Vec2>();
+    let desired = threat.try_normalize().unwrap_or(heading);
+
+    (0..BOT_HEADINGS)
+        .map(|i| Vec2::from_angle(TAU * i as f32 / BOT_HEADINGS as f32))
+        .filter(|&dir| can_move(dir))
+        .max_by(|a, b| a.dot(desired).total_cmp(&b.dot(desired)))
+        .unwrap_or(Vec2::ZERO)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bot_runs_from_agents_along_walls() {
+        let agents = [Vec2::new(-2., 0.), Vec2::new(-3., 1.)];
+        let free = bot_direction(Vec2::ZERO, Vec2::Y, agents.into_iter(), |_| true);
+        assert!(free.x > 0.9);
+
+        // Wall on the right
+        let blocked = bot_direction(Vec2::ZERO, Vec2::Y, agents.into_iter(), |dir| dir.x < 0.1);
+        assert!(blocked.x.abs() < 0.1);
+
+        let wandering = bot_direction(Vec2::ZERO, Vec2::Y, std::iter::empty(), |_| true);
+        assert!(wandering.abs_diff_eq(Vec2::Y, 1e-4));
+    }
+}
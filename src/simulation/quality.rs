@@ -0,0 +1,349 @@
+use std::{f32::consts::PI, fs};
+
+use bevy::{
+    prelude::*,
+    utils::{HashMap, Instant},
+};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+use serde::Serialize;
+
+use crate::{
+    level::Level,
+    statistics::{is_exiting, Statistics, StatisticsSettings},
+    utils::Velocity,
+};
+
+use super::{
+    arrival::AgentExited,
+    flocking::{array::SpatialStructure, MAX_PREFERRED_DISTANCE},
+    navigation::{FlowField, NavGrid, NAV_SCALE},
+    spawning::{Enemy, ENEMY_RADIUS},
+    SimulationSet, SimulationTick,
+};
+
+/// Measures how the crowd behaves instead of how fast it is simulated, so algorithms
+/// can be checked for changed behaviour. Written next to the statistics, e.g. to
+/// statistics-quality.json.
+pub struct QualityPlugin;
+
+impl Plugin for QualityPlugin {
+    fn build(&self, app: &mut App) {
+        app.init_resource::<QualityMetrics>()
+            .add_systems(Startup, init)
+            .add_systems(
+                PreUpdate,
+                (
+                    start_journeys
+                        .after(SimulationSet::GenNavigation)
+                        .before(SimulationSet::Step),
+                    record.after(SimulationSet::Step),
+                ),
+            )
+            .add_systems(Last, write_quality_metrics.run_if(is_exiting));
+    }
+}
+
+/// Local density is the number of agents within this distance divided by the area.
+const DENSITY_RADIUS: f32 = 2.;
+const DENSITY_BIN: f32 = 0.25;
+const OVERLAP_BIN: f32 = 0.05;
+
+/// Counts of values in bins of equal width starting from zero.
+#[derive(Serialize, Debug)]
+struct Histogram {
+    bin_width: f32,
+    counts: Vec<u32>,
+}
+
+impl Histogram {
+    fn new(bin_width: f32) -> Self {
+        Self {
+            bin_width,
+            counts: Vec::new(),
+        }
+    }
+
+    /// Returns the index of the bin that `value` went to.
+    fn add(&mut self, value: f32) -> usize {
+        let bin = (value.max(0.) / self.bin_width) as usize;
+        if bin >= self.counts.len() {
+            self.counts.resize(bin + 1, 0);
+        }
+        self.counts[bin] += 1;
+        bin
+    }
+}
+
+/// Written next to the statistics, e.g. to statistics-quality.json.
+#[derive(Resource, Serialize)]
+struct QualityMetrics {
+    /// Overlaps of every pair of overlapping agents on every tick.
+    overlaps: Histogram,
+    /// Agents per square unit around every agent on every tick.
+    local_density: Histogram,
+    /// Mean speed of agents at each local density.
+    fundamental_diagram: Vec<DiagramPoint>,
+    /// Every agent that reached a target.
+    journeys: Vec<Journey>,
+    /// Agents that were still in the level at the end.
+    unfinished: u32,
+    /// Speeds summed in the bins of `local_density`.
+    #[serde(skip)]
+    speed_sums: Vec<f64>,
+    #[serde(skip)]
+    active: HashMap<Entity, ActiveJourney>,
+    /// Seconds at the start of the current tick, summed in f64 so long runs don't drift.
+    #[serde(skip)]
+    time: f64,
+    #[serde(skip)]
+    ticks: u32,
+}
+
+impl Default for QualityMetrics {
+    fn default() -> Self {
+        Self {
+            overlaps: Histogram::new(OVERLAP_BIN),
+            local_density: Histogram::new(DENSITY_BIN),
+            fundamental_diagram: Vec::new(),
+            journeys: Vec::new(),
+            unfinished: 0,
+            speed_sums: Vec::new(),
+            active: HashMap::new(),
+            time: 0.,
+            ticks: 0,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct DiagramPoint {
+    /// Middle of the density bin.
+    density: f32,
+    mean_speed: f32,
+    samples: u32,
+}
+
+#[derive(Serialize)]
+struct Journey {
+    /// Seconds from spawning to reaching a target.
+    time: f32,
+    /// Distance actually moved.
+    path_length: f32,
+    /// Distance to a target along the flow field at spawn. `None` if no target was reachable.
+    /// Grid steps can make it a bit longer than the shortest path, and with `--density-nav`
+    /// it includes the extra cost of crowded cells.
+    flow_distance: Option<f32>,
+}
+
+struct ActiveJourney {
+    start_time: f64,
+    last_pos: Vec2,
+    path_length: f32,
+    flow_distance: Option<f32>,
+}
+
+#[derive(Resource, Deref, DerefMut)]
+struct QualityGrid(SpatialStructure);
+
+fn init(level: Res<Level>, mut commands: Commands) {
+    // Every pair of overlapping agents is counted, also in jammed cells
+    commands.insert_resource(QualityGrid(SpatialStructure::uncapped(level.size)));
+}
+
+/// Runs after the flow field is generated, so agents spawned this tick can be looked up in it.
+fn start_journeys(
+    enemy_q: Query<(Entity, &Transform), Added<Enemy>>,
+    nav_grid: Res<NavGrid>,
+    flow_field: Res<FlowField>,
+    mut metrics: ResMut<QualityMetrics>,
+) {
+    let metrics = &mut *metrics;
+    for (entity, transform) in &enemy_q {
+        let pos = transform.translation.truncate();
+        let flow_distance = flow_field
+            .0
+            .get(nav_grid.pos_to_index(pos))
+            .map(|(distance, _)| distance * NAV_SCALE)
+            .filter(|distance| distance.is_finite());
+        metrics.active.insert(
+            entity,
+            ActiveJourney {
+                start_time: metrics.time,
+                last_pos: pos,
+                path_length: 0.,
+                flow_distance,
+            },
+        );
+    }
+}
+
+/// Density and speed around one agent and its overlaps with the agents after it.
+struct Sample {
+    density: f32,
+    speed: f32,
+    overlaps: Vec<f32>,
+}
+
+fn record(
+    enemy_q: Query<(Entity, &Transform, &Velocity), With<Enemy>>,
+    tick: Res<SimulationTick>,
+    mut exits: EventReader<AgentExited>,
+    mut grid: ResMut<QualityGrid>,
+    mut metrics: ResMut<QualityMetrics>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+    let metrics = &mut *metrics;
+
+    // Agents exit at the start of the tick, where they were at the end of the previous one
+    for exit in exits.read() {
+        if let Some(journey) = metrics.active.remove(&exit.agent) {
+            metrics.journeys.push(Journey {
+                time: (metrics.time - journey.start_time) as f32,
+                path_length: journey.path_length,
+                flow_distance: journey.flow_distance,
+            });
+        }
+    }
+    metrics.time += tick.length as f64;
+    metrics.ticks += 1;
+
+    grid.reset();
+    let mut agents = Vec::with_capacity(enemy_q.iter().len());
+    for (entity, transform, velocity) in &enemy_q {
+        let pos = transform.translation.truncate();
+        if let Some(journey) = metrics.active.get_mut(&entity) {
+            journey.path_length += journey.last_pos.distance(pos);
+            journey.last_pos = pos;
+        }
+        grid.insert_position(entity, pos);
+        agents.push((entity, pos, velocity.0.length()));
+    }
+
+    #[cfg(not(feature = "parallel"))]
+    let iter = agents.iter();
+    #[cfg(feature = "parallel")]
+    let iter = agents.par_iter();
+
+    let samples = iter
+        .map(|&(entity, pos, speed)| sample(&grid, entity, pos, speed))
+        .collect::<Vec<_>>();
+
+    for sample in samples {
+        let bin = metrics.local_density.add(sample.density);
+        if bin >= metrics.speed_sums.len() {
+            metrics.speed_sums.resize(bin + 1, 0.);
+        }
+        metrics.speed_sums[bin] += sample.speed as f64;
+        for overlap in sample.overlaps {
+            metrics.overlaps.add(overlap);
+        }
+    }
+
+    stats.add("quality", start.elapsed());
+}
+
+fn sample(grid: &SpatialStructure, entity: Entity, pos: Vec2, speed: f32) -> Sample {
+    let rings = (DENSITY_RADIUS / MAX_PREFERRED_DISTANCE).ceil() as usize;
+    let mut nearby = 0;
+    let mut overlaps = Vec::new();
+    for cell in grid.cells_around(pos, rings) {
+        for &(other_entity, other_pos, _) in &grid.grid[cell] {
+            let distance = pos.distance(other_pos);
+            if distance < DENSITY_RADIUS {
+                nearby += 1;
+            }
+            // Each pair once
+            if other_entity > entity && distance < ENEMY_RADIUS * 2. {
+                overlaps.push(ENEMY_RADIUS * 2. - distance);
+            }
+        }
+    }
+    Sample {
+        // Includes the agent itself
+        density: nearby as f32 / (PI * DENSITY_RADIUS * DENSITY_RADIUS),
+        speed,
+        overlaps,
+    }
+}
+
+fn write_quality_metrics(
+    enemy_q: Query<(), With<Enemy>>,
+    statistics: Res<StatisticsSettings>,
+    mut metrics: ResMut<QualityMetrics>,
+) {
+    let metrics = &mut *metrics;
+    metrics.unfinished = enemy_q.iter().len() as u32;
+    metrics.fundamental_diagram = metrics
+        .local_density
+        .counts
+        .iter()
+        .zip(&metrics.speed_sums)
+        .enumerate()
+        .filter(|(_, (&samples, _))| samples > 0)
+        .map(|(bin, (&samples, &speed_sum))| DiagramPoint {
+            density: (bin as f32 + 0.5) * DENSITY_BIN,
+            mean_speed: (speed_sum / samples as f64) as f32,
+            samples,
+        })
+        .collect();
+
+    fs::write(
+        statistics.path_next_to_output("quality", "json"),
+        serde_json::to_string(&*metrics).unwrap(),
+    )
+    .unwrap();
+
+    let journeys = metrics.journeys.len().max(1) as f32;
+    let mean_time = metrics.journeys.iter().map(|j| j.time).sum::<f32>() / journeys;
+    let detours = metrics
+        .journeys
+        .iter()
+        .filter_map(|j| Some(j.path_length / j.flow_distance.filter(|&d| d > 0.)?))
+        .collect::<Vec<_>>();
+    let mean_detour = detours.iter().sum::<f32>() / detours.len().max(1) as f32;
+    let overlapping = metrics.overlaps.counts.iter().sum::<u32>();
+    println!(
+        "{} agents reached a target in {mean_time:.2} s on average, moving {mean_detour:.3} times the flow field distance",
+        metrics.journeys.len(),
+    );
+    println!(
+        "{} agents remained, {:.1} overlapping pairs per tick",
+        metrics.unfinished,
+        overlapping as f32 / metrics.ticks.max(1) as f32
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn counts_agents_nearby() {
+        let mut grid = SpatialStructure::new(20.);
+        let agents = [
+            Vec2::new(10., 10.),
+            Vec2::new(10.6, 10.),
+            Vec2::new(11.5, 10.),
+        ]
+        .into_iter()
+        .enumerate()
+        .map(|(i, pos)| (Entity::from_raw(i as u32), pos))
+        .collect::<Vec<_>>();
+        for &(entity, pos) in &agents {
+            grid.insert_position(entity, pos);
+        }
+
+        let (entity, pos) = agents[0];
+        let first = sample(&grid, entity, pos, 1.);
+        assert!((first.density - 3. / (PI * 4.)).abs() < 1e-5);
+        assert_eq!(first.overlaps.len(), 1);
+        assert!((first.overlaps[0] - 0.4).abs() < 1e-5);
+        // The pair with the first agent was already counted
+        let (entity, pos) = agents[1];
+        let overlaps = sample(&grid, entity, pos, 1.).overlaps;
+        assert_eq!(overlaps.len(), 1);
+        assert!((overlaps[0] - 0.1).abs() < 1e-5);
+    }
+}
@@ -0,0 +1,150 @@
+use bevy::{prelude::*, utils::Instant};
+#[cfg(feature = "parallel")]
+use rayon::iter::{IntoParallelRefIterator, ParallelIterator};
+
+use crate::{level::Level, statistics::Statistics, utils::Velocity};
+
+use super::{
+    arrival::{AgentState, Arrival},
+    flocking::array::SpatialStructure,
+    movement::flow_direction,
+    navigation::{FlowField, NavGrid},
+    spawning::{AgentParams, Enemy, ENEMY_RADIUS},
+    SimulationSet, SimulationStep, SimulationTick,
+};
+
+/// Steers agents with the social force model of Helbing and Molnár instead of flocking.
+/// Agents accelerate towards the flow field direction and are pushed away from their neighbors
+/// and the closest wall with forces that decay exponentially with distance.
+pub struct SocialForcePlugin;
+
+impl Plugin for SocialForcePlugin {
+    fn build(&self, app: &mut App) {
+        app.add_systems(Startup, init)
+            .add_systems(SimulationStep, movement.in_set(SimulationSet::Movement));
+    }
+}
+
+/// How fast agents adapt to the desired velocity, in seconds.
+/// The driving force is still limited by the acceleration of the agent.
+const RELAXATION_TIME: f32 = 0.5;
+/// Agents can be pushed a bit over their desired speed.
+const MAX_SPEED_FACTOR: f32 = 1.3;
+const AGENT_STRENGTH: f32 = 25.;
+/// Distance in which the repulsion from other agents decays by a factor of e.
+const AGENT_RANGE: f32 = 0.3;
+const WALL_STRENGTH: f32 = 25.;
+const WALL_RANGE: f32 = 0.2;
+/// How many grid cells around the agent are searched for neighbors.
+const NEIGHBOR_RINGS: usize = 2;
+/// Repulsion is negligible further than this. Everything closer is within `NEIGHBOR_RINGS`.
+const NEIGHBOR_DISTANCE: f32 = 2.;
+const WALL_DISTANCE: f32 = 1.;
+
+#[derive(Resource, Deref, DerefMut)]
+struct SocialForceGrid(SpatialStructure);
+
+fn init(level: Res<Level>, mut commands: Commands) {
+    println!("USING: social force");
+    commands.insert_resource(SocialForceGrid(SpatialStructure::new(level.size)));
+}
+
+fn movement(
+    mut enemy_q: Query<
+        (
+            Entity,
+            &mut Transform,
+            &mut Velocity,
+            &AgentParams,
+            &AgentState,
+        ),
+        With<Enemy>,
+    >,
+    nav_grid: Res<NavGrid>,
+    flow_field: Option<Res<FlowField>>,
+    arrival: Res<Arrival>,
+    tick: Res<SimulationTick>,
+    mut grid: ResMut<SocialForceGrid>,
+    mut stats: ResMut<Statistics>,
+) {
+    let start = Instant::now();
+    let dt = tick.dt();
+
+    let Some(flow_field) = flow_field.as_ref() else {
+        return;
+    };
+
+    grid.reset();
+    for (entity, transform, ..) in &enemy_q {
+        let pos = transform.translation.truncate();
+        grid.insert_position(entity, pos);
+    }
+
+    stats.add("insert", start.elapsed());
+
+    #[cfg(not(feature = "parallel"))]
+    let iter = grid.grid.iter();
+    #[cfg(feature = "parallel")]
+    let iter = grid.grid.par_iter();
+
+    // Forces are calculated from the same state for all agents
+    let accelerations = iter
+        .filter(|items| !items.is_empty())
+        .map(|items| {
+            items
+                .iter()
+                .filter_map(|&(entity, pos, _)| {
+                    let (_, _, velocity, params, state) = enemy_q.get(entity).ok()?;
+
+                    let (direction, max_speed) = arrival.preferred_motion(
+                        entity,
+                        *state,
+                        pos,
+                        flow_direction(flow_field, &nav_grid, pos),
+                        params.max_speed,
+                    );
+                    let desired = direction * max_speed;
+                    let mut acceleration = ((desired - velocity.0) / RELAXATION_TIME)
+                        .clamp_length_max(params.acceleration);
+
+                    for cell in grid.cells_around(pos, NEIGHBOR_RINGS) {
+                        for &(other_entity, other_pos, _) in &grid.grid[cell] {
+                            let diff = pos - other_pos;
+                            let distance = diff.length();
+                            if other_entity == entity || distance > NEIGHBOR_DISTANCE {
+                                continue;
+                            }
+                            let magnitude = AGENT_STRENGTH
+                                * ((ENEMY_RADIUS * 2. - distance) / AGENT_RANGE).exp();
+                            acceleration += magnitude * diff.normalize_or_zero();
+                        }
+                    }
+
+                    // Walls are already inflated by the agent radius
+                    if let Some(point) = nav_grid.closest_wall_point(pos, WALL_DISTANCE) {
+                        let diff = pos - point;
+                        let magnitude = WALL_STRENGTH * (-diff.length() / WALL_RANGE).exp();
+                        acceleration += magnitude * diff.normalize_or_zero();
+                    }
+
+                    Some((entity, acceleration))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect::<Vec<_>>();
+
+    for (entity, acceleration) in accelerations.into_iter().flatten() {
+        let Ok((_, mut transform, mut velocity, params, _)) = enemy_q.get_mut(entity) else {
+            continue;
+        };
+        let new_vel =
+            (velocity.0 + acceleration * dt).clamp_length_max(params.max_speed * MAX_SPEED_FACTOR);
+        let pos = transform.translation.truncate();
+        let moved = nav_grid.slide(pos, new_vel * dt);
+        transform.translation.x += moved.x;
+        transform.translation.y += moved.y;
+        velocity.0 = moved / dt;
+    }
+
+    stats.add("movement", start.elapsed());
+}
@@ -1,103 +1,207 @@
-use bevy::prelude::*;
+use std::ops::RangeInclusive;
+
+use anyhow::bail;
+use bevy::{prelude::*, utils::Instant};
 use bevy_rapier2d::geometry::Collider;
-use rand::{seq::IteratorRandom, Rng, RngCore};
+use rand::{Rng, RngCore};
 
 use crate::{
-    level::{SpawnPoint, Target},
+    level::Level,
+    statistics::Statistics,
     utils::{spatial, Velocity},
+    REFERENCE_DT,
 };
 
-use super::{navigation::NavGrid, rng::FastRng, SimulationSet};
+pub(super) mod placement;
+mod schedule;
+
+pub use schedule::{Repeat, SpawnSchedule, SpawnWave};
 
-pub struct SpawningPlugin;
+use super::{
+    arrival::AgentState,
+    flocking::{MAX_PREFERRED_DISTANCE, PREFERRED_DISTANCE},
+    movement::{ENEMY_ACCELERATION, ENEMY_SPEED},
+    navigation::NavGrid,
+    rng::FastRng,
+    SimulationSet, SimulationTick,
+};
+
+pub struct SpawningPlugin {
+    pub agent_params: AgentParamsDistribution,
+    pub schedule: SpawnSchedule,
+}
 
 impl Plugin for SpawningPlugin {
     fn build(&self, app: &mut App) {
-        app.add_systems(
-            PreUpdate,
-            (
-                spawn_enemies.in_set(SimulationSet::Spawn),
-                despawn_on_target_enemies.in_set(SimulationSet::Despawn),
-            ),
-        );
+        app.insert_resource(self.agent_params.clone())
+            .insert_resource(self.schedule.clone())
+            .add_systems(PreUpdate, spawn_enemies.in_set(SimulationSet::Spawn));
     }
 }
 
 #[derive(Component, Debug)]
 pub struct Enemy;
 
+/// Movement parameters of one agent, so crowds can mix fast and slow agents.
+#[derive(Component, Clone, Copy, Debug)]
+pub struct AgentParams {
+    pub max_speed: f32,
+    /// How much the velocity can change per second.
+    pub acceleration: f32,
+    /// Distance to other agents that flocking tries to keep.
+    pub preferred_spacing: f32,
+}
+
+/// Ranges that the parameters of spawned agents are picked from uniformly.
+#[derive(Resource, Clone, Debug)]
+pub struct AgentParamsDistribution {
+    pub max_speed: RangeInclusive<f32>,
+    pub acceleration: RangeInclusive<f32>,
+    pub preferred_spacing: RangeInclusive<f32>,
+}
+
+impl Default for AgentParamsDistribution {
+    fn default() -> Self {
+        Self {
+            max_speed: ENEMY_SPEED..=ENEMY_SPEED,
+            acceleration: ENEMY_ACCELERATION..=ENEMY_ACCELERATION,
+            preferred_spacing: PREFERRED_DISTANCE..=PREFERRED_DISTANCE,
+        }
+    }
+}
+
+impl AgentParamsDistribution {
+    pub fn sample(&self, rng: &mut impl RngCore) -> AgentParams {
+        // Doesn't touch the rng with constant parameters to keep spawning the same as before
+        let mut sample = |range: &RangeInclusive<f32>| {
+            if range.start() < range.end() {
+                rng.gen_range(range.clone())
+            } else {
+                *range.start()
+            }
+        };
+        AgentParams {
+            max_speed: sample(&self.max_speed),
+            acceleration: sample(&self.acceleration),
+            preferred_spacing: sample(&self.preferred_spacing),
+        }
+    }
+
+    /// Neighbors further away than `MAX_PREFERRED_DISTANCE` are not found,
+    /// so agents couldn't keep a larger spacing.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        if *self.preferred_spacing.end() > MAX_PREFERRED_DISTANCE {
+            bail!(
+                "Preferred spacing can be at most {MAX_PREFERRED_DISTANCE}, got {}",
+                self.preferred_spacing.end()
+            );
+        }
+        Ok(())
+    }
+}
+
 #[derive(Bundle)]
 pub struct EnemyBundle {
     pub enemy: Enemy,
     pub collider: Collider,
     pub spatial: SpatialBundle,
     pub velocity: Velocity,
+    pub params: AgentParams,
+    pub state: AgentState,
 }
 
 impl EnemyBundle {
-    pub fn new(pos: Vec2, rng: &mut impl RngCore) -> Self {
-        let offset = Vec2::new(rng.gen_range(-0.5..0.5), rng.gen_range(-0.5..0.5));
+    pub fn new(pos: Vec2, params: AgentParams, rng: &mut impl RngCore) -> Self {
         EnemyBundle {
             enemy: Enemy,
             collider: Collider::ball(ENEMY_RADIUS),
-            spatial: spatial(pos + offset, rng.gen_range(1. ..2.)),
+            spatial: spatial(pos, rng.gen_range(1. ..2.)),
             velocity: Velocity::default(),
+            params,
+            state: AgentState::default(),
         }
     }
 }
 
 pub const ENEMY_RADIUS: f32 = 0.5;
 
+/// Distance between the centers of new agents, so they don't start overlapping.
+pub(super) const SPAWN_SPACING: f32 = ENEMY_RADIUS * 2.;
+
+/// Maximum number of agents alive at the same time.
 pub const MAX_ENEMIES: u32 = 10_000;
 
-const SPAWN_PER_TICK: u32 = 300;
+#[derive(Default)]
+struct ScheduleProgress {
+    /// In ticks of `REFERENCE_DT`
+    tick: f32,
+    /// Agents spawned by each wave so far
+    spawned: Vec<u32>,
+}
 
 fn spawn_enemies(
-    spawn_point_q: Query<&Transform, With<SpawnPoint>>,
+    level: Res<Level>,
+    schedule: Res<SpawnSchedule>,
+    enemy_q: Query<&Transform, With<Enemy>>,
+    agent_params: Res<AgentParamsDistribution>,
+    nav_grid: Res<NavGrid>,
+    tick: Res<SimulationTick>,
     mut commands: Commands,
     mut rng: Local<FastRng>,
-    mut count: Local<u32>,
+    mut progress: Local<ScheduleProgress>,
+    mut stats: ResMut<Statistics>,
 ) {
-    if *count >= MAX_ENEMIES {
-        return;
-    }
-    *count += SPAWN_PER_TICK;
+    let start = Instant::now();
 
-    for _ in 0..SPAWN_PER_TICK {
-        let Some(spawn_point) = spawn_point_q.iter().choose(&mut rng.0) else {
-            return;
-        };
+    let progress = &mut *progress;
+    progress.spawned.resize(schedule.waves.len(), 0);
+    let now = progress.tick;
+    progress.tick += tick.length / REFERENCE_DT;
 
-        commands.spawn(EnemyBundle::new(
-            spawn_point.translation.truncate(),
-            &mut rng.0,
-        ));
-    }
-}
+    // Agents that don't fit are spawned later when others have despawned
+    let mut room = MAX_ENEMIES.saturating_sub(enemy_q.iter().len() as u32);
 
-fn despawn_on_target_enemies(
-    target_q: Query<&Transform, With<Target>>,
-    enemy_q: Query<(Entity, &Transform), With<Enemy>>,
-    nav_grid: Option<Res<NavGrid>>,
-    nav_grid2: Option<Res<NavGrid>>,
-    mut commands: Commands,
-) {
-    let pos_to_index = |pos: Vec2| {
-        if let Some(nav_grid) = &nav_grid {
-            nav_grid.pos_to_index(pos)
-        } else {
-            nav_grid2.as_ref().unwrap().pos_to_index(pos)
-        }
+    let is_walkable = |pos: Vec2| {
+        nav_grid
+            .walkable
+            .get(nav_grid.pos_to_index(pos))
+            .copied()
+            .unwrap_or(false)
     };
-    let target_indices = target_q
-        .iter()
-        .map(|t| pos_to_index(t.translation.truncate()))
-        .collect::<Vec<_>>();
-
-    for (entity, transform) in enemy_q.iter() {
-        let nav_idx = pos_to_index(transform.translation.truncate());
-        if target_indices.contains(&nav_idx) {
-            commands.entity(entity).despawn();
+    // Collected only when something is spawned
+    let mut occupied: Option<Vec<Vec2>> = None;
+
+    for (wave, spawned) in schedule.waves.iter().zip(&mut progress.spawned) {
+        let count = wave.spawned_by(now).saturating_sub(*spawned).min(room);
+        if count == 0 {
+            continue;
+        }
+
+        let counts = wave.split(count, level.spawn_points.len(), &mut rng.0);
+        let occupied = occupied
+            .get_or_insert_with(|| enemy_q.iter().map(|t| t.translation.truncate()).collect());
+        for (region, &count) in level.spawn_points.iter().zip(&counts) {
+            if count == 0 {
+                continue;
+            }
+            // Regions that are full get the rest of their agents on later ticks
+            let positions = placement::poisson_disk(
+                region,
+                count as usize,
+                SPAWN_SPACING,
+                occupied,
+                is_walkable,
+                &mut rng.0,
+            );
+            for &pos in &positions {
+                let params = agent_params.sample(&mut rng.0);
+                commands.spawn(EnemyBundle::new(pos, params, &mut rng.0));
+            }
+            occupied.extend(&positions);
+            *spawned += positions.len() as u32;
+            room -= positions.len() as u32;
         }
     }
+
+    stats.add("spawn", start.elapsed());
 }
@@ -0,0 +1,231 @@
+use std::f32::consts::TAU;
+
+use bevy::prelude::*;
+use rand::prelude::*;
+
+use crate::level::SpawnRegion;
+
+/// Candidates tried around a point before it is no longer used to place new points.
+const ATTEMPTS: usize = 30;
+
+/// Picks at most `count` positions in `region` that are at least `spacing` apart from each other
+/// and from `occupied`, with Bridson's Poisson disk sampling.
+/// Filling starts from the center of the region and continues from random positions when
+/// the area around the placed positions is full.
+pub fn poisson_disk(
+    region: &SpawnRegion,
+    count: usize,
+    spacing: f32,
+    occupied: &[Vec2],
+    is_walkable: impl Fn(Vec2) -> bool,
+    rng: &mut impl Rng,
+) -> Vec<Vec2> {
+    let bounds = region.bounds();
+    let grid_bounds = Rect {
+        min: bounds.min - spacing,
+        max: bounds.max + spacing,
+    };
+    let mut grid = DiskGrid::new(grid_bounds, spacing);
+
+    for &pos in occupied {
+        grid.insert(pos);
+    }
+
+    let is_valid =
+        |grid: &DiskGrid, pos: Vec2| region.contains(pos) && is_walkable(pos) && grid.is_free(pos);
+
+    let mut active = Vec::new();
+    let mut placed = Vec::new();
+    let center = region.center();
+    if count > 0 && is_valid(&grid, center) {
+        grid.insert(center);
+        active.push(center);
+        placed.push(center);
+    }
+
+    while placed.len() < count {
+        let candidate = if active.is_empty() {
+            // The region may be split by walls or agents, so restart from anywhere
+            (0..ATTEMPTS)
+                .map(|_| {
+                    Vec2::new(
+                        rng.gen_range(bounds.min.x..=bounds.max.x),
+                        rng.gen_range(bounds.min.y..=bounds.max.y),
+                    )
+                })
+                .find(|&pos| is_valid(&grid, pos))
+        } else {
+            let i = rng.gen_range(0..active.len());
+            let from = active[i];
+            let candidate = (0..ATTEMPTS)
+                .map(|_| {
+                    let angle = rng.gen_range(0. ..TAU);
+                    let distance = rng.gen_range(spacing..spacing * 2.);
+                    from + Vec2::from_angle(angle) * distance
+                })
+                .find(|&pos| is_valid(&grid, pos));
+            if candidate.is_none() {
+                active.swap_remove(i);
+                continue;
+            }
+            candidate
+        };
+
+        let Some(pos) = candidate else {
+            break;
+        };
+        grid.insert(pos);
+        active.push(pos);
+        placed.push(pos);
+    }
+
+    placed
+}
+
+/// Picks at most `count` positions uniformly in `bounds` that are at least `spacing` apart,
+/// with dart throwing. Unlike `poisson_disk` the positions are spread over the whole area
+/// even when there are few of them.
+pub fn scatter(
+    bounds: Rect,
+    count: usize,
+    spacing: f32,
+    is_valid: impl Fn(Vec2) -> bool,
+    rng: &mut impl Rng,
+) -> Vec<Vec2> {
+    let mut grid = DiskGrid::new(bounds, spacing);
+    let mut placed = Vec::new();
+
+    for _ in 0..count * ATTEMPTS {
+        if placed.len() == count {
+            break;
+        }
+        let pos = Vec2::new(
+            rng.gen_range(bounds.min.x..=bounds.max.x),
+            rng.gen_range(bounds.min.y..=bounds.max.y),
+        );
+        if is_valid(pos) && grid.is_free(pos) {
+            grid.insert(pos);
+            placed.push(pos);
+        }
+    }
+
+    placed
+}
+
+/// Points binned to cells as wide as the minimum distance,
+/// so only the neighboring cells need to be checked.
+struct DiskGrid {
+    bounds: Rect,
+    spacing: f32,
+    width: usize,
+    height: usize,
+    cells: Vec<Vec<Vec2>>,
+}
+
+impl DiskGrid {
+    fn new(bounds: Rect, spacing: f32) -> Self {
+        let size = (bounds.size() / spacing).ceil();
+        let (width, height) = (size.x as usize + 1, size.y as usize + 1);
+        Self {
+            bounds,
+            spacing,
+            width,
+            height,
+            cells: vec![Vec::new(); width * height],
+        }
+    }
+
+    fn cell(&self, pos: Vec2) -> Option<[usize; 2]> {
+        if !self.bounds.contains(pos) {
+            return None;
+        }
+        let cell = ((pos - self.bounds.min) / self.spacing).floor();
+        Some([cell.x as usize, cell.y as usize])
+    }
+
+    /// Positions outside of the grid are ignored, they can't be too close to anything in it.
+    fn insert(&mut self, pos: Vec2) {
+        if let Some([x, y]) = self.cell(pos) {
+            self.cells[x + y * self.width].push(pos);
+        }
+    }
+
+    fn is_free(&self, pos: Vec2) -> bool {
+        let Some([x, y]) = self.cell(pos) else {
+            return false;
+        };
+        let spacing_sq = self.spacing * self.spacing;
+        for cy in y.saturating_sub(1)..(y + 2).min(self.height) {
+            for cx in x.saturating_sub(1)..(x + 2).min(self.width) {
+                if self.cells[cx + cy * self.width]
+                    .iter()
+                    .any(|p| p.distance_squared(pos) < spacing_sq)
+                {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::simulation::rng::FastRng;
+
+    use super::*;
+
+    fn assert_spaced(points: &[Vec2], spacing: f32) {
+        for (i, a) in points.iter().enumerate() {
+            for b in &points[i + 1..] {
+                assert!(a.distance(*b) >= spacing, "{a} and {b} overlap");
+            }
+        }
+    }
+
+    #[test]
+    fn fills_circle_without_overlaps() {
+        let mut rng = FastRng::default();
+        let region = SpawnRegion::Circle {
+            center: Vec2::new(10., 10.),
+            radius: 5.,
+        };
+        let points = poisson_disk(&region, 1000, 1., &[], |_| true, &mut rng);
+
+        assert!(points.len() > 20 && points.len() < 1000);
+        assert!(points.iter().all(|p| region.contains(*p)));
+        assert_spaced(&points, 1.);
+    }
+
+    #[test]
+    fn avoids_occupied_and_unwalkable_positions() {
+        let mut rng = FastRng::default();
+        let region = SpawnRegion::Polygon {
+            vertices: vec![
+                Vec2::new(0., 0.),
+                Vec2::new(10., 0.),
+                Vec2::new(10., 10.),
+                Vec2::new(0., 10.),
+            ],
+        };
+        let occupied = vec![Vec2::new(5., 5.), Vec2::new(2., 2.)];
+        let points = poisson_disk(&region, 30, 1., &occupied, |p| p.x > 3., &mut rng);
+
+        assert_eq!(points.len(), 30);
+        assert!(points.iter().all(|p| p.x > 3.));
+        assert_spaced(&[occupied, points].concat(), 1.);
+    }
+
+    #[test]
+    fn scatters_over_whole_area() {
+        let mut rng = FastRng::default();
+        let bounds = Rect::new(0., 0., 100., 100.);
+        let points = scatter(bounds, 200, 1., |p| p.x < 50. || p.y < 50., &mut rng);
+
+        assert_eq!(points.len(), 200);
+        assert!(points.iter().all(|p| p.x < 50. || p.y < 50.));
+        // Not clumped to one spot
+        assert!(points.iter().any(|p| p.x > 75.) && points.iter().any(|p| p.y > 75.));
+        assert_spaced(&points, 1.);
+    }
+}
@@ -0,0 +1,227 @@
+use std::{fs, path::Path};
+
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use rand::{distributions::WeightedIndex, prelude::*};
+use serde::{Deserialize, Serialize};
+
+/// Agents spawned per tick by the default schedule.
+const SPAWN_PER_TICK: f32 = 300.;
+
+/// When and where agents are spawned. Loaded from a JSON file or embedded in the level.
+/// Ticks are counted at the reference tick rate of 60 Hz,
+/// so a schedule plays out the same at every tick rate.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpawnSchedule {
+    pub waves: Vec<SpawnWave>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SpawnWave {
+    /// Tick of the first spawn.
+    pub start: u32,
+    /// Agents spawned in total, or per repetition.
+    pub count: u32,
+    /// Agents spawned per tick.
+    pub rate: f32,
+    /// Relative chances of the spawn points of the level, in the same order.
+    /// All spawn points are equally likely if empty.
+    #[serde(default)]
+    pub spawn_point_weights: Vec<f32>,
+    #[serde(default)]
+    pub repeat: Option<Repeat>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Repeat {
+    /// Ticks from the start of one repetition to the start of the next.
+    pub every: u32,
+    /// How many times the wave is spawned, forever if not given.
+    #[serde(default)]
+    pub times: Option<u32>,
+}
+
+/// Spawns 300 agents per tick whenever there are fewer than `MAX_ENEMIES`,
+/// so agents that reached a target are replaced.
+impl Default for SpawnSchedule {
+    fn default() -> Self {
+        Self {
+            waves: vec![SpawnWave {
+                start: 0,
+                count: SPAWN_PER_TICK as u32,
+                rate: SPAWN_PER_TICK,
+                spawn_point_weights: Vec::new(),
+                // Agents that don't fit under the limit are spawned when there is room
+                repeat: Some(Repeat {
+                    every: 1,
+                    times: None,
+                }),
+            }],
+        }
+    }
+}
+
+impl SpawnSchedule {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read spawn schedule '{}'", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse spawn schedule '{}'", path.display()))
+    }
+
+    /// Checks that the schedule can be used with a level that has `spawn_points` spawn points.
+    pub fn validate(&self, spawn_points: usize) -> anyhow::Result<()> {
+        for (i, wave) in self.waves.iter().enumerate() {
+            if wave.rate <= 0. {
+                bail!("Wave {i} has a rate of {}", wave.rate);
+            }
+            if wave.repeat.as_ref().is_some_and(|r| r.every == 0) {
+                bail!("Wave {i} repeats every 0 ticks");
+            }
+            let weights = &wave.spawn_point_weights;
+            if weights.is_empty() {
+                continue;
+            }
+            if weights.len() != spawn_points {
+                bail!(
+                    "Wave {i} has {} spawn point weights but the level has {spawn_points} spawn points",
+                    weights.len()
+                );
+            }
+            WeightedIndex::new(weights)
+                .with_context(|| format!("Wave {i} has invalid spawn point weights"))?;
+        }
+        Ok(())
+    }
+}
+
+impl SpawnWave {
+    /// How many agents the wave should have spawned in total by `tick`.
+    pub fn spawned_by(&self, tick: f32) -> u32 {
+        if tick < self.start as f32 {
+            return 0;
+        }
+        let elapsed = tick - self.start as f32;
+
+        let (finished, elapsed) = match &self.repeat {
+            Some(repeat) => {
+                let mut finished = (elapsed / repeat.every as f32) as u32;
+                if let Some(times) = repeat.times {
+                    // Let the last repetition run to the end
+                    finished = finished.min(times.saturating_sub(1));
+                }
+                (finished, elapsed - (finished * repeat.every) as f32)
+            }
+            None => (0, elapsed),
+        };
+
+        // The first agents are spawned on the start tick
+        let current = (((elapsed + 1.) * self.rate) as u32).min(self.count);
+        finished.saturating_mul(self.count).saturating_add(current)
+    }
+
+    /// Splits `count` agents between `spawn_points` spawn points by their weights.
+    /// Large counts are split in proportion, only the remainder is picked randomly.
+    pub fn split(&self, count: u32, spawn_points: usize, rng: &mut impl Rng) -> Vec<u32> {
+        let mut counts = vec![0; spawn_points];
+        if spawn_points == 0 {
+            return counts;
+        }
+        let weights = if self.spawn_point_weights.len() == spawn_points {
+            self.spawn_point_weights.clone()
+        } else {
+            vec![1.; spawn_points]
+        };
+        let total = weights.iter().sum::<f32>();
+        if total > 0. {
+            for (count_i, weight) in counts.iter_mut().zip(&weights) {
+                *count_i = (count as f32 * weight / total) as u32;
+            }
+        }
+        let remainder = count.saturating_sub(counts.iter().sum());
+        for _ in 0..remainder {
+            if let Some(i) = self.choose_spawn_point(spawn_points, rng) {
+                counts[i] += 1;
+            }
+        }
+        counts
+    }
+
+    /// Index of the spawn point to spawn the next agent at.
+    pub fn choose_spawn_point(&self, spawn_points: usize, rng: &mut impl Rng) -> Option<usize> {
+        if spawn_points == 0 {
+            return None;
+        }
+        match WeightedIndex::new(&self.spawn_point_weights) {
+            Ok(weights) if self.spawn_point_weights.len() == spawn_points => {
+                Some(weights.sample(rng))
+            }
+            _ => Some(rng.gen_range(0..spawn_points)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wave(repeat: Option<Repeat>) -> SpawnWave {
+        SpawnWave {
+            start: 10,
+            count: 100,
+            rate: 30.,
+            spawn_point_weights: Vec::new(),
+            repeat,
+        }
+    }
+
+    #[test]
+    fn spawns_at_rate_until_count() {
+        let wave = wave(None);
+        assert_eq!(wave.spawned_by(0.), 0);
+        assert_eq!(wave.spawned_by(10.), 30);
+        assert_eq!(wave.spawned_by(11.), 60);
+        assert_eq!(wave.spawned_by(13.), 100);
+        assert_eq!(wave.spawned_by(1000.), 100);
+    }
+
+    #[test]
+    fn repeats_given_times() {
+        let wave = wave(Some(Repeat {
+            every: 50,
+            times: Some(3),
+        }));
+        assert_eq!(wave.spawned_by(59.), 100);
+        assert_eq!(wave.spawned_by(60.), 130);
+        assert_eq!(wave.spawned_by(110.), 230);
+        assert_eq!(wave.spawned_by(1000.), 300);
+    }
+
+    #[test]
+    fn default_keeps_spawning() {
+        let wave = &SpawnSchedule::default().waves[0];
+        assert_eq!(wave.spawned_by(0.), 300);
+        assert_eq!(wave.spawned_by(99.), 30_000);
+    }
+
+    #[test]
+    fn splits_by_weights() {
+        let mut rng = rand::rngs::mock::StepRng::new(0, 1);
+        let mut wave = wave(None);
+        wave.spawn_point_weights = vec![1., 3., 0.];
+        let counts = wave.split(1001, 3, &mut rng);
+        assert_eq!(counts.iter().sum::<u32>(), 1001);
+        assert!(counts[0] >= 250 && counts[1] >= 750);
+        assert_eq!(counts[2], 0);
+    }
+
+    #[test]
+    fn parses_minimal_wave() {
+        let schedule: SpawnSchedule =
+            serde_json::from_str(r#"{ "waves": [{ "start": 0, "count": 5, "rate": 0.5 }] }"#)
+                .unwrap();
+        assert!(schedule.validate(2).is_ok());
+        assert_eq!(schedule.waves[0].spawned_by(0.), 0);
+        assert_eq!(schedule.waves[0].spawned_by(1.), 1);
+    }
+}
@@ -0,0 +1,316 @@
+use std::{f32::consts::TAU, fs, path::Path};
+
+use anyhow::{bail, Context};
+use bevy::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::level::{Level, LevelStartupSet, Target};
+
+use super::{
+    navigation::{NavGridInner, NAV_SCALE},
+    SimulationSet, SimulationTick,
+};
+
+/// Moves targets along scripted paths, so flow field updates can be benchmarked
+/// without following the mouse in the viewer.
+pub struct TargetMotionPlugin {
+    pub motions: TargetMotions,
+}
+
+impl Plugin for TargetMotionPlugin {
+    fn build(&self, app: &mut App) {
+        app.insert_resource(self.motions.clone())
+            .add_systems(
+                PreStartup,
+                init_target_motions.after(LevelStartupSet::Spawn),
+            )
+            .add_systems(PreUpdate, move_targets.in_set(SimulationSet::MoveTargets));
+    }
+}
+
+/// Distance between the points of a motion that are checked against walls,
+/// small enough to not skip over navigation grid cells.
+const VALIDATION_STEP: f32 = NAV_SCALE * 0.5;
+
+/// How the targets of a level move, loaded from a JSON file.
+/// Times are in simulated seconds, so runs are the same at every tick rate.
+#[derive(Resource, Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct TargetMotions {
+    /// In the same order as the targets of the level, `null` for targets that stay still.
+    /// Targets past the end of the list stay still too.
+    pub targets: Vec<Option<Motion>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Motion {
+    /// Moves from the starting position through the waypoints at a constant speed.
+    Path {
+        waypoints: Vec<Vec2>,
+        /// Units per second.
+        speed: f32,
+        /// Return to the starting position and go again, instead of stopping at the last waypoint.
+        #[serde(default)]
+        looped: bool,
+    },
+    /// Circles around `center`, starting from the starting position.
+    Orbit {
+        center: Vec2,
+        /// Seconds per round. Negative values go clockwise.
+        period: f32,
+    },
+}
+
+impl TargetMotions {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let json = fs::read_to_string(path)
+            .with_context(|| format!("Failed to read target motions '{}'", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Failed to parse target motions '{}'", path.display()))
+    }
+
+    /// Checks that the motions match the targets of `level` and keep them inside it
+    /// and out of its walls.
+    pub fn validate(&self, level: &Level) -> anyhow::Result<()> {
+        if self.targets.len() > level.targets.len() {
+            bail!(
+                "There are motions for {} targets but the level has {} targets",
+                self.targets.len(),
+                level.targets.len()
+            );
+        }
+        let inside =
+            |pos: Vec2| pos.cmpge(Vec2::ZERO).all() && pos.cmple(Vec2::splat(level.size)).all();
+        let nav_grid = NavGridInner::new(level.size, &level.walls);
+        let walkable = |pos: Vec2| {
+            nav_grid
+                .walkable
+                .get(nav_grid.pos_to_index(pos))
+                .copied()
+                .unwrap_or(false)
+        };
+        for (i, (motion, &start)) in self.targets.iter().zip(&level.targets).enumerate() {
+            match motion {
+                Some(Motion::Path {
+                    waypoints,
+                    speed,
+                    looped,
+                }) => {
+                    if waypoints.is_empty() {
+                        bail!("Path of target {i} has no waypoints");
+                    }
+                    if *speed <= 0. {
+                        bail!("Path of target {i} has a speed of {speed}");
+                    }
+                    if let Some(waypoint) = waypoints.iter().find(|&&w| !inside(w)) {
+                        bail!("Path of target {i} goes outside the level to {waypoint}");
+                    }
+                    let points = std::iter::once(start)
+                        .chain(waypoints.iter().copied())
+                        .chain(looped.then_some(start))
+                        .collect::<Vec<_>>();
+                    for w in points.windows(2) {
+                        let steps = (w[0].distance(w[1]) / VALIDATION_STEP).ceil() as usize;
+                        if let Some(pos) = (0..=steps)
+                            .map(|step| w[0].lerp(w[1], step as f32 / steps.max(1) as f32))
+                            .find(|&pos| !walkable(pos))
+                        {
+                            bail!("Path of target {i} goes through a wall at {pos}");
+                        }
+                    }
+                }
+                Some(Motion::Orbit { center, period }) => {
+                    if *period == 0. {
+                        bail!("Orbit of target {i} has a period of 0");
+                    }
+                    let radius = start.distance(*center);
+                    if !inside(*center - radius) || !inside(*center + radius) {
+                        bail!("Orbit of target {i} goes outside the level");
+                    }
+                    let steps = (TAU * radius / VALIDATION_STEP).ceil() as usize;
+                    if let Some(pos) = (0..steps)
+                        .map(|step| {
+                            *center
+                                + Vec2::from_angle(TAU * step as f32 / steps as f32)
+                                    .rotate(start - *center)
+                        })
+                        .find(|&pos| !walkable(pos))
+                    {
+                        bail!("Orbit of target {i} goes through a wall at {pos}");
+                    }
+                }
+                None => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Motion {
+    /// Position `time` seconds after starting from `start`.
+    pub fn position(&self, start: Vec2, time: f32) -> Vec2 {
+        match self {
+            Motion::Path {
+                waypoints,
+                speed,
+                looped,
+            } => {
+                let points = std::iter::once(start)
+                    .chain(waypoints.iter().copied())
+                    .chain(looped.then_some(start))
+                    .collect::<Vec<_>>();
+                let length = points.windows(2).map(|w| w[0].distance(w[1])).sum::<f32>();
+                if length == 0. {
+                    return start;
+                }
+
+                let mut distance = speed * time;
+                distance = if *looped {
+                    distance % length
+                } else {
+                    distance.min(length)
+                };
+                for w in points.windows(2) {
+                    let segment = w[0].distance(w[1]);
+                    if distance <= segment {
+                        return w[0].lerp(w[1], distance / segment.max(f32::EPSILON));
+                    }
+                    distance -= segment;
+                }
+                *points.last().unwrap()
+            }
+            Motion::Orbit { center, period } => {
+                *center + Vec2::from_angle(TAU * time / period).rotate(start - *center)
+            }
+        }
+    }
+}
+
+#[derive(Component)]
+struct TargetMotion {
+    start: Vec2,
+    motion: Motion,
+}
+
+/// Targets are matched to the motions by their starting positions.
+fn init_target_motions(
+    level: Res<Level>,
+    motions: Res<TargetMotions>,
+    target_q: Query<(Entity, &Transform), (With<Target>, Without<TargetMotion>)>,
+    mut commands: Commands,
+) {
+    let mut unmatched = target_q.iter().collect::<Vec<_>>();
+    for (&start, motion) in level.targets.iter().zip(&motions.targets) {
+        let Some(i) = unmatched
+            .iter()
+            .position(|(_, transform)| transform.translation.truncate() == start)
+        else {
+            continue;
+        };
+        let (entity, _) = unmatched.swap_remove(i);
+        if let Some(motion) = motion {
+            commands.entity(entity).insert(TargetMotion {
+                start,
+                motion: motion.clone(),
+            });
+        }
+    }
+}
+
+fn move_targets(
+    mut target_q: Query<(&mut Transform, &TargetMotion)>,
+    tick: Res<SimulationTick>,
+    mut time: Local<f32>,
+) {
+    // Positions of a tick are at its end, like the agents after movement
+    *time += tick.length;
+    for (mut transform, target) in &mut target_q {
+        let pos = target.motion.position(target.start, *time);
+        transform.translation.x = pos.x;
+        transform.translation.y = pos.y;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn follows_path() {
+        let path = |looped| Motion::Path {
+            waypoints: vec![Vec2::new(10., 0.), Vec2::new(10., 10.)],
+            speed: 2.,
+            looped,
+        };
+        let start = Vec2::ZERO;
+
+        assert_eq!(path(false).position(start, 0.), start);
+        assert_eq!(path(false).position(start, 2.5), Vec2::new(5., 0.));
+        assert_eq!(path(false).position(start, 7.5), Vec2::new(10., 5.));
+        assert_eq!(path(false).position(start, 100.), Vec2::new(10., 10.));
+        // Back to the start along the diagonal and around again
+        assert!(path(true)
+            .position(start, 10. + 200_f32.sqrt() / 2.)
+            .abs_diff_eq(start, 1e-4));
+        assert!(path(true)
+            .position(start, 10. + 200_f32.sqrt() / 2. + 2.5)
+            .abs_diff_eq(Vec2::new(5., 0.), 1e-4));
+    }
+
+    #[test]
+    fn orbits_center() {
+        let orbit = Motion::Orbit {
+            center: Vec2::new(5., 5.),
+            period: 4.,
+        };
+        let start = Vec2::new(10., 5.);
+
+        assert!(orbit
+            .position(start, 1.)
+            .abs_diff_eq(Vec2::new(5., 10.), 1e-4));
+        assert!(orbit
+            .position(start, 2.)
+            .abs_diff_eq(Vec2::new(0., 5.), 1e-4));
+        assert!(orbit.position(start, 4.).abs_diff_eq(start, 1e-4));
+    }
+
+    #[test]
+    fn rejects_motions_through_walls() {
+        let level = Level {
+            size: 20.,
+            spawn_points: Vec::new(),
+            targets: vec![Vec2::new(3., 10.)],
+            walls: vec![vec![
+                Vec2::new(8., 8.),
+                Vec2::new(12., 8.),
+                Vec2::new(12., 12.),
+                Vec2::new(8., 12.),
+            ]],
+            spawn_schedule: None,
+        };
+        let validate = |motion| {
+            TargetMotions {
+                targets: vec![Some(motion)],
+            }
+            .validate(&level)
+        };
+        let path = |waypoints| Motion::Path {
+            waypoints,
+            speed: 1.,
+            looped: false,
+        };
+
+        assert!(validate(path(vec![Vec2::new(3., 3.), Vec2::new(17., 3.)])).is_ok());
+        assert!(validate(path(vec![Vec2::new(17., 10.)])).is_err());
+        assert!(validate(Motion::Orbit {
+            center: Vec2::new(10., 10.),
+            period: 10.,
+        })
+        .is_ok());
+        assert!(validate(Motion::Orbit {
+            center: Vec2::new(6., 10.),
+            period: 10.,
+        })
+        .is_err());
+    }
+}
@@ -1,17 +1,73 @@
-use std::{collections::HashMap, fs, time::Duration};
+use std::{collections::HashMap, fs, path::PathBuf, time::Duration};
 
-use bevy::{app::AppExit, prelude::*};
+use bevy::{app::AppExit, core::FrameCount, prelude::*};
+use clap::ValueEnum;
 use itertools::Itertools;
+use plotters::{
+    coord::Shift,
+    prelude::{
+        BitMapBackend, ChartBuilder, DrawingArea, DrawingBackend, IntoDrawingArea, IntoFont,
+        LineSeries, PathElement, RGBColor, SVGBackend, BLACK, WHITE,
+    },
+    style::Color as _,
+};
+use serde::Serialize;
 
-pub struct StatisticsPlugin;
+use crate::{
+    level::{Level, LevelPath},
+    simulation::SimulationTick,
+};
+
+use self::metadata::{AgentCounts, RunMetadata};
+
+mod metadata;
+
+pub struct StatisticsPlugin {
+    pub settings: StatisticsSettings,
+}
 
 impl Plugin for StatisticsPlugin {
     fn build(&self, app: &mut App) {
         app.init_resource::<Statistics>()
+            .init_resource::<Metrics>()
+            .init_resource::<WarmupMetrics>()
+            .insert_resource(self.settings.clone())
+            .add_systems(First, discard_warmup)
             .add_systems(Last, write_statistics.run_if(is_exiting));
     }
 }
 
+#[derive(Resource, Clone, Debug)]
+pub struct StatisticsSettings {
+    /// Where to write the timings, metrics and metadata of the run.
+    /// Charts are written next to it.
+    pub output: PathBuf,
+    /// Timings of this many ticks at the start are left out, so spawning the first agents and
+    /// one-off initialization don't skew them.
+    pub warmup: u32,
+    /// Draw the timings and their histograms to charts.
+    pub plot: Option<PlotFormat>,
+}
+
+impl StatisticsSettings {
+    /// `{stem}-{name}.{extension}` next to the output, e.g. statistics-queues.json.
+    pub fn path_next_to_output(&self, name: &str, extension: &str) -> PathBuf {
+        let stem = self
+            .output
+            .file_stem()
+            .unwrap_or_default()
+            .to_string_lossy();
+        self.output
+            .with_file_name(format!("{stem}-{name}.{extension}"))
+    }
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum PlotFormat {
+    Png,
+    Svg,
+}
+
 #[derive(Resource, Default)]
 pub struct Statistics(pub HashMap<&'static str, Vec<Duration>>);
 
@@ -25,10 +81,58 @@ impl Statistics {
     }
 }
 
+/// Values that describe the state of the crowd, one per tick.
+/// Written next to the timings, so the two can be compared.
+#[derive(Resource, Default)]
+pub struct Metrics(pub HashMap<&'static str, Vec<f32>>);
+
+impl Metrics {
+    pub fn add(&mut self, name: &'static str, value: f32) {
+        self.0.entry(name).or_default().push(value);
+    }
+}
+
+/// What the run metadata needs from the metrics that were cleared after the warmup.
+#[derive(Resource, Default)]
+struct WarmupMetrics {
+    ticks: usize,
+    spawned: f32,
+    max_agents: f32,
+}
+
 pub fn is_exiting(mut exit: EventReader<AppExit>) -> bool {
     exit.read().next().is_some()
 }
 
+fn discard_warmup(
+    frames: Res<FrameCount>,
+    settings: Res<StatisticsSettings>,
+    mut stats: ResMut<Statistics>,
+    mut metrics: ResMut<Metrics>,
+    mut warmup: ResMut<WarmupMetrics>,
+) {
+    // Both are cleared so series recorded every tick stay aligned by index. Timings that are
+    // not recorded every tick, like `flow_field_task`, don't line up with the ticks, and
+    // one-off timings of the warmup, like `nav_grid`, are dropped.
+    if settings.warmup > 0 && frames.0 == settings.warmup {
+        let agents = metrics
+            .0
+            .get("agents")
+            .map(Vec::as_slice)
+            .unwrap_or_default();
+        *warmup = WarmupMetrics {
+            ticks: warmup.ticks + agents.len(),
+            spawned: metrics
+                .0
+                .get("spawned")
+                .map_or(0., |spawned| spawned.iter().sum()),
+            max_agents: agents.iter().copied().fold(0., f32::max),
+        };
+        stats.0.clear();
+        metrics.0.clear();
+    }
+}
+
 pub fn mean(a: &[Duration]) -> Duration {
     a.iter().sum::<Duration>() / a.len() as u32
 }
@@ -43,6 +147,14 @@ pub fn median(a: &[Duration]) -> Duration {
     }
 }
 
+/// Smallest value that at least `p` percent of the values are at most.
+pub fn percentile(a: &[Duration], p: f64) -> Duration {
+    let mut a = a.to_vec();
+    a.sort();
+    let rank = (p / 100. * a.len() as f64).ceil() as usize;
+    a[rank.clamp(1, a.len()) - 1]
+}
+
 pub fn std(a: &[Duration]) -> Duration {
     let mean = mean(a).as_secs_f64();
     let variance = a
@@ -61,89 +173,305 @@ fn print_stats(stats: Res<Statistics>) {
     for k in stats.0.keys().sorted() {
         let v = &stats.0[k];
         println!(
-            "{:16 }: mean {: <11}, std {: <11}, median {: <11}",
+            "{:16 }: mean {: <11}, std {: <11}, median {: <11}, p90 {: <11}, p99 {: <11}, max {: <11}",
             k,
             as_ms(mean(v)),
             as_ms(std(v)),
             as_ms(median(v)),
+            as_ms(percentile(v, 90.)),
+            as_ms(percentile(v, 99.)),
+            as_ms(v.iter().copied().max().unwrap_or_default()),
         );
     }
 }
 
-fn write_statistics(stats: Res<Statistics>) {
+fn print_metrics(metrics: &Metrics) {
+    for k in metrics.0.keys().sorted() {
+        let v = &metrics.0[k];
+        let mean = v.iter().sum::<f32>() / v.len().max(1) as f32;
+        let max = v.iter().copied().fold(0., f32::max);
+        println!("{:16 }: mean {: <11.3}, max {: <11.3}", k, mean, max);
+    }
+}
+
+/// Contents of the statistics file.
+#[derive(Serialize)]
+struct StatisticsFile<'a> {
+    metadata: RunMetadata,
+    /// Every sample of every timing in seconds.
+    timings: &'a HashMap<&'static str, Vec<f64>>,
+    histograms: &'a HashMap<&'static str, Histogram>,
+    /// See `Metrics`.
+    metrics: &'a HashMap<&'static str, Vec<f32>>,
+}
+
+fn write_statistics(
+    stats: Res<Statistics>,
+    metrics: Res<Metrics>,
+    warmup: Res<WarmupMetrics>,
+    settings: Res<StatisticsSettings>,
+    level: Res<Level>,
+    level_path: Option<Res<LevelPath>>,
+    tick: Res<SimulationTick>,
+) {
     let stats_f64 = stats
         .0
         .iter()
         .map(|(k, v)| (*k, v.iter().map(|d| d.as_secs_f64()).collect_vec()))
         .collect::<HashMap<_, _>>();
 
-    fs::write(
-        "statistics.json",
-        serde_json::to_string(&stats_f64).unwrap(),
-    )
-    .unwrap();
+    let histograms = stats_f64
+        .iter()
+        .map(|(k, v)| (*k, Histogram::new(v, HISTOGRAM_BINS)))
+        .collect::<HashMap<_, _>>();
+
+    let agents = metrics
+        .0
+        .get("agents")
+        .map(Vec::as_slice)
+        .unwrap_or_default();
+    let metadata = RunMetadata {
+        level: level_path.map(|path| path.0.clone()),
+        level_size: level.size,
+        features: metadata::features(),
+        arguments: std::env::args().skip(1).collect(),
+        ticks: warmup.ticks + agents.len(),
+        warmup: settings.warmup,
+        tick_length: tick.length,
+        substeps: tick.substeps,
+        agents: AgentCounts {
+            spawned: (warmup.spawned
+                + metrics
+                    .0
+                    .get("spawned")
+                    .map_or(0., |spawned| spawned.iter().sum::<f32>())) as u32,
+            max: agents.iter().copied().fold(warmup.max_agents, f32::max) as u32,
+            remaining: agents.last().copied().unwrap_or(0.) as u32,
+        },
+        git_revision: metadata::git_revision(),
+        cpu: metadata::cpu_model(),
+        threads: std::thread::available_parallelism()
+            .ok()
+            .map(|threads| threads.get()),
+    };
+
+    let file = StatisticsFile {
+        metadata,
+        timings: &stats_f64,
+        histograms: &histograms,
+        metrics: &metrics.0,
+    };
+    fs::write(&settings.output, serde_json::to_string(&file).unwrap()).unwrap();
 
     print_stats(stats);
+    print_metrics(&metrics);
+
+    if let Some(format) = settings.plot {
+        // The numbers are already saved, so a failed plot only loses the charts
+        if let Err(e) = plot_stats(&stats_f64, &histograms, &settings, format) {
+            println!("Failed to plot statistics: {e:#}");
+        }
+    }
+}
+
+const HISTOGRAM_BINS: usize = 40;
+
+/// Counts of timings in equal bins from the smallest to the largest one, in seconds.
+#[derive(Serialize, Debug)]
+pub struct Histogram {
+    pub min: f64,
+    pub bin_width: f64,
+    pub counts: Vec<u32>,
+}
+
+impl Histogram {
+    pub fn new(values: &[f64], bins: usize) -> Self {
+        if values.is_empty() {
+            return Self {
+                min: 0.,
+                bin_width: 0.,
+                counts: Vec::new(),
+            };
+        }
+        let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+        let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+        // Equal values still get a bin
+        let bin_width = ((max - min) / bins as f64).max(f64::EPSILON);
+        let mut counts = vec![0; bins];
+        for v in values {
+            let bin = ((v - min) / bin_width) as usize;
+            counts[bin.min(bins - 1)] += 1;
+        }
+        Self {
+            min,
+            bin_width,
+            counts,
+        }
+    }
+}
+
+const COLORS: [RGBColor; 6] = [
+    RGBColor(11, 132, 165),
+    RGBColor(111, 78, 124),
+    RGBColor(157, 216, 102),
+    RGBColor(202, 71, 47),
+    RGBColor(255, 160, 86),
+    RGBColor(141, 221, 208),
+];
+
+/// Writes e.g. statistics-timings.png and statistics-histograms.png
+/// next to statistics.json, or the same as SVG.
+fn plot_stats(
+    stats: &HashMap<&str, Vec<f64>>,
+    histograms: &HashMap<&str, Histogram>,
+    settings: &StatisticsSettings,
+    format: PlotFormat,
+) -> anyhow::Result<()> {
+    let extension = match format {
+        PlotFormat::Png => "png",
+        PlotFormat::Svg => "svg",
+    };
+    let timings_path = settings.path_next_to_output("timings", extension);
+    let histograms_path = settings.path_next_to_output("histograms", extension);
+
+    let timings_size = (1200, 600);
+    let histograms_size = (1200, 300 * histograms.len().div_ceil(3).max(1) as u32);
+    match format {
+        PlotFormat::Png => {
+            plot_timings(
+                BitMapBackend::new(&timings_path, timings_size).into_drawing_area(),
+                stats,
+            )?;
+            plot_histograms(
+                BitMapBackend::new(&histograms_path, histograms_size).into_drawing_area(),
+                histograms,
+            )?;
+        }
+        PlotFormat::Svg => {
+            plot_timings(
+                SVGBackend::new(&timings_path, timings_size).into_drawing_area(),
+                stats,
+            )?;
+            plot_histograms(
+                SVGBackend::new(&histograms_path, histograms_size).into_drawing_area(),
+                histograms,
+            )?;
+        }
+    }
+    Ok(())
+}
+
+/// Every timing over the run, in milliseconds.
+fn plot_timings<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    stats: &HashMap<&str, Vec<f64>>,
+) -> anyhow::Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let width = stats.values().map(|v| v.len()).max().unwrap_or(0);
+    let max = stats
+        .values()
+        .flatten()
+        .copied()
+        .fold(0., f64::max)
+        .max(f64::EPSILON)
+        * 1000.;
+
+    let mut chart = ChartBuilder::on(&root)
+        .caption("Timings", ("sans-serif", 20).into_font())
+        .margin(5)
+        .x_label_area_size(30)
+        .y_label_area_size(50)
+        .build_cartesian_2d(0..width, 0. ..max)?;
+
+    chart.configure_mesh().x_desc("tick").y_desc("ms").draw()?;
+
+    for (i, (name, values)) in stats.iter().sorted_by_key(|(k, _)| *k).enumerate() {
+        let color = COLORS[i % COLORS.len()];
+        chart
+            .draw_series(LineSeries::new(
+                values.iter().enumerate().map(|(i, v)| (i, v * 1000.)),
+                color,
+            ))?
+            .label(*name)
+            .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
+    }
 
-    // plot_stats(stats_f64).unwrap();
-}
-
-// use plotters::{prelude::*, style::Color};
-
-// const COLORS: [RGBColor; 6] = [
-//     RGBColor(11, 132, 165),
-//     RGBColor(111, 78, 124),
-//     RGBColor(157, 216, 102),
-//     RGBColor(202, 71, 47),
-//     RGBColor(255, 160, 86),
-//     RGBColor(141, 221, 208),
-// ];
-//
-// fn plot_stats(stats: HashMap<&str, Vec<f64>>) -> anyhow::Result<()> {
-//     let root = BitMapBackend::new("plot.png", (800, 480)).into_drawing_area();
-//     root.fill(&WHITE)?;
-//
-//     let width = stats.iter().next().unwrap().1.len();
-//
-//     let max = *stats
-//         .values()
-//         .map(|v| v.iter().max_by(|a, b| a.total_cmp(b)).unwrap())
-//         .max_by(|a, b| a.total_cmp(b))
-//         .unwrap();
-//
-//     let min = *stats
-//         .values()
-//         .map(|v| v.iter().min_by(|a, b| a.total_cmp(b)).unwrap())
-//         .min_by(|a, b| a.total_cmp(b))
-//         .unwrap();
-//
-//     let mut chart = ChartBuilder::on(&root)
-//         .caption("Stats", ("sans-serif", 20).into_font())
-//         .margin(5)
-//         .x_label_area_size(30)
-//         .y_label_area_size(30)
-//         .build_cartesian_2d(0..width, min..max)?;
-//
-//     chart.configure_mesh().draw()?;
-//
-//     for (i, (name, values)) in stats.iter().sorted_by_key(|(k, _)| *k).enumerate() {
-//         let color = COLORS[i];
-//         chart
-//             .draw_series(LineSeries::new(
-//                 values.iter().enumerate().map(|(i, v)| (i, *v)),
-//                 color,
-//             ))?
-//             .label(*name)
-//             .legend(move |(x, y)| PathElement::new(vec![(x, y), (x + 20, y)], color));
-//     }
-//
-//     chart
-//         .configure_series_labels()
-//         .background_style(WHITE.mix(0.8))
-//         .border_style(BLACK)
-//         .draw()?;
-//
-//     root.present()?;
-//
-//     Ok(())
-// }
+    chart
+        .configure_series_labels()
+        .background_style(WHITE.mix(0.8))
+        .border_style(BLACK)
+        .draw()?;
+
+    root.present()?;
+
+    Ok(())
+}
+
+/// One histogram per timing, three in a row.
+fn plot_histograms<DB: DrawingBackend>(
+    root: DrawingArea<DB, Shift>,
+    histograms: &HashMap<&str, Histogram>,
+) -> anyhow::Result<()>
+where
+    DB::ErrorType: 'static,
+{
+    root.fill(&WHITE)?;
+
+    let areas = root.split_evenly((histograms.len().div_ceil(3).max(1), 3));
+    for (i, ((name, histogram), area)) in histograms
+        .iter()
+        .sorted_by_key(|(k, _)| *k)
+        .zip(areas)
+        .enumerate()
+    {
+        let color = COLORS[i % COLORS.len()];
+        let min = histogram.min * 1000.;
+        let bin_width = histogram.bin_width * 1000.;
+        let max = min + bin_width * histogram.counts.len().max(1) as f64;
+        let max_count = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+
+        let mut chart = ChartBuilder::on(&area)
+            .caption(*name, ("sans-serif", 16).into_font())
+            .margin(5)
+            .x_label_area_size(30)
+            .y_label_area_size(40)
+            .build_cartesian_2d(min..max, 0..max_count)?;
+
+        chart.configure_mesh().x_desc("ms").draw()?;
+
+        chart.draw_series(histogram.counts.iter().enumerate().map(|(i, &count)| {
+            let start = min + bin_width * i as f64;
+            plotters::prelude::Rectangle::new(
+                [(start, 0), (start + bin_width, count)],
+                color.filled(),
+            )
+        }))?;
+    }
+
+    root.present()?;
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_and_histogram() {
+        let durations = (1..=100).map(Duration::from_millis).collect_vec();
+        assert_eq!(percentile(&durations, 50.), Duration::from_millis(50));
+        assert_eq!(percentile(&durations, 99.), Duration::from_millis(99));
+        assert_eq!(percentile(&durations, 100.), Duration::from_millis(100));
+        assert_eq!(percentile(&durations, 0.), Duration::from_millis(1));
+
+        let histogram = Histogram::new(&[1., 2., 2., 3., 5.], 4);
+        assert_eq!(histogram.min, 1.);
+        assert_eq!(histogram.bin_width, 1.);
+        assert_eq!(histogram.counts, vec![1, 2, 1, 1]);
+    }
+}
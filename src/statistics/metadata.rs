@@ -0,0 +1,92 @@
+use std::{fs, process::Command};
+
+use serde::Serialize;
+
+/// What was run and where, so result files can be compared without knowing how they were made.
+#[derive(Serialize, Debug)]
+pub struct RunMetadata {
+    /// Name of the level, `None` for the default empty level.
+    pub level: Option<String>,
+    pub level_size: f32,
+    /// Enabled cargo features.
+    pub features: Vec<&'static str>,
+    /// Command line arguments of the run.
+    pub arguments: Vec<String>,
+    /// Simulated ticks, including the warmup.
+    pub ticks: usize,
+    pub warmup: u32,
+    /// Seconds
+    pub tick_length: f32,
+    pub substeps: u32,
+    pub agents: AgentCounts,
+    /// `git describe` of the working directory, marked dirty with uncommitted changes.
+    pub git_revision: Option<String>,
+    pub cpu: Option<String>,
+    /// Threads the CPU can run in parallel.
+    pub threads: Option<usize>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct AgentCounts {
+    pub spawned: u32,
+    /// Most agents at the same time.
+    pub max: u32,
+    /// Agents at the end of the run.
+    pub remaining: u32,
+}
+
+macro_rules! enabled_features {
+    ($($feature:literal),* $(,)?) => {
+        [$(cfg!(feature = $feature).then_some($feature)),*]
+            .into_iter()
+            .flatten()
+            .collect()
+    };
+}
+
+pub fn features() -> Vec<&'static str> {
+    enabled_features!(
+        "bench",
+        "navigation1",
+        "navigation2",
+        "spatial_array",
+        "spatial_hash",
+        "spatial_hash_std",
+        "spatial_kdtree",
+        "spatial_kdtree_kiddo",
+        "spatial_kdbush",
+        "spatial_rstar",
+        "parallel",
+        "distance_func2",
+        "branchless",
+        "floatneighbors",
+        "no_id_check",
+        "flocking_boids",
+        "new_movement",
+        "new_move_clamp",
+        "steering_orca",
+        "steering_social_force",
+    )
+}
+
+pub fn git_revision() -> Option<String> {
+    let output = Command::new("git")
+        .args(["describe", "--always", "--dirty", "--abbrev=12"])
+        .output()
+        .ok()?;
+    if !output.status.success() {
+        return None;
+    }
+    let revision = String::from_utf8(output.stdout).ok()?;
+    Some(revision.trim().to_owned())
+}
+
+/// Only known on Linux.
+pub fn cpu_model() -> Option<String> {
+    let cpuinfo = fs::read_to_string("/proc/cpuinfo").ok()?;
+    cpuinfo
+        .lines()
+        .find(|line| line.starts_with("model name"))
+        .and_then(|line| line.split_once(':'))
+        .map(|(_, model)| model.trim().to_owned())
+}
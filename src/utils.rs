@@ -2,6 +2,10 @@ use std::iter;
 
 use bevy::prelude::*;
 
+mod inflate;
+
+pub use inflate::{inflate_polygon, InflateError};
+
 pub type Vertices = Vec<Vec2>;
 
 pub trait ToVec2 {
@@ -49,6 +53,15 @@ pub fn rectangle(size: Vec2) -> Vertices {
     ]
 }
 
+pub fn make_circle(center: Vec2, radius: f32, segments: usize) -> Vertices {
+    let mut vertices = Vec::with_capacity(segments);
+    for i in 0..segments {
+        let angle = -(i as f32 / segments as f32) * std::f32::consts::TAU;
+        vertices.push(center + Vec2::new(angle.cos(), angle.sin()) * radius);
+    }
+    vertices
+}
+
 pub fn spatial(pos: Vec2, z: f32) -> SpatialBundle {
     SpatialBundle {
         transform: Transform::from_translation(pos.extend(z)),
@@ -103,50 +116,63 @@ pub fn is_point_in_polygon(point: Vec2, vertices: &Vertices) -> bool {
     odd_nodes
 }
 
-pub fn is_clockwise(vertices: &Vertices) -> bool {
+/// Nonzero winding number of the polygon around the point.
+/// Positive for counter-clockwise polygons.
+pub fn winding_number(point: Vec2, vertices: &Vertices) -> i32 {
+    let mut winding = 0;
+    for (a, b) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+        let side = (*b - *a).perp_dot(point - *a);
+        if a.y <= point.y {
+            if b.y > point.y && side > 0. {
+                winding += 1;
+            }
+        } else if b.y <= point.y && side < 0. {
+            winding -= 1;
+        }
+    }
+    winding
+}
+
+pub fn polygon_area(vertices: &Vertices) -> f32 {
     let mut sum = 0.;
     for i in 0..vertices.len() {
         let vi = vertices[i];
         let vj = vertices[(i + 1) % vertices.len()];
-        sum += (vj.x - vi.x) * (vj.y + vi.y);
+        sum += vi.perp_dot(vj);
     }
-    sum > 0.
+    sum.abs() / 2.
 }
 
-use geo_types::Coordinate;
-use itertools::Itertools;
-use offset_polygon::offset_polygon;
+/// Whether segments `a1`-`a2` and `b1`-`b2` cross each other or overlap along a line.
+/// Segments that only touch at a point don't count.
+pub fn segments_intersect(a1: Vec2, a2: Vec2, b1: Vec2, b2: Vec2) -> bool {
+    let d1 = (a2 - a1).perp_dot(b1 - a1);
+    let d2 = (a2 - a1).perp_dot(b2 - a1);
+    let d3 = (b2 - b1).perp_dot(a1 - b1);
+    let d4 = (b2 - b1).perp_dot(a2 - b1);
 
-pub fn inflate_polygon(vertices: &Vertices, amount: f32) -> Option<Vertices> {
-    if vertices.len() < 3 {
-        return None;
+    if ((d1 > 0. && d2 < 0.) || (d1 < 0. && d2 > 0.))
+        && ((d3 > 0. && d4 < 0.) || (d3 < 0. && d4 > 0.))
+    {
+        return true;
     }
-
-    let mut coords = vertices
-        .iter()
-        .map(|v| Coordinate {
-            x: f64::from(v.x),
-            y: f64::from(v.y),
-        })
-        .cycle()
-        .take(vertices.len() + 1)
-        .collect_vec();
-
-    if is_clockwise(vertices) {
-        coords.reverse();
+    if d1 != 0. || d2 != 0. {
+        return false;
     }
 
-    // TODO: Detect failures properly (common failure is to return a very small or empty polygon)
-    let lines = match offset_polygon(&coords.into(), f64::from(amount), 10.) {
-        Ok(lines) => lines.first()?.clone(),
-        Err(_) => {
-            return None;
-        }
-    };
+    // On the same line, compare positions along it
+    let direction = if a1 == a2 { b2 - b1 } else { a2 - a1 };
+    let (a1, a2) = (a1.dot(direction), a2.dot(direction));
+    let (b1, b2) = (b1.dot(direction), b2.dot(direction));
+    a1.max(a2).min(b1.max(b2)) > a1.min(a2).max(b1.min(b2))
+}
 
-    lines
-        .points_iter()
-        .map(|c| Vec2::new(c.x() as f32, c.y() as f32))
-        .collect_vec()
-        .into()
+pub fn is_clockwise(vertices: &Vertices) -> bool {
+    let mut sum = 0.;
+    for i in 0..vertices.len() {
+        let vi = vertices[i];
+        let vj = vertices[(i + 1) % vertices.len()];
+        sum += (vj.x - vi.x) * (vj.y + vi.y);
+    }
+    sum > 0.
 }
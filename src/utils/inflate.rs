@@ -0,0 +1,549 @@
+//! Polygon inflation (offsetting outwards by a distance).
+//!
+//! The raw offset curve is built from the edges moved outwards, arcs around convex vertices
+//! and connectors through reflex vertices. The curve is split at all self-intersections and
+//! pieces that are inside the inflated shape are dropped. The remaining pieces are chained
+//! into rings. Everything is done in f64 and the vertices are welded by index, so the pieces
+//! always connect exactly.
+
+use std::{collections::HashMap, f64::consts::PI, fmt};
+
+use bevy::math::DVec2;
+
+use super::{is_clockwise, Vertices};
+
+/// Maximum angle of one arc segment around convex vertices.
+const MAX_ARC_STEP: f64 = PI / 16.;
+/// Distance under which points are considered to be the same.
+const EPS: f64 = 1e-7;
+/// How far from the sides of an outline piece to check if they are inside or outside.
+const SIDE_OFFSET: f64 = 1e-5;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum InflateError {
+    TooFewVertices,
+    /// The polygon has no area.
+    Degenerate,
+    /// The outline pieces did not connect into closed rings.
+    OpenBoundary,
+    /// Result has no outer ring.
+    Empty,
+}
+
+impl fmt::Display for InflateError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::TooFewVertices => write!(f, "polygon has fewer than 3 vertices"),
+            Self::Degenerate => write!(f, "polygon has no area"),
+            Self::OpenBoundary => write!(f, "inflated outline is not closed"),
+            Self::Empty => write!(f, "inflated polygon is empty"),
+        }
+    }
+}
+
+impl std::error::Error for InflateError {}
+
+/// Expands the polygon by `amount`. Returns the outline rings of the result.
+/// Outer rings are counter-clockwise and holes are clockwise,
+/// so a point is inside the result if the sum of its winding numbers is not zero.
+pub fn inflate_polygon(vertices: &Vertices, amount: f32) -> Result<Vec<Vertices>, InflateError> {
+    let mut polygon = Vec::<DVec2>::with_capacity(vertices.len());
+    for v in vertices {
+        let v = v.as_dvec2();
+        if polygon.last().map_or(true, |last| last.distance(v) > EPS) {
+            polygon.push(v);
+        }
+    }
+    while polygon.len() > 1 && polygon[0].distance(*polygon.last().unwrap()) <= EPS {
+        polygon.pop();
+    }
+    if polygon.len() < 3 {
+        return Err(InflateError::TooFewVertices);
+    }
+    if is_clockwise(vertices) {
+        polygon.reverse();
+    }
+    if signed_area(&polygon).abs() <= EPS {
+        return Err(InflateError::Degenerate);
+    }
+
+    let amount = f64::from(amount);
+    let (raw, primitives) = raw_offset_curve(&polygon, amount);
+    let mut graph = Graph::new(raw);
+    graph.split_intersections();
+
+    // The result is on the left side of the curve. Keep pieces that have the inside on the left
+    // and the outside on the right, others are inside the result or overlap other pieces.
+    let is_covered = |p: DVec2| {
+        is_inside_polygon(p, &polygon) || primitives.iter().any(|c| is_inside_convex(p, c))
+    };
+    let pieces = graph
+        .pieces()
+        .into_iter()
+        .filter(|&[a, b]| {
+            let (a, b) = (graph.nodes[a], graph.nodes[b]);
+            let mid = (a + b) / 2.;
+            let side = (b - a).normalize().perp() * SIDE_OFFSET;
+            is_covered(mid + side) && !is_covered(mid - side)
+        })
+        .collect::<Vec<_>>();
+
+    let rings = chain_rings(&graph.nodes, cancel_duplicates(pieces))?;
+
+    if !rings.iter().any(|r| signed_area(r) > 0.) {
+        return Err(InflateError::Empty);
+    }
+
+    Ok(rings
+        .into_iter()
+        .map(|r| r.into_iter().map(|v| v.as_vec2()).collect())
+        .collect())
+}
+
+fn signed_area(vertices: &[DVec2]) -> f64 {
+    let mut sum = 0.;
+    for i in 0..vertices.len() {
+        sum += vertices[i].perp_dot(vertices[(i + 1) % vertices.len()]);
+    }
+    sum / 2.
+}
+
+/// Outward normal of the edge `a`-`b` of a counter-clockwise polygon.
+fn normal(a: DVec2, b: DVec2) -> DVec2 {
+    let d = (b - a).normalize();
+    DVec2::new(d.y, -d.x)
+}
+
+/// Returns the raw offset curve and the convex shapes it is the outline of
+/// (rectangles along edges and fans around convex vertices).
+/// Together with the polygon, the shapes cover the inflated polygon.
+fn raw_offset_curve(polygon: &[DVec2], amount: f64) -> (Vec<DVec2>, Vec<Vec<DVec2>>) {
+    let n = polygon.len();
+    let mut raw = Vec::with_capacity(n * 3);
+    let mut primitives = Vec::with_capacity(n * 2);
+
+    for i in 0..n {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % n];
+        let c = polygon[(i + 2) % n];
+        let n1 = normal(a, b);
+        let n2 = normal(b, c);
+
+        raw.push(a + n1 * amount);
+        raw.push(b + n1 * amount);
+        primitives.push(vec![a, a + n1 * amount, b + n1 * amount, b]);
+
+        let cross = n1.perp_dot(n2);
+        let dot = n1.dot(n2);
+        let angle = if cross.abs() <= 1e-12 && dot < 0. {
+            // Spike that turns back on itself
+            PI
+        } else {
+            cross.atan2(dot)
+        };
+
+        if angle > 1e-9 {
+            // Convex vertex, go around it with segments that touch the circle
+            let steps = (angle / MAX_ARC_STEP).ceil();
+            let step = angle / steps;
+            let radius = amount / (step / 2.).cos();
+            let start = n1.y.atan2(n1.x);
+            let mut fan = vec![b, b + n1 * amount];
+            for s in 0..steps as usize {
+                let a = start + (s as f64 + 0.5) * step;
+                let p = b + DVec2::new(a.cos(), a.sin()) * radius;
+                raw.push(p);
+                fan.push(p);
+            }
+            fan.push(b + n2 * amount);
+            primitives.push(fan);
+        } else if angle < -1e-9 {
+            // Reflex vertex, the offset edges overlap
+            raw.push(b);
+        }
+    }
+
+    raw.dedup_by(|a, b| a.distance(*b) <= EPS);
+    while raw.len() > 1 && raw[0].distance(*raw.last().unwrap()) <= EPS {
+        raw.pop();
+    }
+
+    (raw, primitives)
+}
+
+fn is_inside_convex(point: DVec2, polygon: &[DVec2]) -> bool {
+    (0..polygon.len()).all(|i| {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % polygon.len()];
+        let len = a.distance(b);
+        // Zero length edges don't restrict anything
+        len <= EPS || (b - a).perp_dot(point - a) > 0.
+    })
+}
+
+fn is_inside_polygon(point: DVec2, polygon: &[DVec2]) -> bool {
+    let n = polygon.len();
+    let mut winding = 0;
+    for i in 0..n {
+        let a = polygon[i];
+        let b = polygon[(i + 1) % n];
+        let side = (b - a).perp_dot(point - a);
+        if a.y <= point.y {
+            if b.y > point.y && side > 0. {
+                winding += 1;
+            }
+        } else if b.y <= point.y && side < 0. {
+            winding -= 1;
+        }
+    }
+    winding != 0
+}
+
+fn distance_to_segment(p: DVec2, a: DVec2, b: DVec2) -> f64 {
+    let ab = b - a;
+    let t = ((p - a).dot(ab) / ab.length_squared()).clamp(0., 1.);
+    p.distance(a + ab * t)
+}
+
+/// The raw curve as nodes connected by segments.
+/// Segments store the nodes that lie on them so they can be split into pieces.
+struct Graph {
+    nodes: Vec<DVec2>,
+    /// Union-find parents for welding nodes that are at the same position.
+    parents: Vec<usize>,
+    /// For each segment, (position along the segment, node) of points splitting it.
+    splits: Vec<Vec<(f64, usize)>>,
+}
+
+impl Graph {
+    fn new(raw: Vec<DVec2>) -> Self {
+        let n = raw.len();
+        Self {
+            parents: (0..n).collect(),
+            splits: (0..n).map(|i| vec![(0., i), (1., (i + 1) % n)]).collect(),
+            nodes: raw,
+        }
+    }
+
+    fn segment(&self, i: usize) -> (DVec2, DVec2) {
+        (self.nodes[i], self.nodes[(i + 1) % self.splits.len()])
+    }
+
+    fn find(&mut self, mut i: usize) -> usize {
+        while self.parents[i] != i {
+            self.parents[i] = self.parents[self.parents[i]];
+            i = self.parents[i];
+        }
+        i
+    }
+
+    fn weld(&mut self, a: usize, b: usize) {
+        let a = self.find(a);
+        let b = self.find(b);
+        self.parents[a.max(b)] = a.min(b);
+    }
+
+    fn add_node(&mut self, pos: DVec2) -> usize {
+        self.nodes.push(pos);
+        self.parents.push(self.parents.len());
+        self.nodes.len() - 1
+    }
+
+    /// Node at the start or end of segment `i` if `t` is close to it.
+    fn endpoint(&self, i: usize, t: f64, len: f64) -> Option<usize> {
+        if t * len <= EPS {
+            Some(i)
+        } else if (1. - t) * len <= EPS {
+            Some((i + 1) % self.splits.len())
+        } else {
+            None
+        }
+    }
+
+    fn split_intersections(&mut self) {
+        let n = self.splits.len();
+        for i in 0..n {
+            let (a1, a2) = self.segment(i);
+            for j in i + 1..n {
+                let (b1, b2) = self.segment(j);
+                if a1.x.min(a2.x) > b1.x.max(b2.x) + EPS
+                    || b1.x.min(b2.x) > a1.x.max(a2.x) + EPS
+                    || a1.y.min(a2.y) > b1.y.max(b2.y) + EPS
+                    || b1.y.min(b2.y) > a1.y.max(a2.y) + EPS
+                {
+                    continue;
+                }
+                self.intersect(i, j);
+            }
+        }
+    }
+
+    fn intersect(&mut self, i: usize, j: usize) {
+        let (a1, a2) = self.segment(i);
+        let (b1, b2) = self.segment(j);
+        let da = a2 - a1;
+        let db = b2 - b1;
+        let len_a = da.length();
+        let len_b = db.length();
+        let denom = da.perp_dot(db);
+
+        if denom.abs() <= 1e-12 * len_a * len_b {
+            // Parallel, only overlapping collinear segments touch
+            self.touch(i, j);
+            self.touch(j, i);
+            return;
+        }
+
+        let t = (b1 - a1).perp_dot(db) / denom;
+        let u = (b1 - a1).perp_dot(da) / denom;
+        if t * len_a < -EPS || (t - 1.) * len_a > EPS || u * len_b < -EPS || (u - 1.) * len_b > EPS
+        {
+            return;
+        }
+
+        match (self.endpoint(i, t, len_a), self.endpoint(j, u, len_b)) {
+            (Some(a), Some(b)) => self.weld(a, b),
+            (Some(a), None) => self.splits[j].push((u, a)),
+            (None, Some(b)) => self.splits[i].push((t, b)),
+            (None, None) => {
+                let node = self.add_node(a1 + da * t);
+                self.splits[i].push((t, node));
+                self.splits[j].push((u, node));
+            }
+        }
+    }
+
+    /// Splits segment `i` at the endpoints of segment `j` that lie on it.
+    fn touch(&mut self, i: usize, j: usize) {
+        let (a1, a2) = self.segment(i);
+        let da = a2 - a1;
+        let len = da.length();
+        for node in [j, (j + 1) % self.splits.len()] {
+            let p = self.nodes[node];
+            if distance_to_segment(p, a1, a2) > EPS {
+                continue;
+            }
+            let t = (p - a1).dot(da) / (len * len);
+            match self.endpoint(i, t, len) {
+                Some(end) => self.weld(end, node),
+                None => self.splits[i].push((t, node)),
+            }
+        }
+    }
+
+    /// Splits the segments at their split points.
+    /// Returns the pieces as pairs of welded nodes.
+    fn pieces(&mut self) -> Vec<[usize; 2]> {
+        let mut pieces = Vec::new();
+        for i in 0..self.splits.len() {
+            let mut splits = std::mem::take(&mut self.splits[i]);
+            splits.sort_by(|a, b| a.0.total_cmp(&b.0));
+            let mut prev = self.find(splits[0].1);
+            for &(_, node) in &splits[1..] {
+                let node = self.find(node);
+                if node != prev {
+                    pieces.push([prev, node]);
+                    prev = node;
+                }
+            }
+        }
+        pieces
+    }
+}
+
+/// Removes duplicate pieces and pieces that are cancelled by an opposite piece.
+/// These come from collinear overlapping edges.
+fn cancel_duplicates(pieces: Vec<[usize; 2]>) -> Vec<[usize; 2]> {
+    let mut counts = HashMap::<[usize; 2], i32>::new();
+    for [a, b] in pieces {
+        if a < b {
+            *counts.entry([a, b]).or_default() += 1;
+        } else {
+            *counts.entry([b, a]).or_default() -= 1;
+        }
+    }
+    let mut pieces = counts
+        .into_iter()
+        .filter_map(|([a, b], count)| match count.signum() {
+            1 => Some([a, b]),
+            -1 => Some([b, a]),
+            _ => None,
+        })
+        .collect::<Vec<_>>();
+    // Keep the output deterministic
+    pieces.sort_unstable();
+    pieces
+}
+
+fn chain_rings(nodes: &[DVec2], pieces: Vec<[usize; 2]>) -> Result<Vec<Vec<DVec2>>, InflateError> {
+    let mut outgoing = HashMap::<usize, Vec<usize>>::new();
+    for (i, [a, _]) in pieces.iter().enumerate() {
+        outgoing.entry(*a).or_default().push(i);
+    }
+
+    let mut used = vec![false; pieces.len()];
+    let mut rings = Vec::new();
+
+    for start in 0..pieces.len() {
+        if used[start] {
+            continue;
+        }
+        let mut ring = Vec::new();
+        let mut current = start;
+        loop {
+            used[current] = true;
+            let [a, b] = pieces[current];
+            ring.push(nodes[a]);
+            if b == pieces[start][0] {
+                break;
+            }
+
+            // Turn as far left as possible where rings touch each other
+            let dir = nodes[b] - nodes[a];
+            current = outgoing
+                .get(&b)
+                .into_iter()
+                .flatten()
+                .copied()
+                .filter(|&p| !used[p])
+                .max_by(|&p, &q| {
+                    let turn = |p: usize| {
+                        let next = nodes[pieces[p][1]] - nodes[b];
+                        dir.perp_dot(next).atan2(dir.dot(next))
+                    };
+                    turn(p).total_cmp(&turn(q))
+                })
+                .ok_or(InflateError::OpenBoundary)?;
+        }
+
+        let ring = remove_collinear(ring);
+        if ring.len() >= 3 && signed_area(&ring).abs() > EPS {
+            rings.push(ring);
+        }
+    }
+
+    Ok(rings)
+}
+
+fn remove_collinear(ring: Vec<DVec2>) -> Vec<DVec2> {
+    let n = ring.len();
+    (0..n)
+        .filter(|&i| {
+            let prev = ring[(i + n - 1) % n];
+            let next = ring[(i + 1) % n];
+            let d1 = ring[i] - prev;
+            let d2 = next - ring[i];
+            d1.perp_dot(d2).abs() > EPS * d1.length().max(d2.length()) || d1.dot(d2) < 0.
+        })
+        .map(|i| ring[i])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::utils::{rectangle, square, winding_number, WithOffset};
+    use bevy::math::Vec2;
+
+    fn net_area(rings: &[Vertices]) -> f32 {
+        rings
+            .iter()
+            .map(|r| {
+                let area = crate::utils::polygon_area(r);
+                if is_clockwise(r) {
+                    -area
+                } else {
+                    area
+                }
+            })
+            .sum()
+    }
+
+    fn is_inside(point: Vec2, rings: &[Vertices]) -> bool {
+        rings.iter().map(|r| winding_number(point, r)).sum::<i32>() != 0
+    }
+
+    #[test]
+    fn square_area() {
+        let rings = inflate_polygon(&square(10.), 1.).unwrap();
+        assert_eq!(rings.len(), 1);
+        let expected = 12. * 12. - (4. - std::f32::consts::PI);
+        assert!((net_area(&rings) - expected).abs() < 0.1);
+    }
+
+    #[test]
+    fn orientation_does_not_matter() {
+        let mut reversed = square(10.);
+        reversed.reverse();
+        let a = inflate_polygon(&square(10.), 1.).unwrap();
+        let b = inflate_polygon(&reversed, 1.).unwrap();
+        assert!((net_area(&a) - net_area(&b)).abs() < 1e-3);
+    }
+
+    #[test]
+    fn concave_closes_into_hole() {
+        // C shape with a narrow opening that closes up but leaves a hole inside
+        let c = vec![
+            Vec2::new(0., 0.),
+            Vec2::new(10., 0.),
+            Vec2::new(10., 4.5),
+            Vec2::new(9., 4.5),
+            Vec2::new(9., 1.),
+            Vec2::new(1., 1.),
+            Vec2::new(1., 9.),
+            Vec2::new(9., 9.),
+            Vec2::new(9., 5.5),
+            Vec2::new(10., 5.5),
+            Vec2::new(10., 10.),
+            Vec2::new(0., 10.),
+        ];
+        let rings = inflate_polygon(&c, 0.6).unwrap();
+        assert_eq!(rings.len(), 2);
+        assert_eq!(rings.iter().filter(|r| is_clockwise(r)).count(), 1);
+        assert!(!is_inside(Vec2::new(5., 5.), &rings));
+        assert!(is_inside(Vec2::new(9.5, 5.), &rings));
+        assert!(is_inside(Vec2::new(-0.5, 5.), &rings));
+    }
+
+    #[test]
+    fn self_touching() {
+        // Two squares that share a corner, traced as one polygon
+        let polygon = vec![
+            Vec2::new(0., 0.),
+            Vec2::new(1., 0.),
+            Vec2::new(1., 1.),
+            Vec2::new(2., 1.),
+            Vec2::new(2., 2.),
+            Vec2::new(1., 2.),
+            Vec2::new(1., 1.),
+            Vec2::new(0., 1.),
+        ];
+        let rings = inflate_polygon(&polygon, 0.25).unwrap();
+        assert!(is_inside(Vec2::new(0.5, 0.5), &rings));
+        assert!(is_inside(Vec2::new(1.5, 1.5), &rings));
+        assert!(is_inside(Vec2::new(1.1, 0.9), &rings));
+        assert!(!is_inside(Vec2::new(1.9, 0.1), &rings));
+    }
+
+    #[test]
+    fn thin_wall() {
+        let wall = rectangle(Vec2::new(20., 0.01)).with_offset(Vec2::splat(10.));
+        let rings = inflate_polygon(&wall, 0.65).unwrap();
+        assert_eq!(rings.len(), 1);
+        assert!(is_inside(Vec2::new(10., 10.6), &rings));
+        assert!(is_inside(Vec2::new(10., 9.4), &rings));
+        assert!(!is_inside(Vec2::new(10., 10.7), &rings));
+    }
+
+    #[test]
+    fn errors() {
+        assert_eq!(
+            inflate_polygon(&vec![Vec2::ZERO, Vec2::X], 1.),
+            Err(InflateError::TooFewVertices)
+        );
+        assert_eq!(
+            inflate_polygon(&vec![Vec2::ZERO, Vec2::X, Vec2::X * 2.], 1.),
+            Err(InflateError::Degenerate)
+        );
+    }
+}
@@ -22,7 +22,10 @@ use crate::{
     Command,
 };
 
-use crate::simulation::spawning::{Enemy, ENEMY_RADIUS};
+use crate::simulation::{
+    player::Player,
+    spawning::{Enemy, ENEMY_RADIUS},
+};
 
 use crate::level::*;
 
@@ -51,7 +54,9 @@ impl Plugin for VisualizationPlugin {
                 Update,
                 (
                     add_target_sprites,
+                    add_player_sprites,
                     add_spawn_point_sprites,
+                    draw_spawn_regions,
                     add_enemy_sprites,
                     draw_level_bounds,
                     toggle_show_flow_field,
@@ -119,6 +124,23 @@ fn add_target_sprites(
     }
 }
 
+fn add_player_sprites(
+    mut commands: Commands,
+    new_player_q: Query<Entity, Added<Player>>,
+    asset_server: Res<AssetServer>,
+) {
+    for entity in new_player_q.iter() {
+        commands.entity(entity).insert((
+            Sprite {
+                color: Color::hex("#E0C03A").unwrap(),
+                custom_size: Some(Vec2::splat(ENEMY_RADIUS * 3.)),
+                ..default()
+            },
+            asset_server.load::<Image>("circle.png"),
+        ));
+    }
+}
+
 fn add_spawn_point_sprites(
     mut commands: Commands,
     new_spawn_point_q: Query<Entity, Added<SpawnPoint>>,
@@ -136,6 +158,23 @@ fn add_spawn_point_sprites(
     }
 }
 
+fn draw_spawn_regions(mut gizmos: Gizmos, spawn_point_q: Query<(&SpawnPoint, &Transform)>) {
+    let color = Color::hex("#8E2CD8").unwrap().with_a(0.8);
+    for (spawn_point, transform) in &spawn_point_q {
+        match spawn_point.region(transform) {
+            SpawnRegion::Point(_) => {}
+            SpawnRegion::Circle { center, radius } => {
+                gizmos.circle_2d(center, radius, color);
+            }
+            SpawnRegion::Polygon { vertices } => {
+                for (p1, p2) in vertices.iter().zip(vertices.iter().cycle().skip(1)) {
+                    gizmos.line_2d(*p1, *p2, color);
+                }
+            }
+        }
+    }
+}
+
 fn make_triangulated_mesh(vertices: &Vertices) -> anyhow::Result<Mesh> {
     let center = vertices.iter().sum::<Vec2>() / vertices.len() as f32;
     let flat_vertices = vertices